@@ -0,0 +1,120 @@
+//! compare `File` and `memmap2::MmapMut` as backing storage
+//!
+//! run with `cargo bench -p fat-bits --features memmap`; set `FAT_BENCH_IMAGE` to benchmark an
+//! existing image instead of a freshly created 512 MiB scratch file
+
+use std::fs::{File, OpenOptions};
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use fat_bits::SliceLike;
+
+const IMAGE_SIZE: u64 = 512 * 1024 * 1024;
+
+const SEQUENTIAL_CHUNK: usize = 64 * 1024;
+
+const SECTOR_SIZE: usize = 512;
+const RANDOM_READS: usize = 100_000;
+
+/// xorshift64, good enough to scatter reads over the image
+fn random_offsets(len: u64) -> Vec<u64> {
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+
+    let sectors = len / SECTOR_SIZE as u64;
+
+    (0..RANDOM_READS)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+
+            (state % sectors) * SECTOR_SIZE as u64
+        })
+        .collect()
+}
+
+fn sequential(slice_like: &mut impl SliceLike) -> Duration {
+    let len = slice_like.byte_len().unwrap();
+
+    let mut buf = vec![0; SEQUENTIAL_CHUNK];
+
+    let start = Instant::now();
+
+    let mut offset = 0;
+
+    while offset + SEQUENTIAL_CHUNK as u64 <= len {
+        slice_like.read_at_offset(offset, &mut buf).unwrap();
+        black_box(&buf);
+
+        offset += SEQUENTIAL_CHUNK as u64;
+    }
+
+    start.elapsed()
+}
+
+fn random(slice_like: &mut impl SliceLike, offsets: &[u64]) -> Duration {
+    let mut buf = [0; SECTOR_SIZE];
+
+    let start = Instant::now();
+
+    for &offset in offsets {
+        slice_like.read_at_offset(offset, &mut buf).unwrap();
+        black_box(&buf);
+    }
+
+    start.elapsed()
+}
+
+fn report(name: &str, len: u64, sequential: Duration, random: Duration) {
+    let mib = len as f64 / (1024.0 * 1024.0);
+
+    println!(
+        "{name:>6}: sequential {:>8.2?} ({:>8.1} MiB/s), random {:>8.2?} ({:>6.0} ns/read)",
+        sequential,
+        mib / sequential.as_secs_f64(),
+        random,
+        random.as_nanos() as f64 / RANDOM_READS as f64,
+    );
+}
+
+fn main() {
+    let (path, scratch) = match std::env::var_os("FAT_BENCH_IMAGE") {
+        Some(path) => (PathBuf::from(path), false),
+        None => {
+            let path = std::env::temp_dir().join("fat-bits-bench.img");
+
+            let file = File::create(&path).unwrap();
+            file.set_len(IMAGE_SIZE).unwrap();
+
+            (path, true)
+        }
+    };
+
+    let mut file = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+
+    let len = file.byte_len().unwrap();
+    let offsets = random_offsets(len);
+
+    // warm up the page cache so both variants start from the same state
+    sequential(&mut file);
+
+    let file_sequential = sequential(&mut file);
+    let file_random = random(&mut file, &offsets);
+
+    report("file", len, file_sequential, file_random);
+
+    // SAFETY: the file is not modified by anyone else while the benchmark runs
+    let mut mmap = unsafe { memmap2::MmapMut::map_mut(&file).unwrap() };
+
+    let mmap_sequential = sequential(&mut mmap);
+    let mmap_random = random(&mut mmap, &offsets);
+
+    report("mmap", len, mmap_sequential, mmap_random);
+
+    drop(mmap);
+
+    if scratch {
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,21 @@
+#![no_main]
+
+use fat_bits::dir::DirEntry;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|bytes: [u8; 32]| {
+    let Ok(dir_entry) = DirEntry::load(&bytes, 0) else {
+        return;
+    };
+
+    assert_eq!(dir_entry.name(), &bytes[..11]);
+
+    if dir_entry.is_dir() {
+        assert_eq!(dir_entry.file_size(), 0);
+    }
+
+    if !dir_entry.is_empty() {
+        // must not panic for any loaded name
+        let _ = dir_entry.name_string();
+    }
+});
@@ -1,16 +1,18 @@
-use std::fmt::Display;
+#[cfg(not(feature = "std"))]
+use alloc::format;
+use core::fmt::Display;
 
-use crate::FatType;
 use crate::utils::{load_u16_le, load_u32_le};
+use crate::{FatFsError, FatType};
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum ExtBpb {
     ExtBpb16(ExtBpb16),
     ExtBpb32(ExtBpb32),
 }
 
 impl Display for ExtBpb {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             ExtBpb::ExtBpb16(ext_bpb16) => write!(f, "{}", ext_bpb16),
             ExtBpb::ExtBpb32(ext_bpb32) => write!(f, "{}", ext_bpb32),
@@ -18,7 +20,7 @@ impl Display for ExtBpb {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct Bpb {
     fat_type: FatType,
 
@@ -41,7 +43,7 @@ pub struct Bpb {
 }
 
 impl Display for Bpb {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "Bpb {{")?;
 
         match self.fat_type {
@@ -50,7 +52,7 @@ impl Display for Bpb {
             FatType::Fat32 => writeln!(f, "    FAT32")?,
         }
 
-        writeln!(f, "")?;
+        writeln!(f)?;
 
         // writeln!(
         //     f,
@@ -72,7 +74,7 @@ impl Display for Bpb {
         writeln!(f, "    hidden_sectors: {}", self.hidden_sectors())?;
         writeln!(f, "    total sectors 32: {}", self.total_sectors_32())?;
 
-        writeln!(f, "")?;
+        writeln!(f)?;
 
         let ext_bpb_str = format!("{}", self.ext_bpb);
 
@@ -87,9 +89,23 @@ impl Display for Bpb {
 }
 
 impl Bpb {
-    pub fn load(bytes: &[u8]) -> anyhow::Result<Bpb> {
+    pub fn load(bytes: &[u8]) -> Result<Bpb, FatFsError> {
+        Self::parse(bytes).map_err(|err| FatFsError::from_anyhow(err, FatFsError::InvalidBpb))
+    }
+
+    fn parse(bytes: &[u8]) -> anyhow::Result<Bpb> {
         anyhow::ensure!(bytes.len() >= 512, "invalid BPB of len {}", bytes.len());
 
+        // check the signature first, if it's wrong this is most likely not a FAT image at all
+        let signature_word: [u8; 2] = bytes[510..][..2].try_into().unwrap();
+
+        if signature_word != [0x55, 0xAA] {
+            return Err(FatFsError::NotAFatFilesystem {
+                found: signature_word,
+            }
+            .into());
+        }
+
         // let jmp_boot = bytes[..3].try_into().unwrap();
 
         let oem_name = bytes[3..][..8].try_into().unwrap();
@@ -191,14 +207,46 @@ impl Bpb {
         Ok(bpb)
     }
 
+    /// encode this BPB back into a boot sector, including the 0x55 0xAA signature
+    ///
+    /// the jump instruction is not stored, so a standard short jump is written instead
+    pub fn to_bytes(&self) -> [u8; 512] {
+        let mut bytes = [0; 512];
+
+        bytes[..3].copy_from_slice(match self.ext_bpb {
+            ExtBpb::ExtBpb16(_) => &[0xEB, 0x3C, 0x90],
+            ExtBpb::ExtBpb32(_) => &[0xEB, 0x58, 0x90],
+        });
+        bytes[3..11].copy_from_slice(&self.oem_name);
+        bytes[11..13].copy_from_slice(&self.bytes_per_sector.to_le_bytes());
+        bytes[13] = self.sectors_per_cluster;
+        bytes[14..16].copy_from_slice(&self.reserved_sector_count.to_le_bytes());
+        bytes[16] = self.num_fats;
+        bytes[17..19].copy_from_slice(&self.root_entry_count.to_le_bytes());
+        bytes[19..21].copy_from_slice(&self.total_sectors_16.to_le_bytes());
+        bytes[21] = self.media;
+        bytes[22..24].copy_from_slice(&self.fat_size_16.to_le_bytes());
+        bytes[24..26].copy_from_slice(&self.sectors_per_track.to_le_bytes());
+        bytes[26..28].copy_from_slice(&self.num_heads.to_le_bytes());
+        bytes[28..32].copy_from_slice(&self.hidden_sectors.to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.total_sectors_32.to_le_bytes());
+
+        match &self.ext_bpb {
+            ExtBpb::ExtBpb16(ext_bpb16) => ext_bpb16.write_to(&mut bytes),
+            ExtBpb::ExtBpb32(ext_bpb32) => ext_bpb32.write_to(&mut bytes),
+        }
+
+        bytes[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        bytes
+    }
+
     /// number of sectors usable for data
     pub fn num_data_sectors(&self) -> u32 {
-        let data_sectors = self.total_sectors()
+        self.total_sectors()
             - (self.reserved_sector_count() as u32
                 + (self.num_fats() as u32 * self.fat_size())
-                + self.root_dir_sectors());
-
-        data_sectors
+                + self.root_dir_sectors())
     }
 
     /// total number of clusters on this volume
@@ -217,7 +265,7 @@ impl Bpb {
     }
 
     /// convert a given sector to an byte offset
-    fn sector_to_offset(&self, sector: u32) -> u64 {
+    pub fn sector_to_offset(&self, sector: u32) -> u64 {
         sector as u64 * self.bytes_per_sector() as u64
     }
 
@@ -231,6 +279,11 @@ impl Bpb {
         self.bytes_per_sector() as usize * self.fat_size() as usize
     }
 
+    /// byte offset of the `n`-th FAT copy, starting at 0
+    pub fn nth_fat_offset(&self, n: u8) -> u64 {
+        self.fat_offset() + n as u64 * self.fat_len_bytes() as u64
+    }
+
     /// byte offset of the root directory; None for FAT32
     pub fn root_directory_offset(&self) -> Option<u64> {
         if self.fat_type() == FatType::Fat32 {
@@ -254,7 +307,7 @@ impl Bpb {
     pub fn first_data_sector(&self) -> u32 {
         self.reserved_sector_count() as u32
             + (self.num_fats() as u32 * self.fat_size())
-            + self.root_dir_sectors() as u32
+            + self.root_dir_sectors()
     }
 
     pub fn data_offset(&self) -> u64 {
@@ -287,6 +340,29 @@ impl Bpb {
     }
 
     /// get root cluster for FAT32
+    /// the raw volume label, if the extended BPB has a valid one
+    pub fn volume_label(&self) -> Option<&[u8]> {
+        match &self.ext_bpb {
+            ExtBpb::ExtBpb16(ext_bpb16) if ext_bpb16.boot_sig() == 0x29 => {
+                Some(ext_bpb16.volume_label())
+            }
+            ExtBpb::ExtBpb32(ext_bpb32) if ext_bpb32.boot_sig() == 0x29 => {
+                Some(ext_bpb32.volume_label())
+            }
+            _ => None,
+        }
+    }
+
+    /// offset of the volume label in the boot sector, if the extended BPB has a valid one
+    pub fn volume_label_offset(&self) -> Option<usize> {
+        self.volume_label()?;
+
+        match self.fat_type {
+            FatType::Fat12 | FatType::Fat16 => Some(43),
+            FatType::Fat32 => Some(71),
+        }
+    }
+
     pub fn root_cluster(&self) -> Option<u32> {
         if let ExtBpb::ExtBpb32(ext_bpb32) = &self.ext_bpb {
             Some(ext_bpb32.root_cluster())
@@ -295,6 +371,25 @@ impl Bpb {
         }
     }
 
+    /// sector number of the FSInfo structure (only FAT32)
+    pub fn fs_info_sector(&self) -> Option<u16> {
+        if let ExtBpb::ExtBpb32(ext_bpb32) = &self.ext_bpb {
+            Some(ext_bpb32.fs_info())
+        } else {
+            None
+        }
+    }
+
+    /// sector number of the backup boot sector (only FAT32, None if there is none)
+    pub fn backup_boot_sector(&self) -> Option<u16> {
+        match &self.ext_bpb {
+            ExtBpb::ExtBpb32(ext_bpb32) if ext_bpb32.bk_boot_sector() != 0 => {
+                Some(ext_bpb32.bk_boot_sector())
+            }
+            _ => None,
+        }
+    }
+
     /// total number of sectors in this device
     ///
     /// uses total_sectors_16 or total_sectors_32
@@ -315,7 +410,7 @@ impl Bpb {
     }
 
     pub fn oem_name_str(&self) -> Option<&str> {
-        std::str::from_utf8(&self.oem_name()).ok()
+        core::str::from_utf8(self.oem_name()).ok()
     }
 
     pub fn bytes_per_sector(&self) -> u16 {
@@ -368,7 +463,7 @@ impl Bpb {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct ExtBpb16 {
     drive_number: u8,
     boot_sig: u8,
@@ -378,7 +473,7 @@ pub struct ExtBpb16 {
 }
 
 impl Display for ExtBpb16 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "ExtBpb16 {{")?;
 
         writeln!(f, "    drive number: {}", self.drive_number())?;
@@ -394,7 +489,11 @@ impl Display for ExtBpb16 {
 }
 
 impl ExtBpb16 {
-    pub fn load(bytes: &[u8]) -> anyhow::Result<ExtBpb16> {
+    pub fn load(bytes: &[u8]) -> Result<ExtBpb16, FatFsError> {
+        Self::parse(bytes).map_err(|err| FatFsError::from_anyhow(err, FatFsError::InvalidBpb))
+    }
+
+    fn parse(bytes: &[u8]) -> anyhow::Result<ExtBpb16> {
         let drive_number = bytes[36];
 
         if !&[0x80, 0x00].contains(&drive_number) {
@@ -416,7 +515,7 @@ impl ExtBpb16 {
 
         let file_sys_type: [u8; 8] = bytes[54..][..8].try_into().unwrap();
 
-        let Some(s) = std::str::from_utf8(&file_sys_type).ok() else {
+        let Some(s) = core::str::from_utf8(&file_sys_type).ok() else {
             anyhow::bail!("invalid file sys type: {:X?}", file_sys_type);
         };
 
@@ -424,15 +523,6 @@ impl ExtBpb16 {
             anyhow::bail!("invalid file sys type: {}", s);
         }
 
-        let signature_word = &bytes[510..512];
-
-        anyhow::ensure!(
-            signature_word == &[0x55, 0xAA],
-            "invalid signature word: [{:#X}, {:#X}] instead of [0x55, 0xAA]",
-            bytes[510],
-            bytes[511]
-        );
-
         Ok(ExtBpb16 {
             drive_number,
             boot_sig,
@@ -442,6 +532,15 @@ impl ExtBpb16 {
         })
     }
 
+    /// write the extended BPB fields into a boot sector at offsets 36..62
+    fn write_to(&self, bytes: &mut [u8; 512]) {
+        bytes[36] = self.drive_number;
+        bytes[38] = self.boot_sig;
+        bytes[39..43].copy_from_slice(&self.volume_serial_number.to_le_bytes());
+        bytes[43..54].copy_from_slice(&self.volume_label);
+        bytes[54..62].copy_from_slice(&self.file_sys_type);
+    }
+
     pub fn drive_number(&self) -> u8 {
         self.drive_number
     }
@@ -459,7 +558,7 @@ impl ExtBpb16 {
     }
 
     pub fn volume_label_str(&self) -> Option<&str> {
-        std::str::from_utf8(&self.volume_label).ok()
+        core::str::from_utf8(&self.volume_label).ok()
     }
 
     pub fn file_sys_type(&self) -> &[u8] {
@@ -467,11 +566,11 @@ impl ExtBpb16 {
     }
 
     pub fn file_sys_type_str(&self) -> &str {
-        std::str::from_utf8(&self.file_sys_type).unwrap()
+        core::str::from_utf8(&self.file_sys_type).unwrap()
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct ExtBpb32 {
     fat_size_32: u32,
     ext_flags: u16,
@@ -485,7 +584,7 @@ pub struct ExtBpb32 {
 }
 
 impl Display for ExtBpb32 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "ExtBpb32 {{")?;
 
         writeln!(f, "    fat_size_32: {}", self.fat_size_32)?;
@@ -505,7 +604,11 @@ impl Display for ExtBpb32 {
 }
 
 impl ExtBpb32 {
-    pub fn load(bytes: &[u8]) -> anyhow::Result<ExtBpb32> {
+    pub fn load(bytes: &[u8]) -> Result<ExtBpb32, FatFsError> {
+        Self::parse(bytes).map_err(|err| FatFsError::from_anyhow(err, FatFsError::InvalidBpb))
+    }
+
+    fn parse(bytes: &[u8]) -> anyhow::Result<ExtBpb32> {
         let fat_size_32 = load_u32_le(&bytes[36..][..4]);
 
         anyhow::ensure!(fat_size_32 != 0, "fat_size_32 is zero");
@@ -527,7 +630,7 @@ impl ExtBpb32 {
         );
 
         let reserved = &bytes[52..][..12];
-        anyhow::ensure!(reserved == &[0; 12], "reserved is not zeroed");
+        anyhow::ensure!(reserved == [0; 12], "reserved is not zeroed");
 
         let drive_number = bytes[64];
 
@@ -538,7 +641,7 @@ impl ExtBpb32 {
         let volume_serial_number = load_u32_le(&bytes[67..][..4]);
         let volume_label = bytes[71..][..11].try_into().unwrap();
 
-        if volume_serial_number != 0 || &volume_label != &[0; 11] {
+        if volume_serial_number != 0 || volume_label != [0; 11] {
             anyhow::ensure!(
                 boot_sig == 0x29,
                 "VollID or VolLab is set, but BootSig is {} instead of 0x29",
@@ -548,19 +651,10 @@ impl ExtBpb32 {
 
         let file_sys_type = &bytes[82..][..8];
         anyhow::ensure!(
-            std::str::from_utf8(file_sys_type) == Ok("FAT32   "),
+            core::str::from_utf8(file_sys_type) == Ok("FAT32   "),
             "invalid file sys type"
         );
 
-        let signature_word = &bytes[510..][..2];
-
-        anyhow::ensure!(
-            signature_word == &[0x55, 0xAA],
-            "invalid signature word [{:#X}, {:#X}] instead of [0x55, 0xAA]",
-            signature_word[0],
-            signature_word[1]
-        );
-
         Ok(ExtBpb32 {
             fat_size_32,
             ext_flags,
@@ -574,6 +668,22 @@ impl ExtBpb32 {
         })
     }
 
+    /// write the extended BPB fields into a boot sector at offsets 36..90
+    ///
+    /// FSVer and the reserved bytes are always zero
+    fn write_to(&self, bytes: &mut [u8; 512]) {
+        bytes[36..40].copy_from_slice(&self.fat_size_32.to_le_bytes());
+        bytes[40..42].copy_from_slice(&self.ext_flags.to_le_bytes());
+        bytes[44..48].copy_from_slice(&self.root_cluster.to_le_bytes());
+        bytes[48..50].copy_from_slice(&self.fs_info.to_le_bytes());
+        bytes[50..52].copy_from_slice(&self.bk_boot_sector.to_le_bytes());
+        bytes[64] = self.drive_number;
+        bytes[66] = self.boot_sig;
+        bytes[67..71].copy_from_slice(&self.volume_serial_number.to_le_bytes());
+        bytes[71..82].copy_from_slice(&self.volume_label);
+        bytes[82..90].copy_from_slice(b"FAT32   ");
+    }
+
     pub fn fat_size_32(&self) -> u32 {
         self.fat_size_32
     }
@@ -611,6 +721,6 @@ impl ExtBpb32 {
     }
 
     pub fn volume_label_str(&self) -> Option<&str> {
-        std::str::from_utf8(self.volume_label()).ok()
+        core::str::from_utf8(self.volume_label()).ok()
     }
 }
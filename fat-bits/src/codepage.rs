@@ -0,0 +1,42 @@
+//! OEM code page 437, the character set of short names on most DOS disks
+
+/// characters 0x80 to 0xFF, the lower half is plain ASCII
+const CP437_HIGH: [char; 128] = [
+    // 0x80
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    // 0x90
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    // 0xA0
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    // 0xB0
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    // 0xC0
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    // 0xD0
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    // 0xE0
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    // 0xF0
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// decode a single OEM byte
+pub fn decode(byte: u8) -> char {
+    if byte.is_ascii() {
+        byte as char
+    } else {
+        CP437_HIGH[byte as usize - 0x80]
+    }
+}
+
+/// encode `c` as an OEM byte, None if it has no representation in CP437
+pub fn encode(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8);
+    }
+
+    CP437_HIGH
+        .iter()
+        .position(|&x| x == c)
+        .map(|idx| (idx + 0x80) as u8)
+}
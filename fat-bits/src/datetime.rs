@@ -1,6 +1,8 @@
-use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Timelike};
+use core::fmt::{Debug, Display};
 
-#[derive(Debug, Clone, Copy)]
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeZone, Timelike};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Date {
     repr: u16,
 }
@@ -23,14 +25,14 @@ impl Date {
     fn from_day_month_year(day: u8, month: u8, year: u16) -> anyhow::Result<Date> {
         anyhow::ensure!(day <= 31, "invalid day: {}", day);
         anyhow::ensure!(month <= 12, "invalid month: {}", month);
-        anyhow::ensure!(1980 <= year && year <= 2107, "invalid year: {}", year);
+        anyhow::ensure!((1980..=2107).contains(&year), "invalid year: {}", year);
 
-        let repr = day as u16 | (month as u16) << 4 | (year - 1980) << 8;
+        let repr = day as u16 | (month as u16) << 5 | (year - 1980) << 9;
 
         Ok(Date { repr })
     }
 
-    pub fn from_datetime(datetime: DateTime<Local>) -> anyhow::Result<Date> {
+    pub fn from_datetime<Tz: TimeZone>(datetime: DateTime<Tz>) -> anyhow::Result<Date> {
         let date = datetime.date_naive();
 
         Date::from_day_month_year(
@@ -53,15 +55,31 @@ impl Date {
     }
 
     pub fn year(&self) -> u16 {
-        ((self.repr & 0xFE00) >> 9) as u16 + 1980
+        ((self.repr & 0xFE00) >> 9) + 1980
     }
 
-    pub fn to_naive_date(&self) -> NaiveDate {
+    pub fn to_naive_date(self) -> NaiveDate {
         NaiveDate::from_ymd_opt(self.year() as i32, self.month() as u32, self.day() as u32).unwrap()
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Display for Date {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year(), self.month(), self.day())
+    }
+}
+
+impl Debug for Date {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Date")
+            .field("year", &self.year())
+            .field("month", &self.month())
+            .field("day", &self.day())
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Time {
     repr: u16,
 }
@@ -74,7 +92,7 @@ impl Time {
     }
 
     fn from_seconds_minutes_hours(seconds: u8, minutes: u8, hours: u8) -> anyhow::Result<Time> {
-        anyhow::ensure!(seconds <= 58 && seconds % 2 == 0, "invalid seconds: {}", seconds);
+        anyhow::ensure!(seconds <= 58 && seconds.is_multiple_of(2), "invalid seconds: {}", seconds);
         anyhow::ensure!(minutes <= 59, "invalid minutes: {}", minutes);
         anyhow::ensure!(hours <= 23, "invalid hours: {}", hours);
 
@@ -83,7 +101,7 @@ impl Time {
         Ok(Time { repr })
     }
 
-    pub fn from_datetime(datetime: DateTime<Local>) -> anyhow::Result<Time> {
+    pub fn from_datetime<Tz: TimeZone>(datetime: DateTime<Tz>) -> anyhow::Result<Time> {
         let time = datetime.time();
 
         let seconds = (time.second() as u8) & !0x01;
@@ -107,8 +125,24 @@ impl Time {
         ((self.repr >> 11) & 0x1F) as u8
     }
 
-    pub fn to_naive_time(&self) -> NaiveTime {
+    pub fn to_naive_time(self) -> NaiveTime {
         NaiveTime::from_hms_opt(self.hour() as u32, self.minute() as u32, self.second() as u32)
             .unwrap()
     }
 }
+
+impl Display for Time {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02}:{:02}:{:02}", self.hour(), self.minute(), self.second())
+    }
+}
+
+impl Debug for Time {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Time")
+            .field("hour", &self.hour())
+            .field("minute", &self.minute())
+            .field("second", &self.second())
+            .finish()
+    }
+}
@@ -1,15 +1,28 @@
-use std::fmt::Display;
-use std::io::{Read, Write};
+#[cfg(not(feature = "std"))]
+use alloc::format;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::fmt::Display;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
 use std::time::SystemTime;
 
 use bitflags::bitflags;
-use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeDelta, Timelike};
+#[cfg(feature = "std")]
+use chrono::{DateTime, Local, Timelike};
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
 use compact_str::CompactString;
 use log::debug;
 
+#[cfg(feature = "std")]
 use crate::FatFs;
+use crate::FatFsError;
 use crate::datetime::{Date, Time};
+use crate::io::{IoError, ReadBytes};
+#[cfg(feature = "std")]
 use crate::iter::ClusterChainReader;
+#[cfg(feature = "std")]
 use crate::subslice::SubSliceMut;
 use crate::utils::{load_u16_le, load_u32_le};
 
@@ -31,7 +44,7 @@ bitflags! {
 }
 
 impl Display for Attr {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let mut if_has_attr = |attr: Attr, c: char| {
             if self.contains(attr) {
                 write!(f, "{}", c)
@@ -52,11 +65,14 @@ impl Display for Attr {
 }
 
 /// represents an entry in a diectory
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DirEntry {
     name: [u8; 11],
     attr: Attr,
 
+    // reserved for Windows NT, kept as is so writing an entry back doesn't change it
+    nt_res: u8,
+
     create_time_tenths: u8,
     create_time: Time,
     create_date: Date,
@@ -79,34 +95,34 @@ pub struct DirEntry {
 }
 
 impl Display for DirEntry {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let mut name = self.name_string();
 
         if self.attr.contains(Attr::Directory) {
             name.push('/');
         }
 
-        write!(
-            f,
-            "{}    {}",
-            self.attr,
-            // self.create_time().format("%a %b %d %H:%M:%S%.3f %Y"),
-            // self.write_time().format("%a %b %d %H:%M:%S%.3f %Y"),
-            name,
-        )?;
+        write!(f, "{}    {} {}    {}", self.attr, self.write_date, self.write_time, name)?;
 
         Ok(())
     }
 }
 
 impl DirEntry {
-    pub fn load(bytes: &[u8], offset: u64) -> anyhow::Result<DirEntry> {
+    pub fn load(bytes: &[u8], offset: u64) -> Result<DirEntry, FatFsError> {
+        Self::parse(bytes, offset)
+            .map_err(|err| FatFsError::from_anyhow(err, FatFsError::InvalidDirEntry))
+    }
+
+    fn parse(bytes: &[u8], offset: u64) -> anyhow::Result<DirEntry> {
         assert_eq!(bytes.len(), 32);
 
         let attr = Attr::from_bits_truncate(bytes[11]);
 
         let name = bytes[..11].try_into().unwrap();
 
+        let nt_res = bytes[12];
+
         let create_time_tenths = bytes[13];
         anyhow::ensure!(
             create_time_tenths <= 199,
@@ -145,6 +161,7 @@ impl DirEntry {
         Ok(DirEntry {
             name,
             attr,
+            nt_res,
             create_time_tenths,
             create_time,
             create_date,
@@ -160,19 +177,19 @@ impl DirEntry {
         })
     }
 
-    pub fn create(_name: &str, attr: Attr) -> anyhow::Result<Self> {
-        // TODO
+    /// create a new DirEntry with the (already encoded) 8.3 name `name`, timestamped now
+    #[cfg(feature = "std")]
+    pub fn create(name: [u8; 11], attr: Attr) -> anyhow::Result<Self> {
         let now: DateTime<Local> = SystemTime::now().into();
 
         let create_date = Date::from_datetime(now)?;
         let create_time = Time::from_datetime(now)?;
         let create_time_tenths = (now.time().nanosecond() / 100_000_000) as u8;
 
-        let name = [0; 11];
-
         Ok(DirEntry {
             name,
             attr,
+            nt_res: 0,
             create_time_tenths,
             create_time,
             create_date,
@@ -188,17 +205,85 @@ impl DirEntry {
         })
     }
 
-    fn write(&self, mut writer: impl Write) -> std::io::Result<()> {
+    /// set the 8.3 name from `name`, which must already be a valid short name like "FILE.TXT"
+    ///
+    /// non-ASCII characters are encoded as OEM code page 437 with the `oem_cp437` feature and
+    /// rejected otherwise. drops the long name, but keeps the number of LFN slots so they can
+    /// still be erased from disk
+    pub fn set_name_from_str(&mut self, name: &str) -> anyhow::Result<()> {
+        let (stem, ext) = match name.rfind('.') {
+            Some(idx) => (&name[..idx], &name[idx + 1..]),
+            None => (name, ""),
+        };
+
+        anyhow::ensure!(!stem.is_empty(), "short name \"{}\" has an empty stem", name);
+
+        let mut short_name = [b' '; 11];
+
+        let (stem_out, ext_out) = short_name.split_at_mut(8);
+
+        for (s, out) in [(stem, stem_out), (ext, ext_out)] {
+            for (n, c) in s.chars().enumerate() {
+                let byte = if c.is_ascii() {
+                    let c = c.to_ascii_uppercase() as u8;
+
+                    anyhow::ensure!(
+                        c.is_ascii_alphanumeric() || VALID_SHORT_NAME_SYMBOLS.contains(&c),
+                        "invalid character {:?} in short name \"{}\"",
+                        c as char,
+                        name
+                    );
+
+                    c
+                } else {
+                    encode_oem(c).ok_or_else(|| {
+                        anyhow::anyhow!("can't represent {:?} in short name \"{}\"", c, name)
+                    })?
+                };
+
+                anyhow::ensure!(n < out.len(), "\"{}\" is too long for a short name", name);
+
+                out[n] = byte;
+            }
+        }
+
+        // 0xE5 marks a free entry, so a leading 0xE5 is stored as 0x05
+        if short_name[0] == 0xE5 {
+            short_name[0] = 0x05;
+        }
+
+        self.name = short_name;
+        self.checksum = Self::checksum(&short_name);
+        self.long_name = None;
+
+        Ok(())
+    }
+
+    /// copy of this DirEntry under the (already encoded) 8.3 name `name`, not yet written anywhere
+    #[cfg(feature = "std")]
+    pub(crate) fn renamed(&self, name: [u8; 11]) -> Self {
+        DirEntry {
+            name,
+            checksum: Self::checksum(&name),
+            long_name: None,
+            n_longname_slots: 0,
+            offset: !0,
+            ..self.clone()
+        }
+    }
+
+    /// the 32 byte on-disk representation of this entry, without any long name entries
+    pub fn to_bytes(&self) -> [u8; 32] {
         let mut buf = [0; 32];
 
         buf[..11].copy_from_slice(self.name());
 
         buf[11] = self.attr().bits();
 
-        buf[12] = 0;
+        buf[12] = self.nt_res;
 
         buf[13] = self.create_time_tenths;
-        buf[13..15].copy_from_slice(&self.create_time.repr().to_le_bytes());
+        buf[14..16].copy_from_slice(&self.create_time.repr().to_le_bytes());
 
         buf[16..18].copy_from_slice(&self.create_date.repr().to_le_bytes());
 
@@ -213,6 +298,13 @@ impl DirEntry {
 
         buf[28..].copy_from_slice(&self.file_size.to_le_bytes());
 
+        buf
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn write(&self, mut writer: impl Write) -> std::io::Result<()> {
+        let buf = self.to_bytes();
+
         debug!("self: {self:?}");
         debug!("writing new dir entry: {:?}", buf);
 
@@ -222,14 +314,24 @@ impl DirEntry {
     }
 
     /// write this DisEntry back to the underlying data
+    #[cfg(feature = "std")]
     pub fn write_back(&self, fat_fs: &FatFs) -> std::io::Result<()> {
-        eprintln!("making new SubSliceMut at offset {:#X}", self.offset);
+        debug!("writing DirEntry back at offset {:#X}", self.offset);
         let sub_slice = SubSliceMut::new(fat_fs.inner.clone(), self.offset, 32);
 
         self.write(sub_slice)
     }
 
+    /// write this DirEntry to a new location at `offset`
+    #[cfg(feature = "std")]
+    pub(crate) fn write_at(&mut self, fat_fs: &FatFs, offset: u64) -> std::io::Result<()> {
+        self.offset = offset;
+
+        self.write_back(fat_fs)
+    }
+
     /// erase this DirEntry
+    #[cfg(feature = "std")]
     pub fn erase(self, fat_fs: &FatFs) -> std::io::Result<()> {
         let mut sub_slice = SubSliceMut::new(fat_fs.inner.clone(), self.offset, 32);
 
@@ -239,7 +341,7 @@ impl DirEntry {
         // paste over with zeros
         sub_slice.write_all(&[0; 31])
 
-        // TODO: erase long filename entries as well
+        // long filename entries are erased by FatFs::erase_dir_entry
     }
 
     /// indicates this DirEntry is empty
@@ -255,14 +357,23 @@ impl DirEntry {
         self.name[0] == 0x00
     }
 
+    /// regular file, which includes system files like Windows does
     pub fn is_file(&self) -> bool {
-        !self
-            .attr
-            .intersects(Attr::Directory | Attr::System | Attr::VolumeId)
+        !self.attr.intersects(Attr::Directory | Attr::VolumeId)
     }
 
     pub fn is_dir(&self) -> bool {
-        self.attr.contains(Attr::Directory) && !self.attr.intersects(Attr::System | Attr::VolumeId)
+        self.attr.contains(Attr::Directory) && !self.attr.contains(Attr::VolumeId)
+    }
+
+    /// file or directory with the system attribute set
+    pub fn is_system(&self) -> bool {
+        self.attr.contains(Attr::System) && !self.attr.contains(Attr::VolumeId)
+    }
+
+    /// the volume label entry in the root dir
+    pub fn is_volume_id(&self) -> bool {
+        self.attr.contains(Attr::VolumeId)
     }
 
     pub fn is_dot(&self) -> bool {
@@ -270,7 +381,7 @@ impl DirEntry {
             return false;
         }
 
-        self.name[0] == b'.' && &self.name[1..] == &[b' '; 10]
+        self.name[0] == b'.' && self.name[1..] == [b' '; 10]
     }
 
     pub fn is_dotdot(&self) -> bool {
@@ -278,11 +389,16 @@ impl DirEntry {
             return false;
         }
 
-        &self.name[..2] == &[b'.', b'.'] && &self.name[2..] == &[b' '; 9]
+        self.name[..2] == *b".." && self.name[2..] == [b' '; 9]
     }
 
+    pub fn is_dot_or_dotdot(&self) -> bool {
+        self.is_dot() || self.is_dotdot()
+    }
+
+    /// has the hidden attribute set, . and .. are not hidden by themselves
     pub fn is_hidden(&self) -> bool {
-        self.is_dot() || self.is_dotdot() || self.attr.contains(Attr::Hidden)
+        self.attr.contains(Attr::Hidden)
     }
 
     pub fn is_readonly(&self) -> bool {
@@ -298,7 +414,7 @@ impl DirEntry {
     }
 
     pub fn stem_str(&self) -> Option<&str> {
-        std::str::from_utf8(self.stem()).ok()
+        core::str::from_utf8(self.stem()).ok()
     }
 
     pub fn extension(&self) -> &[u8] {
@@ -306,7 +422,7 @@ impl DirEntry {
     }
 
     pub fn extension_str(&self) -> Option<&str> {
-        std::str::from_utf8(self.extension()).ok()
+        core::str::from_utf8(self.extension()).ok()
     }
 
     pub fn name_string(&self) -> CompactString {
@@ -320,6 +436,20 @@ impl DirEntry {
             return long_filename.into();
         }
 
+        self.short_name_string()
+    }
+
+    /// the 8.3 name as a string, ignoring any long name
+    pub fn short_name_string(&self) -> CompactString {
+        // '.' isn't a valid character in a short name, so these would come out as "?" and "??"
+        if self.is_dot() {
+            return CompactString::const_new(".");
+        }
+
+        if self.is_dotdot() {
+            return CompactString::const_new("..");
+        }
+
         let name = &self.name[..8];
         let ext = &self.name[8..];
 
@@ -329,15 +459,15 @@ impl DirEntry {
             s.push('.');
         }
 
-        const VALID_SYMBOLS: &[u8] = &[
-            b'$', b'%', b'\'', b'-', b'_', b'@', b'~', b'`', b'!', b'(', b')', b'{', b'}', b'^',
-            b'#', b'&',
-        ];
-
         fn map_chars(c: u8) -> char {
+            #[cfg(feature = "oem_cp437")]
+            if !c.is_ascii() {
+                return crate::codepage::decode(c);
+            }
+
             if !c.is_ascii()
                 || c < 0x20
-                || !(c.is_ascii_alphanumeric() || VALID_SYMBOLS.contains(&c))
+                || !(c.is_ascii_alphanumeric() || VALID_SHORT_NAME_SYMBOLS.contains(&c))
             {
                 '?'
             } else {
@@ -345,9 +475,17 @@ impl DirEntry {
             }
         }
 
-        s.extend(name.trim_ascii_end().iter().copied().map(map_chars));
+        let mut chars = name.trim_ascii_end().iter().copied().map(map_chars);
 
-        if !ext.is_empty() {
+        // 0x05 stands in for a leading 0xE5, which would mark the entry as free
+        if name[0] == 0x05 {
+            chars.next();
+            s.push(map_chars(0xE5));
+        }
+
+        s.extend(chars);
+
+        if !ext.trim_ascii_end().is_empty() {
             s.push('.');
 
             s.extend(ext.trim_ascii_end().iter().copied().map(map_chars));
@@ -356,10 +494,41 @@ impl DirEntry {
         s
     }
 
+    /// byte offset of this DirEntry in the underlying data
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// check if `name` refers to this DirEntry
+    ///
+    /// unless `case_sensitive` is set, long names are compared ignoring case and short names
+    /// after converting `name` to uppercase, like Windows does
+    pub fn matches_name(&self, name: &str, case_sensitive: bool) -> bool {
+        if case_sensitive {
+            return self.name_string() == name;
+        }
+
+        if let Some(long_name) = self.long_name()
+            && long_name
+                .chars()
+                .flat_map(char::to_lowercase)
+                .eq(name.chars().flat_map(char::to_lowercase))
+        {
+            return true;
+        }
+
+        self.short_name_string() == name.to_ascii_uppercase()
+    }
+
     pub fn long_name(&self) -> Option<&str> {
         self.long_name.as_deref()
     }
 
+    /// number of LFN slots directly preceding this entry
+    pub fn n_longname_slots(&self) -> u8 {
+        self.n_longname_slots
+    }
+
     pub fn set_long_name(&mut self, long_name: CompactString, n_slots: u8) {
         self.long_name = Some(long_name);
         self.n_longname_slots = n_slots;
@@ -369,6 +538,13 @@ impl DirEntry {
         self.attr
     }
 
+    /// replace the attributes, except for the directory and volume id bits, which are kept
+    pub fn set_attr(&mut self, attr: Attr) {
+        let kind = Attr::Directory | Attr::VolumeId;
+
+        self.attr = attr.difference(kind) | self.attr.intersection(kind);
+    }
+
     pub fn create_time(&self) -> NaiveDateTime {
         let date = self.create_date.to_naive_date();
         let time = self.create_time.to_naive_time();
@@ -384,7 +560,8 @@ impl DirEntry {
         self.last_access_date.to_naive_date()
     }
 
-    pub fn update_last_access_date(
+    #[cfg(feature = "std")]
+    pub fn set_last_access_date(
         &mut self,
         time: impl Into<DateTime<Local>>,
     ) -> anyhow::Result<()> {
@@ -393,10 +570,31 @@ impl DirEntry {
         Ok(())
     }
 
+    /// set the last access date to `dt` and write it to disk
+    #[cfg(feature = "std")]
+    pub fn update_access_date(
+        &mut self,
+        fat_fs: &FatFs,
+        dt: DateTime<Local>,
+    ) -> anyhow::Result<()> {
+        self.set_last_access_date(dt)?;
+
+        fat_fs
+            .inner
+            .borrow_mut()
+            .write_at_offset(self.offset + 18, &self.last_access_date.repr().to_le_bytes())?;
+
+        Ok(())
+    }
+
     pub fn first_cluster(&self) -> u32 {
         self.first_cluster
     }
 
+    pub fn set_first_cluster(&mut self, cluster: u32) {
+        self.first_cluster = cluster;
+    }
+
     pub fn write_time(&self) -> NaiveDateTime {
         let time = self.write_time.to_naive_time();
         let date = self.write_date.to_naive_date();
@@ -404,7 +602,8 @@ impl DirEntry {
         NaiveDateTime::new(date, time)
     }
 
-    pub fn update_write_time(&mut self, time: impl Into<DateTime<Local>>) -> anyhow::Result<()> {
+    #[cfg(feature = "std")]
+    pub fn set_write_datetime(&mut self, time: impl Into<DateTime<Local>>) -> anyhow::Result<()> {
         let time = time.into();
 
         self.write_date = Date::from_datetime(time)?;
@@ -413,11 +612,31 @@ impl DirEntry {
         Ok(())
     }
 
+    /// set the write time and date to `dt` and write them to disk
+    #[cfg(feature = "std")]
+    pub fn update_write_time(&mut self, fat_fs: &FatFs, dt: DateTime<Local>) -> anyhow::Result<()> {
+        self.set_write_datetime(dt)?;
+
+        let mut bytes = [0; 4];
+
+        bytes[..2].copy_from_slice(&self.write_time.repr().to_le_bytes());
+        bytes[2..].copy_from_slice(&self.write_date.repr().to_le_bytes());
+
+        fat_fs.inner.borrow_mut().write_at_offset(self.offset + 22, &bytes)?;
+
+        Ok(())
+    }
+
+    pub fn set_write_time(&mut self, time: Time, date: Date) {
+        self.write_time = time;
+        self.write_date = date;
+    }
+
     pub fn file_size(&self) -> u32 {
         self.file_size
     }
 
-    pub fn update_file_size(&mut self, file_size: u32) {
+    pub fn set_file_size(&mut self, file_size: u32) {
         self.file_size = file_size
     }
 
@@ -432,6 +651,148 @@ impl DirEntry {
     }
 }
 
+/// byte offsets of the 13 UTF-16 characters in an LFN entry
+const LFN_CHAR_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+const VALID_SHORT_NAME_SYMBOLS: &[u8] = b"$%'-_@~`!(){}^#&";
+
+/// encode a non-ASCII character for a short name
+#[cfg(feature = "oem_cp437")]
+fn encode_oem(c: char) -> Option<u8> {
+    crate::codepage::encode(c)
+}
+
+/// encode a non-ASCII character for a short name
+#[cfg(not(feature = "oem_cp437"))]
+fn encode_oem(_c: char) -> Option<u8> {
+    None
+}
+
+/// encode `label` as an 11 byte volume label, padded with spaces
+///
+/// unlike short names, labels may contain spaces, but no dot
+pub fn encode_volume_label(label: &str) -> anyhow::Result<[u8; 11]> {
+    anyhow::ensure!(!label.trim().is_empty(), "volume label is empty");
+    anyhow::ensure!(!label.starts_with(' '), "volume label \"{}\" starts with a space", label);
+
+    let mut bytes = [b' '; 11];
+
+    for (n, c) in label.chars().enumerate() {
+        anyhow::ensure!(n < bytes.len(), "volume label \"{}\" is too long", label);
+
+        bytes[n] = if c.is_ascii() {
+            let c = c.to_ascii_uppercase() as u8;
+
+            anyhow::ensure!(
+                c == b' ' || c.is_ascii_alphanumeric() || VALID_SHORT_NAME_SYMBOLS.contains(&c),
+                "invalid character {:?} in volume label \"{}\"",
+                c as char,
+                label
+            );
+
+            c
+        } else {
+            encode_oem(c).ok_or_else(|| {
+                anyhow::anyhow!("can't represent {:?} in volume label \"{}\"", c, label)
+            })?
+        };
+    }
+
+    Ok(bytes)
+}
+
+/// decode a volume label from the BPB or a VolumeId entry, without trailing spaces
+pub fn decode_volume_label(bytes: &[u8]) -> CompactString {
+    let end = bytes.iter().rposition(|&b| b != b' ' && b != 0).map_or(0, |idx| idx + 1);
+
+    bytes[..end]
+        .iter()
+        .map(|&b| {
+            #[cfg(feature = "oem_cp437")]
+            if !b.is_ascii() {
+                return crate::codepage::decode(b);
+            }
+
+            if b.is_ascii() && b >= 0x20 { b as char } else { '?' }
+        })
+        .collect()
+}
+
+/// generate the 8.3 basis name for `name`
+///
+/// also returns whether the conversion was lossy, i.e. the basis name does not represent `name`
+/// exactly and a numeric tail has to be added
+pub fn short_name_basis(name: &str) -> ([u8; 11], bool) {
+    let mut short_name = [b' '; 11];
+
+    // leading dots are stripped
+    let trimmed = name.trim_start_matches('.');
+    let mut lossy = trimmed.len() != name.len();
+
+    let (stem, ext) = match trimmed.rfind('.') {
+        Some(idx) => (&trimmed[..idx], Some(&trimmed[idx + 1..])),
+        None => (trimmed, None),
+    };
+
+    let mut convert = |s: &str, out: &mut [u8]| {
+        let mut n = 0;
+
+        for c in s.chars() {
+            if c == ' ' || c == '.' {
+                // spaces and (inner) dots are dropped
+                lossy = true;
+                continue;
+            }
+
+            let c = if c.is_ascii()
+                && (c.is_ascii_alphanumeric() || VALID_SHORT_NAME_SYMBOLS.contains(&(c as u8)))
+            {
+                c.to_ascii_uppercase() as u8
+            } else {
+                lossy = true;
+                b'_'
+            };
+
+            if n == out.len() {
+                // truncated
+                lossy = true;
+                break;
+            }
+
+            out[n] = c;
+            n += 1;
+        }
+    };
+
+    convert(stem, &mut short_name[..8]);
+
+    if let Some(ext) = ext {
+        convert(ext, &mut short_name[8..]);
+    }
+
+    (short_name, lossy)
+}
+
+/// add the numeric tail `~n` to a basis name
+pub fn short_name_with_tail(basis: [u8; 11], n: u32) -> [u8; 11] {
+    let tail = format!("~{n}");
+
+    assert!(tail.len() < 8);
+
+    let mut short_name = basis;
+
+    let stem_len = basis[..8]
+        .iter()
+        .position(|&c| c == b' ')
+        .unwrap_or(8)
+        .min(8 - tail.len());
+
+    short_name[stem_len..][..tail.len()].copy_from_slice(tail.as_bytes());
+    short_name[stem_len + tail.len()..8].fill(b' ');
+
+    short_name
+}
+
 /// long filename entry in a directory
 ///
 /// this should not be exposed to end users, only for internal consumption in the DirIter
@@ -462,7 +823,7 @@ impl LongNameDirEntry {
         let name2 = &bytes[14..][..12];
 
         anyhow::ensure!(
-            &bytes[26..][..2] == &[0, 0],
+            bytes[26..][..2] == [0, 0],
             "LDIR_FstClusLO must be zero, not 0x{:04X}",
             load_u32_le(&bytes[26..][..2])
         );
@@ -475,7 +836,7 @@ impl LongNameDirEntry {
             .chunks_exact(2)
             .chain(name2.chunks_exact(2))
             .chain(name3.chunks_exact(2))
-            .map(|x| load_u16_le(x))
+            .map(load_u16_le)
             .zip(name.iter_mut())
         {
             *y = x;
@@ -504,6 +865,57 @@ impl LongNameDirEntry {
     pub fn checksum(&self) -> u8 {
         self.checksum
     }
+
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+
+        bytes[0] = if self.is_last {
+            self.ordinal | 0x40
+        } else {
+            self.ordinal
+        };
+
+        bytes[11] = Attr::LongName.bits();
+        bytes[13] = self.checksum;
+
+        for (&offset, &c) in LFN_CHAR_OFFSETS.iter().zip(self.name.iter()) {
+            bytes[offset..][..2].copy_from_slice(&c.to_le_bytes());
+        }
+
+        bytes
+    }
+}
+
+/// encode `name` as a chain of LFN entries for the short entry with checksum `checksum`
+///
+/// see `LongFilenameBuf::encode`
+pub fn long_name_entries(name: &str, checksum: u8) -> anyhow::Result<Vec<[u8; 32]>> {
+    LongFilenameBuf::encode(name, checksum)
+}
+
+/// write the LFN entries for `name` to consecutive slots starting at `dir_offset`
+///
+/// returns the offset at which the 8.3 entry with checksum `checksum` has to follow. all slots
+/// have to lie in the same cluster (or the FAT12/16 root dir), `FatFs::create_file` handles
+/// directories where that isn't the case
+#[cfg(feature = "std")]
+pub fn write_lfn_entries(
+    fat_fs: &FatFs,
+    dir_offset: u64,
+    name: &str,
+    checksum: u8,
+) -> anyhow::Result<u64> {
+    let entries = LongFilenameBuf::encode(name, checksum)?;
+
+    let len = 32 * entries.len();
+
+    let mut writer = SubSliceMut::new(fat_fs.inner.clone(), dir_offset, len);
+
+    for entry in &entries {
+        writer.write_all(entry)?;
+    }
+
+    Ok(dir_offset + len as u64)
 }
 
 /// wraps both Regular DirEntry and LongNameDirEntry
@@ -541,6 +953,44 @@ struct LongFilenameBuf {
 }
 
 impl LongFilenameBuf {
+    /// encode `name` as a chain of LFN entries for the short entry with checksum `checksum`, the
+    /// counterpart to assembling a name with `next`
+    ///
+    /// entries are returned in on-disk order, i.e. the last one (highest ordinal) first. the name
+    /// is null terminated and padded with 0xFFFF, unless it fills the last entry exactly
+    pub fn encode(name: &str, checksum: u8) -> anyhow::Result<Vec<[u8; 32]>> {
+        let mut name: Vec<u16> = name.encode_utf16().collect();
+
+        anyhow::ensure!(!name.is_empty(), "long filename can't be empty");
+        anyhow::ensure!(name.len() <= 255, "long filename too long");
+
+        if !name.len().is_multiple_of(13) {
+            // null terminated, unless the name fits the entries perfectly, rest is padded with
+            // 0xFFFF
+            name.push(0);
+            name.resize(name.len().next_multiple_of(13), 0xFFFF);
+        }
+
+        let n_entries = name.len() / 13;
+
+        let entries = name
+            .chunks_exact(13)
+            .enumerate()
+            .rev()
+            .map(|(i, chunk)| {
+                LongNameDirEntry {
+                    ordinal: i as u8 + 1,
+                    is_last: i + 1 == n_entries,
+                    name: chunk.try_into().unwrap(),
+                    checksum,
+                }
+                .to_bytes()
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
     pub fn reset(&mut self) {
         self.rev_buf.clear();
         self.checksum = None;
@@ -552,10 +1002,13 @@ impl LongFilenameBuf {
         if dir_entry.is_last() {
             // first/lasts entry
 
-            anyhow::ensure!(
-                dir_entry.ordinal() <= 20,
-                "can't have more than 20 long filename dir entries"
-            );
+            if dir_entry.ordinal() == 0 || dir_entry.ordinal() > 20 {
+                // don't leave a partially assembled name from a previous entry behind
+                self.reset();
+
+                anyhow::ensure!(dir_entry.ordinal() > 0, "LFN ordinal 0 is invalid");
+                anyhow::bail!("LFN ordinal {} exceeds maximum of 20", dir_entry.ordinal());
+            }
 
             let mut name = dir_entry.name();
 
@@ -602,6 +1055,8 @@ impl LongFilenameBuf {
         self.extend_name(dir_entry.name());
         self.last_ordinal = Some(dir_entry.ordinal());
 
+        self.n_slots += 1;
+
         Ok(())
     }
 
@@ -640,93 +1095,208 @@ impl LongFilenameBuf {
     }
 }
 
-pub struct DirIter<'a> {
-    reader: ClusterChainReader<'a>,
+/// where a `DirIter` reads the raw entries of a directory from
+pub trait DirReader: ReadBytes {
+    /// offset of the next byte in the underlying data, which becomes the offset of the next entry
+    fn current_offset(&self) -> u64;
+}
+
+#[cfg(feature = "std")]
+impl DirReader for ClusterChainReader<'_> {
+    fn current_offset(&self) -> u64 {
+        ClusterChainReader::current_offset(self)
+    }
+}
+
+/// `DirReader` over the raw bytes of a directory, e.g. a cluster that was read into memory
+pub struct SliceDirReader<'a> {
+    bytes: &'a [u8],
+
+    // offset of bytes[0] in the underlying data
+    offset: u64,
+}
+
+impl<'a> SliceDirReader<'a> {
+    /// `offset` is where `bytes` start in the underlying data
+    pub fn new(bytes: &'a [u8], offset: u64) -> Self {
+        SliceDirReader { bytes, offset }
+    }
+}
+
+impl ReadBytes for SliceDirReader<'_> {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        let mut bytes = self.bytes;
+
+        bytes.read_bytes(buf)?;
+
+        self.offset += buf.len() as u64;
+        self.bytes = bytes;
+
+        Ok(())
+    }
+}
+
+impl DirReader for SliceDirReader<'_> {
+    fn current_offset(&self) -> u64 {
+        self.offset
+    }
+}
+
+pub struct DirIter<R> {
+    reader: R,
 
     long_filename_buf: LongFilenameBuf,
+
+    // drop LFN entries until the next regular entry
+    realign: bool,
 }
 
-impl<'a> DirIter<'a> {
-    pub fn new(reader: ClusterChainReader<'a>) -> Self {
+impl<R: DirReader> DirIter<R> {
+    pub fn new(reader: R) -> Self {
         DirIter {
             reader,
             long_filename_buf: Default::default(),
+            realign: false,
         }
     }
 
+    /// skip any LFN entries up to the next regular entry
+    ///
+    /// the regular entry right after a partially skipped LFN sequence is returned without its
+    /// long name
+    pub fn realign_to_short_entry(mut self) -> Self {
+        self.long_filename_buf.reset();
+        self.realign = true;
+
+        self
+    }
+
+    /// number of remaining entries, including . and ..
+    pub fn count_including_dot_dotdot(&mut self) -> usize {
+        self.by_ref().count()
+    }
+
+    /// check if the remaining entries contain nothing but . and ..
+    ///
+    /// stops at the first other entry
+    pub fn is_empty_dir(&mut self) -> bool {
+        self.all(|dir_entry| dir_entry.is_dot_or_dotdot())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> DirIter<ClusterChainReader<'a>> {
+    /// iterate over the directory starting at `first_cluster` (0 for the root dir), beginning
+    /// `start_offset` bytes into the directory
+    ///
+    /// `start_offset` may land in the middle of a LFN sequence, use `realign_to_short_entry` if
+    /// that's a possibility
+    pub fn from_offset(fat_fs: &'a FatFs, first_cluster: u32, start_offset: u64) -> Self {
+        let mut reader = if first_cluster == 0 {
+            ClusterChainReader::root_dir_reader(fat_fs)
+        } else {
+            ClusterChainReader::new(fat_fs, first_cluster)
+        };
+
+        reader.skip(start_offset);
+
+        DirIter::new(reader)
+    }
+
+    /// yield every entry together with its position in the directory
+    ///
+    /// the position is the byte offset just past the entry relative to the start of the
+    /// directory, so it can be passed to `from_offset` to resume after that entry
+    pub fn with_positions(mut self) -> impl Iterator<Item = (DirEntry, u64)> + 'a {
+        std::iter::from_fn(move || {
+            let dir_entry = self.next()?;
+
+            Some((dir_entry, self.reader.position()))
+        })
+    }
+
+    /// find the entry `name`, honoring the case sensitivity setting of the FS
     pub fn find_by_name(&mut self, name: &str) -> Option<DirEntry> {
-        self.find(|dir_entry| &dir_entry.name_string() == name)
+        let case_sensitive = self.reader.fat_fs().case_sensitive();
+
+        self.find(|dir_entry| dir_entry.matches_name(name, case_sensitive))
     }
 }
 
-impl Iterator for DirIter<'_> {
+impl<R: DirReader> Iterator for DirIter<R> {
     type Item = DirEntry;
 
     fn next(&mut self) -> Option<Self::Item> {
-        fn next_impl(me: &mut DirIter<'_>) -> anyhow::Result<Option<DirEntry>> {
-            let offset = me.reader.current_offset();
+        fn next_impl<R: DirReader>(me: &mut DirIter<R>) -> anyhow::Result<Option<DirEntry>> {
+            loop {
+                let offset = me.reader.current_offset();
 
-            let mut chunk = [0; 32];
+                let mut chunk = [0; 32];
 
-            if me.reader.read_exact(&mut chunk).is_err() {
-                // nothing we can do here since we might be in an invalid state after a partial read
-                anyhow::bail!("read failed");
-            }
+                if me.reader.read_bytes(&mut chunk).is_err() {
+                    // end of the chain (or an empty one), after a partial read we might be in an
+                    // invalid state anyway, so stop here
+                    return Ok(None);
+                }
 
-            let dir_entry = DirEntryWrapper::load(&chunk, offset)
-                .map_err(|e| anyhow::anyhow!("failed to load dir entry: {e}"))?;
+                let dir_entry = DirEntryWrapper::load(&chunk, offset)
+                    .map_err(|e| anyhow::anyhow!("failed to load dir entry: {e}"))?;
 
-            let mut dir_entry = match dir_entry {
-                DirEntryWrapper::Regular(dir_entry) => dir_entry,
-                DirEntryWrapper::LongName(long_name) => {
-                    me.long_filename_buf.next(long_name).map_err(|e| {
-                        me.long_filename_buf.reset();
-                        anyhow::anyhow!("invalid long filename entry: {e}")
-                    })?;
+                let mut dir_entry = match dir_entry {
+                    DirEntryWrapper::Regular(dir_entry) => dir_entry,
+                    DirEntryWrapper::LongName(_) if me.realign => continue,
+                    DirEntryWrapper::LongName(long_name) => {
+                        me.long_filename_buf.next(long_name).map_err(|e| {
+                            me.long_filename_buf.reset();
+                            anyhow::anyhow!("invalid long filename entry: {e}")
+                        })?;
 
-                    return next_impl(me);
-                }
-            };
+                        continue;
+                    }
+                };
 
-            if dir_entry.is_sentinel() {
-                return Ok(None);
-            }
+                me.realign = false;
 
-            if dir_entry.is_empty() {
-                return next_impl(me);
-            }
-
-            if let Some((iter, n_slots)) = me
-                .long_filename_buf
-                .get_buf(dir_entry.checksum)
-                .map_err(|e| {
-                    anyhow::anyhow!(
-                        "failed to get long filename for {}: {}",
-                        dir_entry.name_string(),
-                        e
-                    )
-                })?
-            {
-                // attach long filename to dir_entry
+                if dir_entry.is_sentinel() {
+                    return Ok(None);
+                }
 
-                let long_filename: CompactString =
-                    char::decode_utf16(iter).filter_map(|x| x.ok()).collect();
+                if dir_entry.is_empty() {
+                    continue;
+                }
 
-                dir_entry.set_long_name(long_filename, n_slots);
-            }
+                if let Some((iter, n_slots)) = me
+                    .long_filename_buf
+                    .get_buf(dir_entry.checksum)
+                    .map_err(|e| {
+                        anyhow::anyhow!(
+                            "failed to get long filename for {}: {}",
+                            dir_entry.name_string(),
+                            e
+                        )
+                    })?
+                {
+                    // attach long filename to dir_entry
+
+                    let long_filename: CompactString =
+                        char::decode_utf16(iter).filter_map(|x| x.ok()).collect();
+
+                    dir_entry.set_long_name(long_filename, n_slots);
+                }
 
-            me.long_filename_buf.reset();
+                me.long_filename_buf.reset();
 
-            Ok(Some(dir_entry))
+                return Ok(Some(dir_entry));
+            }
         }
 
-        match next_impl(self) {
-            Ok(x) => x,
-            Err(e) => {
-                // print error message, try next
-                debug!("{}", e);
-
-                self.next()
+        loop {
+            match next_impl(self) {
+                Ok(x) => return x,
+                Err(e) => {
+                    // every error comes after consuming an entry, so skipping it makes progress
+                    debug!("{}", e);
+                }
             }
         }
 
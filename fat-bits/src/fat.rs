@@ -1,12 +1,17 @@
-use std::fmt::Display;
-use std::io::Write as _;
-use std::mem::MaybeUninit;
-use std::ops::RangeInclusive;
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::fmt::Display;
+use core::mem::MaybeUninit;
+use core::ops::RangeInclusive;
 
 use enum_dispatch::enum_dispatch;
 use log::debug;
 
 use crate::FatType;
+use crate::io::{IoError, WriteBytes};
+#[cfg(feature = "std")]
 use crate::subslice::SubSliceMut;
 
 const FREE_ENTRY: u32 = 0;
@@ -21,6 +26,20 @@ pub enum FatError {
     DefectiveCluster,
     #[error("invalid next cluster 0x{0:0X}")]
     InvalidEntry(u32),
+    #[error("no free cluster left")]
+    NoFreeCluster,
+    #[error("cluster chain starting at {0} contains a cycle")]
+    CyclicChain(u32),
+    #[error("can't allocate a chain of zero clusters")]
+    EmptyChain,
+}
+
+/// position in the FAT journal, see `Fat::start_journal`
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct JournalMark {
+    len: usize,
+    outermost: bool,
 }
 
 #[enum_dispatch]
@@ -30,13 +49,28 @@ trait FatOps {
     fn get_entry(&self, cluster: u32) -> u32;
     fn set_entry(&mut self, cluster: u32, entry: u32);
 
+    // (cluster, previous entry) of every change since the journal was started
+    fn journal_mut(&mut self) -> &mut Option<Vec<(u32, u32)>>;
+
     fn valid_entries(&self) -> RangeInclusive<u32>;
     fn reserved_entries(&self) -> RangeInclusive<u32>;
     fn defective_entry(&self) -> u32;
     fn reserved_eof_entries(&self) -> RangeInclusive<u32>;
     fn eof_entry(&self) -> u32;
 
-    fn write_to_disk(&self, sub_slice: SubSliceMut) -> std::io::Result<()>;
+    // number of bytes the entries take up on disk
+    fn len_bytes(&self) -> usize;
+
+    // write the entries as they are laid out on disk, i.e. `len_bytes` bytes
+    fn write_to_disk(&self, writer: &mut dyn WriteBytes) -> Result<(), IoError>;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len_bytes());
+
+        self.write_to_disk(&mut bytes).expect("writing to a Vec can't fail");
+
+        bytes
+    }
 }
 
 #[enum_dispatch(FatOps)]
@@ -49,7 +83,7 @@ pub enum Fat {
 }
 
 impl Display for Fat {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match &self {
             Fat::Fat12(fat12) => write!(f, "{}", fat12),
             Fat::Fat16(fat16) => write!(f, "{}", fat16),
@@ -75,6 +109,23 @@ impl Fat {
         }
     }
 
+    /// range of clusters that can hold data
+    pub fn valid_clusters(&self) -> RangeInclusive<u32> {
+        self.valid_entries()
+    }
+
+    /// iterate over the clusters of the chain starting at `first_cluster`
+    pub fn chain(&self, first_cluster: u32) -> ClusterChainIter<'_> {
+        ClusterChainIter {
+            fat: self,
+            first_cluster,
+            cluster: Some(first_cluster),
+            error: None,
+            n_clusters: 0,
+            max_len: self.valid_entries().count() as u32,
+        }
+    }
+
     pub fn get_next_cluster(&self, cluster: u32) -> Result<Option<u32>, FatError> {
         if cluster == FREE_ENTRY {
             // can't get next cluster for free cluster
@@ -104,9 +155,7 @@ impl Fat {
 
         let entry = self.get_entry(cluster);
 
-        if entry == FREE_ENTRY {
-            Ok(None)
-        } else if entry == self.eof_entry() {
+        if entry == FREE_ENTRY || self.is_eof(entry) {
             Ok(None)
         } else if self.valid_entries().contains(&entry) {
             Ok(Some(entry))
@@ -115,7 +164,7 @@ impl Fat {
         } else if entry == self.defective_entry() {
             Err(FatError::DefectiveCluster)
         } else {
-            unreachable!()
+            Err(FatError::InvalidEntry(entry))
         }
     }
 
@@ -148,7 +197,29 @@ impl Fat {
             log::debug!("setting {cluster} EOF");
         }
 
-        self.set_entry(cluster, next_cluster.unwrap_or(self.eof_entry()));
+        self.update_entry(cluster, next_cluster.unwrap_or(self.eof_entry()));
+    }
+
+    /// allocate a free cluster and append it to the chain ending in `after`
+    ///
+    /// `after` must be the last cluster of its chain. prefers the first free cluster following
+    /// `after` to keep chains contiguous
+    pub fn allocate_cluster(&mut self, after: u32) -> Result<u32, FatError> {
+        if !self.valid_entries().contains(&after) || !self.is_eof(self.get_entry(after)) {
+            return Err(FatError::InvalidEntry(after));
+        }
+
+        let valid_clusters = self.valid_entries();
+
+        let new_cluster = (after + 1..=*valid_clusters.end())
+            .chain(*valid_clusters.start()..after)
+            .find(|&cluster| self.is_free(cluster))
+            .ok_or(FatError::NoFreeCluster)?;
+
+        self.set_next_cluster(new_cluster, None);
+        self.set_next_cluster(after, Some(new_cluster));
+
+        Ok(new_cluster)
     }
 
     /// free a cluster
@@ -170,7 +241,7 @@ impl Fat {
         // use free_chain to free a chain of clusters iteratively
         assert!(self.is_eof(entry));
 
-        self.set_entry(cluster, FREE_ENTRY);
+        self.update_entry(cluster, FREE_ENTRY);
     }
 
     /// free `first_cluster` and all following clusters
@@ -183,7 +254,7 @@ impl Fat {
             // assert cluster either points to another cluster of is the EOF
             assert!(self.valid_entries().contains(&entry) || self.is_eof(entry));
 
-            self.set_entry(first_cluster, FREE_ENTRY);
+            self.update_entry(first_cluster, FREE_ENTRY);
 
             if self.valid_entries().contains(&entry) {
                 first_cluster = entry;
@@ -193,6 +264,47 @@ impl Fat {
         }
     }
 
+    /// free the chain starting at `first_cluster`, returning the number of freed clusters
+    ///
+    /// the whole chain is checked first, so a broken chain is left untouched
+    pub fn free_cluster_chain(&mut self, first_cluster: u32) -> Result<usize, FatError> {
+        let mut n_clusters = 0;
+
+        for cluster in self.chain(first_cluster) {
+            if self.is_free(cluster?) {
+                return Err(FatError::FreeCluster);
+            }
+
+            n_clusters += 1;
+        }
+
+        self.free_chain(first_cluster);
+
+        Ok(n_clusters)
+    }
+
+    /// make `last_cluster` the end of its chain and free all clusters following it
+    ///
+    /// returns the number of freed clusters
+    pub fn truncate_chain(&mut self, last_cluster: u32) -> Result<usize, FatError> {
+        let Some(tail) = self.get_next_cluster(last_cluster)? else {
+            // already the end of the chain
+            return Ok(0);
+        };
+
+        self.update_entry(last_cluster, self.eof_entry());
+
+        self.free_cluster_chain(tail).inspect_err(|_| {
+            // leave the chain as it was
+            self.update_entry(last_cluster, tail);
+        })
+    }
+
+    /// check if `cluster` is free
+    pub fn is_free(&self, cluster: u32) -> bool {
+        self.get_entry(cluster) == FREE_ENTRY
+    }
+
     pub fn count_free_clusters(&self) -> u32 {
         self.valid_entries()
             .map(|cluster| self.get_entry(cluster))
@@ -209,8 +321,140 @@ impl Fat {
         entry == self.eof_entry() || self.reserved_eof_entries().contains(&entry)
     }
 
-    pub fn write_back(&self, sub_slice: SubSliceMut) -> std::io::Result<()> {
-        self.write_to_disk(sub_slice)
+    /// set an entry, recording the previous value if a journal is running
+    fn update_entry(&mut self, cluster: u32, entry: u32) {
+        let prev_entry = self.get_entry(cluster);
+
+        if let Some(journal) = self.journal_mut() {
+            journal.push((cluster, prev_entry));
+        }
+
+        self.set_entry(cluster, entry);
+    }
+
+    /// start recording changes, so they can be rolled back later
+    ///
+    /// journals can be nested, the returned mark identifies this one
+    #[cfg(feature = "std")]
+    pub(crate) fn start_journal(&mut self) -> JournalMark {
+        match self.journal_mut() {
+            Some(journal) => JournalMark {
+                len: journal.len(),
+                outermost: false,
+            },
+            journal @ None => {
+                *journal = Some(Vec::new());
+
+                JournalMark {
+                    len: 0,
+                    outermost: true,
+                }
+            }
+        }
+    }
+
+    /// keep all changes since `mark`
+    #[cfg(feature = "std")]
+    pub(crate) fn commit_journal(&mut self, mark: JournalMark) {
+        if mark.outermost {
+            *self.journal_mut() = None;
+        }
+    }
+
+    /// undo all changes since `mark`
+    #[cfg(feature = "std")]
+    pub(crate) fn rollback_journal(&mut self, mark: JournalMark) {
+        let changes = match self.journal_mut() {
+            Some(journal) => journal.split_off(mark.len),
+            None => Vec::new(),
+        };
+
+        for &(cluster, prev_entry) in changes.iter().rev() {
+            self.set_entry(cluster, prev_entry);
+        }
+
+        self.commit_journal(mark);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_back(&self, mut sub_slice: SubSliceMut) -> std::io::Result<()> {
+        assert!(self.len_bytes() <= sub_slice.len());
+
+        self.write_to_disk(&mut sub_slice)
+    }
+
+    /// serialize the FAT into the bytes it takes up on disk, without needing a `SubSliceMut`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        FatOps::to_bytes(self)
+    }
+
+    /// entries, including the two reserved ones, that differ between this FAT and `other`
+    ///
+    /// both FATs have to be of the same type and size
+    pub fn mismatched_entries<'a>(&'a self, other: &'a Fat) -> impl Iterator<Item = u32> + 'a {
+        assert_eq!(self.fat_type(), other.fat_type());
+        assert_eq!(self.valid_entries(), other.valid_entries());
+
+        let max = *self.valid_entries().end();
+
+        (0..=max).filter(move |&cluster| self.get_entry(cluster) != other.get_entry(cluster))
+    }
+}
+
+/// iterator over the clusters of a chain, see `Fat::chain`
+///
+/// yields an error and stops if a cluster is invalid, its FAT entry can't be followed, or the
+/// chain is longer than there are clusters, i.e. contains a cycle. a free cluster ends the chain,
+/// callers that care have to check for that themselves
+pub struct ClusterChainIter<'a> {
+    fat: &'a Fat,
+
+    first_cluster: u32,
+
+    // next cluster to yield
+    cluster: Option<u32>,
+
+    // failure to look up the cluster after the last yielded one, reported by the next call
+    error: Option<FatError>,
+
+    n_clusters: u32,
+    max_len: u32,
+}
+
+impl<'a> ClusterChainIter<'a> {
+    /// yield clusters up to the first error, dropping the error
+    pub fn take_while_ok(self) -> impl Iterator<Item = u32> + 'a {
+        self.map_while(Result::ok)
+    }
+}
+
+impl Iterator for ClusterChainIter<'_> {
+    type Item = Result<u32, FatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+
+        let cluster = self.cluster.take()?;
+
+        if !self.fat.valid_entries().contains(&cluster) {
+            return Some(Err(FatError::InvalidEntry(cluster)));
+        }
+
+        if self.n_clusters >= self.max_len {
+            // more clusters than there are, must be a cycle
+            return Some(Err(FatError::CyclicChain(self.first_cluster)));
+        }
+
+        self.n_clusters += 1;
+
+        match self.fat.get_next_cluster(cluster) {
+            Ok(next_cluster) => self.cluster = next_cluster,
+            Err(err) => self.error = Some(err),
+        }
+
+        Some(Ok(cluster))
     }
 }
 
@@ -218,10 +462,12 @@ struct Fat12 {
     max: u32,
 
     next_sectors: Box<[u16]>,
+
+    journal: Option<Vec<(u32, u32)>>,
 }
 
 impl Display for Fat12 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "Fat 12 {{")?;
 
         for (i, &x) in self.next_sectors.iter().enumerate() {
@@ -249,42 +495,32 @@ impl Fat12 {
 
         let mut next_sectors = unsafe { next_sectors.assume_init() };
 
-        // assume bytes.len() is multiple of 3
-        // TODO: fix later
-        // assert_eq!(bytes.len() % 3, 0);
-        assert_eq!(max % 2, 0);
+        // two entries are packed into three bytes, so entry n starts at byte n * 3 / 2
+        for (idx, entry) in next_sectors.iter_mut().enumerate() {
+            let offset = idx * 3 / 2;
 
-        let bytes = &bytes[..next_sectors.len() / 2 * 3];
-
-        let (chunks, rem) = bytes.as_chunks::<3>();
-
-        assert_eq!(rem.len(), 0);
-
-        // TODO: correctly handle cases where max is larger than #FAT entries and v.v.
-        for (idx, triple) in chunks.iter().take(max as usize / 2).enumerate() {
-            // first (even) entry gets truncated
-            let first = u16::from_le_bytes(triple[..2].try_into().unwrap()) & 0xFFF;
-            // second (odd) entry gets shifted
-            let second = u16::from_le_bytes(triple[1..].try_into().unwrap()) >> 4;
+            let Some(pair) = bytes.get(offset..offset + 2) else {
+                // FAT is shorter than max, treat missing entries as free
+                debug!("FAT12 only holds {idx} entries, but max is {max}");
+                break;
+            };
 
-            assert!(idx + 1 < next_sectors.len());
+            let pair = u16::from_le_bytes(pair.try_into().unwrap());
 
-            next_sectors[2 * idx] = first;
-            next_sectors[2 * idx + 1] = second;
+            *entry = if idx % 2 == 0 {
+                // even entry gets truncated
+                pair & 0xFFF
+            } else {
+                // odd entry gets shifted
+                pair >> 4
+            };
         }
 
-        if max % 2 == 1 {
-            // odd max: need to fix last cluster
-            let idx = max as usize / 2 + 1;
-
-            let triple = chunks[idx];
-
-            let first = u16::from_le_bytes(triple[..2].try_into().unwrap()) & 0xFFF;
-
-            next_sectors[2 * idx] = first;
+        Fat12 {
+            max,
+            next_sectors,
+            journal: None,
         }
-
-        Fat12 { max, next_sectors }
     }
 }
 
@@ -300,12 +536,16 @@ impl FatOps for Fat12 {
         self.next_sectors[cluster as usize] = entry as u16;
     }
 
+    fn journal_mut(&mut self) -> &mut Option<Vec<(u32, u32)>> {
+        &mut self.journal
+    }
+
     fn valid_entries(&self) -> RangeInclusive<u32> {
         2..=self.max
     }
 
     fn reserved_entries(&self) -> RangeInclusive<u32> {
-        (self.max as u32 + 1)..=0xFF6
+        (self.max + 1)..=0xFF6
     }
 
     fn defective_entry(&self) -> u32 {
@@ -320,46 +560,30 @@ impl FatOps for Fat12 {
         0xFFF
     }
 
-    fn write_to_disk(&self, mut sub_slice: SubSliceMut) -> std::io::Result<()> {
-        assert!(2 * sub_slice.len() > 3 * self.next_sectors.len());
-
-        let mut iter = self.next_sectors.chunks_exact(2);
-
-        let mut buf: [u8; 3];
-
-        for chunk in &mut iter {
-            // first (even) entry gets truncated
-            // let first = u16::from_le_bytes(triple[..2].try_into().unwrap()) & 0xFFF;
-            // second (odd) entry gets shifted
-            // let second = u16::from_le_bytes(triple[1..].try_into().unwrap()) >> 4;
-
-            // assert!(idx + 1 < next_sectors.len());
-
-            // next_sectors[2 * idx] = first;
-            // next_sectors[2 * idx + 1] = second;
+    fn len_bytes(&self) -> usize {
+        // N entries of 12 bits take ceil(N * 12 / 8) bytes
+        (self.next_sectors.len() * 3).div_ceil(2)
+    }
 
-            // sub_slice.write_all(&entry.to_le_bytes())?;
+    fn write_to_disk(&self, writer: &mut dyn WriteBytes) -> Result<(), IoError> {
+        let len = self.len_bytes();
 
-            let first = chunk[0];
-            let second = chunk[1];
+        let mut bytes = Vec::with_capacity(len + 1);
 
-            buf = [0; 3];
+        // every pair of entries a, b is packed into three bytes, a missing final b is zero
+        for pair in self.next_sectors.chunks(2) {
+            let a = pair[0] & 0xFFF;
+            let b = pair.get(1).copied().unwrap_or(0) & 0xFFF;
 
-            // buf[..2] |= &first.to_le_bytes();
-            buf[0] = first.to_le_bytes()[0];
-            buf[1] = first.to_le_bytes()[1] | (second << 4).to_le_bytes()[0];
-            buf[2] = (second << 4).to_le_bytes()[1];
-            sub_slice.write_all(&buf)?;
+            bytes.push(a as u8);
+            bytes.push((a >> 8) as u8 | ((b & 0xF) << 4) as u8);
+            bytes.push((b >> 4) as u8);
         }
 
-        match iter.remainder() {
-            [] => {}
-            [x] => sub_slice.write_all(&x.to_le_bytes())?,
+        // an odd final entry only takes one and a half bytes
+        bytes.truncate(len);
 
-            _ => unreachable!(),
-        }
-
-        Ok(())
+        writer.write_bytes(&bytes)
     }
 }
 
@@ -367,10 +591,12 @@ struct Fat16 {
     max: u32,
 
     next_sectors: Box<[u16]>,
+
+    journal: Option<Vec<(u32, u32)>>,
 }
 
 impl Display for Fat16 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "Fat 16 {{")?;
 
         for (i, &x) in self.next_sectors.iter().enumerate() {
@@ -398,23 +624,18 @@ impl Fat16 {
 
         let mut next_sectors = unsafe { next_sectors.assume_init() };
 
-        // assume bytes.len() is multiple of 2
-        // TODO: fix later
-        assert_eq!(bytes.len() % 2, 0);
-
-        let (chunks, rem) = bytes.as_chunks::<2>();
-
-        assert_eq!(rem.len(), 0);
+        let (chunks, _) = bytes.as_chunks::<2>();
 
-        // TODO: correctly handle cases where max is larger than #FAT entries and v.v.
-        for (idx, chunk) in chunks.iter().take(max as usize / 2).enumerate() {
-            // first (even) entry gets truncated
-            let entry = u16::from_le_bytes(chunk[..2].try_into().unwrap());
-
-            next_sectors[idx] = entry;
+        // entries past the end of the FAT are treated as free
+        for (entry, chunk) in next_sectors.iter_mut().zip(chunks) {
+            *entry = u16::from_le_bytes(*chunk);
         }
 
-        Fat16 { max, next_sectors }
+        Fat16 {
+            max,
+            next_sectors,
+            journal: None,
+        }
     }
 }
 
@@ -430,12 +651,16 @@ impl FatOps for Fat16 {
         self.next_sectors[cluster as usize] = entry as u16;
     }
 
+    fn journal_mut(&mut self) -> &mut Option<Vec<(u32, u32)>> {
+        &mut self.journal
+    }
+
     fn valid_entries(&self) -> RangeInclusive<u32> {
         2..=self.max
     }
 
     fn reserved_entries(&self) -> RangeInclusive<u32> {
-        (self.max as u32 + 1)..=0xFFF6
+        (self.max + 1)..=0xFFF6
     }
 
     fn defective_entry(&self) -> u32 {
@@ -450,25 +675,32 @@ impl FatOps for Fat16 {
         0xFFFF
     }
 
-    fn write_to_disk(&self, mut sub_slice: SubSliceMut) -> std::io::Result<()> {
-        assert_eq!(2 * sub_slice.len(), self.next_sectors.len());
+    fn len_bytes(&self) -> usize {
+        2 * self.next_sectors.len()
+    }
 
+    fn write_to_disk(&self, writer: &mut dyn WriteBytes) -> Result<(), IoError> {
         for &entry in self.next_sectors.iter() {
-            sub_slice.write_all(&entry.to_le_bytes())?;
+            writer.write_bytes(&entry.to_le_bytes())?;
         }
 
         Ok(())
     }
 }
 
+/// FAT32 entries are only 28 bits wide
+const FAT32_ENTRY_MASK: u32 = 0x0FFFFFFF;
+
 struct Fat32 {
     max: u32,
 
     next_sectors: Box<[u32]>,
+
+    journal: Option<Vec<(u32, u32)>>,
 }
 
 impl Display for Fat32 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "Fat 32 {{")?;
 
         for (i, &x) in self.next_sectors.iter().enumerate() {
@@ -504,15 +736,16 @@ impl Fat32 {
 
         assert_eq!(rem.len(), 0);
 
-        // TODO: correctly handle cases where max is larger than #FAT entries and v.v.
-        for (idx, chunk) in chunks.iter().take(max as usize / 2).enumerate() {
-            // first (even) entry gets truncated
-            let entry = u32::from_le_bytes(chunk[..4].try_into().unwrap());
-
-            next_sectors[idx] = entry;
+        // entries past the end of the FAT are treated as free
+        for (entry, chunk) in next_sectors.iter_mut().zip(chunks) {
+            *entry = u32::from_le_bytes(*chunk);
         }
 
-        Fat32 { max, next_sectors }
+        Fat32 {
+            max,
+            next_sectors,
+            journal: None,
+        }
     }
 }
 
@@ -521,11 +754,20 @@ impl FatOps for Fat32 {
         let cluster = cluster as usize;
         assert!(cluster < self.next_sectors.len());
 
-        self.next_sectors[cluster] as u32
+        // the high 4 bits are reserved and not part of the entry
+        self.next_sectors[cluster] & FAT32_ENTRY_MASK
     }
 
     fn set_entry(&mut self, cluster: u32, entry: u32) {
-        self.next_sectors[cluster as usize] = entry;
+        let cluster = cluster as usize;
+
+        // the reserved high 4 bits have to be preserved
+        self.next_sectors[cluster] =
+            (self.next_sectors[cluster] & !FAT32_ENTRY_MASK) | (entry & FAT32_ENTRY_MASK);
+    }
+
+    fn journal_mut(&mut self) -> &mut Option<Vec<(u32, u32)>> {
+        &mut self.journal
     }
 
     fn valid_entries(&self) -> RangeInclusive<u32> {
@@ -533,28 +775,318 @@ impl FatOps for Fat32 {
     }
 
     fn reserved_entries(&self) -> RangeInclusive<u32> {
-        (self.max + 1)..=0xFFFFFFF6
+        (self.max + 1)..=0x0FFFFFF6
     }
 
     fn defective_entry(&self) -> u32 {
-        0xFFFFFFF7
+        0x0FFFFFF7
     }
 
     fn reserved_eof_entries(&self) -> RangeInclusive<u32> {
-        0xFFFFFFF8..=0xFFFFFFFE
+        0x0FFFFFF8..=0x0FFFFFFE
     }
 
     fn eof_entry(&self) -> u32 {
-        0xFFFFFFFF
+        0x0FFFFFFF
     }
 
-    fn write_to_disk(&self, mut sub_slice: SubSliceMut) -> std::io::Result<()> {
-        assert_eq!(4 * sub_slice.len(), self.next_sectors.len());
+    fn len_bytes(&self) -> usize {
+        4 * self.next_sectors.len()
+    }
 
+    fn write_to_disk(&self, writer: &mut dyn WriteBytes) -> Result<(), IoError> {
         for &entry in self.next_sectors.iter() {
-            sub_slice.write_all(&entry.to_le_bytes())?;
+            writer.write_bytes(&entry.to_le_bytes())?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::{Fat, Fat12, Fat16, Fat32, FatError, FatOps};
+    use crate::subslice::SubSliceMut;
+
+    /// pack 12 bit entries the way they are laid out on disk
+    fn encode_fat12(entries: &[u16]) -> Vec<u8> {
+        let mut bytes = vec![0; (entries.len() * 3).div_ceil(2)];
+
+        for (idx, &entry) in entries.iter().enumerate() {
+            let offset = idx * 3 / 2;
+
+            if idx % 2 == 0 {
+                bytes[offset] = entry as u8;
+                bytes[offset + 1] |= (entry >> 8) as u8 & 0x0F;
+            } else {
+                bytes[offset] |= (entry << 4) as u8;
+                bytes[offset + 1] = (entry >> 4) as u8;
+            }
+        }
+
+        bytes
+    }
+
+    /// some arbitrary, but distinct, 12 bit entries
+    fn entries(n: usize) -> Vec<u16> {
+        (0..n).map(|i| (i as u16).wrapping_mul(0x9E5) & 0xFFF).collect()
+    }
+
+    fn assert_entries(fat: &Fat12, entries: &[u16]) {
+        assert_eq!(fat.next_sectors.len(), fat.max as usize + 1);
+
+        for cluster in 0..=fat.max {
+            assert_eq!(
+                fat.get_entry(cluster),
+                entries[cluster as usize] as u32,
+                "entry {cluster}"
+            );
+        }
+    }
+
+    #[test]
+    fn mismatched_entries() {
+        let mut entries = entries(64);
+
+        let fat = Fat::Fat12(Fat12::new(&encode_fat12(&entries), 63));
+
+        assert_eq!(fat.mismatched_entries(&fat).count(), 0);
+
+        entries[1] ^= 0x001;
+        entries[40] ^= 0x800;
+
+        let other = Fat::Fat12(Fat12::new(&encode_fat12(&entries), 63));
+
+        assert_eq!(fat.mismatched_entries(&other).collect::<Vec<_>>(), [1, 40]);
+    }
+
+    #[test]
+    fn smallest_max() {
+        let entries = [0xFF8, 0xFFF, 0x003, 0xFFF];
+
+        let fat = Fat12::new(&encode_fat12(&entries), 3);
+
+        assert_entries(&fat, &entries);
+    }
+
+    #[test]
+    fn largest_max() {
+        let entries = entries(4085);
+
+        let fat = Fat12::new(&encode_fat12(&entries), 4084);
+
+        assert_entries(&fat, &entries);
+    }
+
+    #[test]
+    fn odd_max() {
+        let entries = entries(12);
+
+        let fat = Fat12::new(&encode_fat12(&entries), 11);
+
+        assert_entries(&fat, &entries);
+    }
+
+    #[test]
+    fn more_bytes_than_max() {
+        let entries = entries(20);
+
+        let fat = Fat12::new(&encode_fat12(&entries), 10);
+
+        assert_entries(&fat, &entries);
+    }
+
+    #[test]
+    fn exact_fit() {
+        // 11 entries need 16.5 bytes, 12 entries exactly 18
+        for (max, len) in [(10, 17), (11, 18)] {
+            let entries = entries(max as usize + 1);
+            let bytes = encode_fat12(&entries);
+
+            assert_eq!(bytes.len(), len);
+
+            let fat = Fat12::new(&bytes, max);
+
+            assert_entries(&fat, &entries);
+        }
+    }
+
+    #[test]
+    fn round_trip_random() {
+        // xorshift32, deterministic so failures are reproducible
+        let mut state: u32 = 0xDEAD_BEEF;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        for _ in 0..64 {
+            let max = 3 + next() % 4082;
+
+            let entries: Vec<u16> = (0..=max).map(|_| next() as u16 & 0xFFF).collect();
+
+            let fat = Fat12::new(&encode_fat12(&entries), max);
+
+            // exactly as many bytes as needed
+            let len = ((max as usize + 1) * 12).div_ceil(8);
+
+            let data: Rc<RefCell<&'static mut [u8]>> =
+                Rc::new(RefCell::new(Box::leak(vec![0; len].into_boxed_slice())));
+
+            fat.write_to_disk(&mut SubSliceMut::new(data.clone(), 0, len)).unwrap();
+
+            assert_eq!(&data.borrow()[..], encode_fat12(&entries), "max {max}");
+
+            let reloaded = Fat12::new(&data.borrow()[..], max);
+
+            assert_entries(&reloaded, &entries);
+        }
+    }
+
+    #[test]
+    fn round_trip_set_entry() {
+        for max in [10, 11] {
+            let mut fat = Fat12::new(&encode_fat12(&entries(max as usize + 1)), max);
+
+            fat.set_entry(2, 0x003);
+            fat.set_entry(3, 0xFFF);
+            fat.set_entry(max, 0xFF7);
+
+            let len = (max as usize + 1) * 3 / 2 + 1;
+
+            let data: Rc<RefCell<&'static mut [u8]>> =
+                Rc::new(RefCell::new(Box::leak(vec![0; len].into_boxed_slice())));
+
+            fat.write_to_disk(&mut SubSliceMut::new(data.clone(), 0, len)).unwrap();
+
+            let reloaded = Fat12::new(&data.borrow()[..], max);
+
+            assert_eq!(reloaded.next_sectors, fat.next_sectors);
+        }
+    }
+
+    #[test]
+    fn to_bytes() {
+        for max in [10, 11] {
+            let entries = entries(max as usize + 1);
+            let bytes = encode_fat12(&entries);
+
+            let fat = Fat::from(Fat12::new(&bytes, max));
+
+            assert_eq!(fat.to_bytes(), bytes);
+        }
+
+        let mut fat16 = Fat16::new(&[0; 8], 4086);
+        fat16.set_entry(2, 0xFFFF);
+        fat16.set_entry(3, 0x1234);
+
+        let bytes = Fat::from(fat16).to_bytes();
+
+        assert_eq!(bytes.len(), 2 * 4087);
+        assert_eq!(bytes[4..8], [0xFF, 0xFF, 0x34, 0x12]);
+
+        let mut fat32 = Fat32::new(&[0; 16], 65526);
+        fat32.set_entry(2, 0x0FFFFFFF);
+        fat32.set_entry(3, 0x02345678);
+
+        let bytes = Fat::from(fat32).to_bytes();
+
+        assert_eq!(bytes.len(), 4 * 65527);
+        assert_eq!(bytes[8..16], [0xFF, 0xFF, 0xFF, 0x0F, 0x78, 0x56, 0x34, 0x02]);
+    }
+
+    #[test]
+    fn fat32_reserved_bits() {
+        let max = 65526;
+
+        let mut bytes = vec![0; 4 * (max as usize + 1)];
+
+        // 2 -> EOF with all high bits set, 3 -> 4 with some, 4 -> EOF, last entry -> 3
+        for (cluster, entry) in [(2, 0xFFFFFFFF), (3, 0xA0000004), (4, 0x0FFFFFF8), (max, 3)] {
+            bytes[4 * cluster as usize..][..4].copy_from_slice(&u32::to_le_bytes(entry));
+        }
+
+        let mut fat = Fat::from(Fat32::new(&bytes, max));
+
+        assert_eq!(fat.get_next_cluster(2).unwrap(), None);
+        assert_eq!(fat.get_next_cluster(3).unwrap(), Some(4));
+        assert_eq!(fat.get_next_cluster(4).unwrap(), None);
+
+        // entries in the second half of the FAT are decoded as well
+        assert_eq!(fat.get_next_cluster(max).unwrap(), Some(3));
+
+        // the high bits are kept when an entry changes
+        fat.set_next_cluster(2, Some(5));
+        fat.set_next_cluster(5, None);
+        fat.set_next_cluster(4, Some(6));
+        fat.set_next_cluster(6, None);
+
+        let bytes = fat.to_bytes();
+
+        assert_eq!(bytes[8..12], 0xF0000005u32.to_le_bytes());
+        assert_eq!(bytes[12..16], 0xA0000004u32.to_le_bytes());
+        assert_eq!(bytes[16..20], 6u32.to_le_bytes());
+        assert_eq!(bytes[20..24], 0x0FFFFFFFu32.to_le_bytes());
+        assert_eq!(bytes[24..28], 0x0FFFFFFFu32.to_le_bytes());
+    }
+
+    #[test]
+    fn chain() {
+        // 2 -> 3 -> 4 -> EOF, 5 -> 0xFF7 (defective), 6 -> 7 -> 6, 8 free
+        let mut entries = vec![0xFF8, 0xFFF, 0x003, 0x004, 0xFFF, 0xFF7, 0x007, 0x006, 0x000];
+        entries.resize(12, 0);
+
+        let fat = Fat::from(Fat12::new(&encode_fat12(&entries), 11));
+
+        let clusters: Result<Vec<u32>, FatError> = fat.chain(2).collect();
+        assert_eq!(clusters.unwrap(), [2, 3, 4]);
+
+        // the cluster is yielded before the error of following it
+        let mut chain = fat.chain(5);
+        assert_eq!(chain.next().unwrap().unwrap(), 5);
+        assert!(matches!(chain.next(), Some(Err(FatError::DefectiveCluster))));
+        assert!(chain.next().is_none());
+
+        let mut chain = fat.chain(6);
+        assert_eq!(chain.by_ref().take(10).map(Result::unwrap).count(), 10);
+        assert!(matches!(chain.next(), Some(Err(FatError::CyclicChain(6)))));
+        assert!(chain.next().is_none());
+
+        assert_eq!(fat.chain(8).map(Result::unwrap).collect::<Vec<_>>(), [8]);
+
+        assert!(matches!(fat.chain(0).next(), Some(Err(FatError::InvalidEntry(0)))));
+        assert!(matches!(fat.chain(12).next(), Some(Err(FatError::InvalidEntry(12)))));
+
+        assert_eq!(fat.chain(5).take_while_ok().collect::<Vec<_>>(), [5]);
+        assert_eq!(fat.chain(6).take_while_ok().count(), 10);
+    }
+
+    #[test]
+    fn free_cluster_chain() {
+        // 2 -> 3 -> 4 -> EOF, 5 -> EOF, 6 -> 7 -> 6, 8 free
+        let mut entries = vec![0xFF8, 0xFFF, 0x003, 0x004, 0xFFF, 0xFFF, 0x007, 0x006, 0x000];
+        entries.resize(12, 0);
+
+        let mut fat = Fat::from(Fat12::new(&encode_fat12(&entries), 11));
+
+        assert!(matches!(fat.free_cluster_chain(6), Err(FatError::CyclicChain(6))));
+        assert!(matches!(fat.free_cluster_chain(8), Err(FatError::FreeCluster)));
+        assert!(matches!(fat.free_cluster_chain(12), Err(FatError::InvalidEntry(12))));
+
+        // failed attempts don't change anything
+        assert_eq!(fat.get_entry(6), 0x007);
+        assert_eq!(fat.get_entry(7), 0x006);
+
+        assert_eq!(fat.free_cluster_chain(2).unwrap(), 3);
+        assert_eq!(fat.free_cluster_chain(5).unwrap(), 1);
+
+        for cluster in 2..=5 {
+            assert!(fat.is_free(cluster));
+        }
+    }
+}
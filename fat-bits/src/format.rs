@@ -0,0 +1,367 @@
+use log::debug;
+
+use crate::{FatFs, FatType, SliceLike};
+
+/// number of FAT copies written by `FatFs::format`
+const NUM_FATS: u8 = 2;
+
+/// root dir entries for FAT12 and FAT16
+const ROOT_ENTRY_COUNT: u16 = 512;
+
+const FAT32_RESERVED_SECTORS: u16 = 32;
+const FAT32_FS_INFO_SECTOR: u16 = 1;
+const FAT32_BACKUP_BOOT_SECTOR: u16 = 6;
+const FAT32_ROOT_CLUSTER: u32 = 2;
+
+const MEDIA: u8 = 0xF8;
+
+/// parameters for `FatFs::format`
+#[derive(Debug, Clone)]
+pub struct FormatParams {
+    /// total size of the volume in bytes, gets rounded down to whole sectors
+    pub total_size: u64,
+    pub bytes_per_sector: u16,
+    /// picked based on the volume size if None
+    pub sectors_per_cluster: Option<u8>,
+    /// picked based on the volume size if None
+    pub fat_type: Option<FatType>,
+    pub oem_name: [u8; 8],
+    pub volume_label: [u8; 11],
+    pub volume_serial_number: u32,
+}
+
+impl FormatParams {
+    /// default parameters for a volume of `total_size` bytes
+    pub fn new(total_size: u64) -> FormatParams {
+        FormatParams {
+            total_size,
+            bytes_per_sector: 512,
+            sectors_per_cluster: None,
+            fat_type: None,
+            oem_name: *b"MSWIN4.1",
+            volume_label: *b"NO NAME    ",
+            volume_serial_number: 0,
+        }
+    }
+}
+
+/// on-disk layout computed from `FormatParams`
+#[derive(Debug)]
+struct Layout {
+    fat_type: FatType,
+    bytes_per_sector: u16,
+    sectors_per_cluster: u8,
+    reserved_sectors: u16,
+    root_entry_count: u16,
+    total_sectors: u32,
+    fat_size: u32,
+}
+
+impl Layout {
+    fn new(params: &FormatParams) -> anyhow::Result<Layout> {
+        let bytes_per_sector = params.bytes_per_sector;
+
+        anyhow::ensure!(
+            [512, 1024, 2048, 4096].contains(&bytes_per_sector),
+            "invalid bytes per sector: {}",
+            bytes_per_sector
+        );
+
+        let total_sectors: u32 = (params.total_size / bytes_per_sector as u64)
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("volume of {} bytes is too large", params.total_size))?;
+
+        let fat_type = params.fat_type.unwrap_or(match params.total_size {
+            0..=0x100_0000 => FatType::Fat12,
+            0x100_0001..=0x2000_0000 => FatType::Fat16,
+            _ => FatType::Fat32,
+        });
+
+        let (reserved_sectors, root_entry_count) = match fat_type {
+            FatType::Fat12 | FatType::Fat16 => (1, ROOT_ENTRY_COUNT),
+            FatType::Fat32 => (FAT32_RESERVED_SECTORS, 0),
+        };
+
+        let candidates: Vec<u8> = match params.sectors_per_cluster {
+            Some(sectors_per_cluster) => {
+                anyhow::ensure!(
+                    sectors_per_cluster.is_power_of_two(),
+                    "invalid sectors per cluster: {}",
+                    sectors_per_cluster
+                );
+
+                vec![sectors_per_cluster]
+            }
+            // clusters of at most 32 KiB for compatibility
+            None => (0..8)
+                .map(|shift| 1 << shift)
+                .filter(|&n: &u8| n as u32 * bytes_per_sector as u32 <= 32 * 1024)
+                .collect(),
+        };
+
+        for sectors_per_cluster in candidates {
+            let mut layout = Layout {
+                fat_type,
+                bytes_per_sector,
+                sectors_per_cluster,
+                reserved_sectors,
+                root_entry_count,
+                total_sectors,
+                fat_size: 0,
+            };
+
+            layout.fit_fat_size()?;
+
+            if layout.is_valid_cluster_count() {
+                return Ok(layout);
+            }
+
+            debug!(
+                "{} clusters with {} sectors per cluster is invalid for {:?}",
+                layout.count_of_clusters(),
+                sectors_per_cluster,
+                fat_type
+            );
+        }
+
+        anyhow::bail!(
+            "can't create a {:?} file system on a volume of {} sectors",
+            fat_type,
+            total_sectors
+        )
+    }
+
+    fn root_dir_sectors(&self) -> u32 {
+        (32 * self.root_entry_count as u32).div_ceil(self.bytes_per_sector as u32)
+    }
+
+    fn first_data_sector(&self) -> u32 {
+        self.reserved_sectors as u32 + NUM_FATS as u32 * self.fat_size + self.root_dir_sectors()
+    }
+
+    fn count_of_clusters(&self) -> u32 {
+        (self.total_sectors - self.first_data_sector()) / self.sectors_per_cluster as u32
+    }
+
+    fn sector_to_offset(&self, sector: u32) -> u64 {
+        sector as u64 * self.bytes_per_sector as u64
+    }
+
+    /// smallest FAT (in sectors) that can hold an entry for every cluster
+    fn fit_fat_size(&mut self) -> anyhow::Result<()> {
+        self.fat_size = 1;
+
+        loop {
+            let non_data_sectors = self.first_data_sector();
+
+            anyhow::ensure!(
+                non_data_sectors < self.total_sectors,
+                "volume of {} sectors is too small for a {:?} file system",
+                self.total_sectors,
+                self.fat_type
+            );
+
+            let num_entries = self.count_of_clusters() + 2;
+
+            let fat_bytes = match self.fat_type {
+                FatType::Fat12 => (num_entries * 3).div_ceil(2),
+                FatType::Fat16 => num_entries * 2,
+                FatType::Fat32 => num_entries * 4,
+            };
+
+            let fat_size = fat_bytes.div_ceil(self.bytes_per_sector as u32);
+
+            // shrinking the data region only ever makes the required FAT smaller, so this
+            // converges
+            if fat_size <= self.fat_size {
+                return Ok(());
+            }
+
+            self.fat_size = fat_size;
+        }
+    }
+
+    fn is_valid_cluster_count(&self) -> bool {
+        let count_of_clusters = self.count_of_clusters();
+
+        match self.fat_type {
+            FatType::Fat12 => count_of_clusters < 4085,
+            FatType::Fat16 => (4085..65525).contains(&count_of_clusters),
+            FatType::Fat32 => count_of_clusters >= 65525,
+        }
+    }
+
+    fn boot_sector(&self, params: &FormatParams) -> Vec<u8> {
+        let mut bytes = vec![0; self.bytes_per_sector as usize];
+
+        let (total_sectors_16, total_sectors_32) = match u16::try_from(self.total_sectors) {
+            Ok(n) if self.fat_type != FatType::Fat32 => (n, 0),
+            _ => (0, self.total_sectors),
+        };
+
+        bytes[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        bytes[3..11].copy_from_slice(&params.oem_name);
+        bytes[11..13].copy_from_slice(&self.bytes_per_sector.to_le_bytes());
+        bytes[13] = self.sectors_per_cluster;
+        bytes[14..16].copy_from_slice(&self.reserved_sectors.to_le_bytes());
+        bytes[16] = NUM_FATS;
+        bytes[17..19].copy_from_slice(&self.root_entry_count.to_le_bytes());
+        bytes[19..21].copy_from_slice(&total_sectors_16.to_le_bytes());
+        bytes[21] = MEDIA;
+        bytes[24..26].copy_from_slice(&32u16.to_le_bytes());
+        bytes[26..28].copy_from_slice(&64u16.to_le_bytes());
+        bytes[32..36].copy_from_slice(&total_sectors_32.to_le_bytes());
+
+        let ext_bpb = match self.fat_type {
+            FatType::Fat12 | FatType::Fat16 => {
+                bytes[22..24].copy_from_slice(&(self.fat_size as u16).to_le_bytes());
+
+                &mut bytes[36..]
+            }
+            FatType::Fat32 => {
+                bytes[36..40].copy_from_slice(&self.fat_size.to_le_bytes());
+                bytes[44..48].copy_from_slice(&FAT32_ROOT_CLUSTER.to_le_bytes());
+                bytes[48..50].copy_from_slice(&FAT32_FS_INFO_SECTOR.to_le_bytes());
+                bytes[50..52].copy_from_slice(&FAT32_BACKUP_BOOT_SECTOR.to_le_bytes());
+
+                &mut bytes[64..]
+            }
+        };
+
+        // drive number, reserved, boot sig, volume ID, volume label, file sys type
+        ext_bpb[0] = 0x80;
+        ext_bpb[2] = 0x29;
+        ext_bpb[3..7].copy_from_slice(&params.volume_serial_number.to_le_bytes());
+        ext_bpb[7..18].copy_from_slice(&params.volume_label);
+        ext_bpb[18..26].copy_from_slice(match self.fat_type {
+            FatType::Fat12 => b"FAT12   ",
+            FatType::Fat16 => b"FAT16   ",
+            FatType::Fat32 => b"FAT32   ",
+        });
+
+        bytes[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        bytes
+    }
+
+    /// first sector of an empty FAT
+    ///
+    /// entry 0 holds the media byte, entry 1 is EOF, and for FAT32 the root dir cluster is EOF
+    fn first_fat_sector(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.bytes_per_sector as usize];
+
+        match self.fat_type {
+            FatType::Fat12 => {
+                bytes[..3].copy_from_slice(&[MEDIA, 0xFF, 0xFF]);
+            }
+            FatType::Fat16 => {
+                bytes[..2].copy_from_slice(&(0xFF00 | MEDIA as u16).to_le_bytes());
+                bytes[2..4].copy_from_slice(&0xFFFFu16.to_le_bytes());
+            }
+            FatType::Fat32 => {
+                bytes[..4].copy_from_slice(&(0x0FFFFF00 | MEDIA as u32).to_le_bytes());
+                bytes[4..8].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+
+                let root = 4 * FAT32_ROOT_CLUSTER as usize;
+                bytes[root..][..4].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+            }
+        }
+
+        bytes
+    }
+
+    fn fs_info_sector(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.bytes_per_sector as usize];
+
+        // everything but the root dir cluster is free
+        let free_count = self.count_of_clusters() - 1;
+        let next_free = FAT32_ROOT_CLUSTER + 1;
+
+        bytes[..4].copy_from_slice(&0x41615252u32.to_le_bytes());
+        bytes[484..488].copy_from_slice(&0x61417272u32.to_le_bytes());
+        bytes[488..492].copy_from_slice(&free_count.to_le_bytes());
+        bytes[492..496].copy_from_slice(&next_free.to_le_bytes());
+        bytes[508..512].copy_from_slice(&0xAA550000u32.to_le_bytes());
+
+        bytes
+    }
+}
+
+/// overwrite `len` bytes at `offset` with zeros
+fn zero_range(storage: &mut impl SliceLike, offset: u64, len: u64) -> std::io::Result<()> {
+    const CHUNK: u64 = 64 * 1024;
+
+    let zeros = [0; CHUNK as usize];
+
+    let mut written = 0;
+
+    while written < len {
+        let n = CHUNK.min(len - written);
+
+        storage.write_at_offset(offset + written, &zeros[..n as usize])?;
+
+        written += n;
+    }
+
+    Ok(())
+}
+
+impl FatFs {
+    /// create a fresh, empty FAT file system on `storage`
+    ///
+    /// `storage` is grown to the volume size if it is too small and resizable
+    pub fn format<S>(mut storage: S, params: FormatParams) -> anyhow::Result<FatFs>
+    where
+        S: SliceLike + Send + 'static,
+    {
+        let layout = Layout::new(&params)?;
+
+        debug!("formatting volume: {layout:?}");
+
+        let volume_len = layout.sector_to_offset(layout.total_sectors);
+
+        if storage.byte_len()? < volume_len {
+            storage.resize(volume_len)?;
+        }
+
+        // reserved sectors, all FATs, and the root directory start out zeroed
+        let metadata_len = match layout.fat_type {
+            FatType::Fat12 | FatType::Fat16 => layout.sector_to_offset(layout.first_data_sector()),
+            FatType::Fat32 => {
+                layout.sector_to_offset(layout.first_data_sector())
+                    + layout.sector_to_offset(layout.sectors_per_cluster as u32)
+            }
+        };
+
+        zero_range(&mut storage, 0, metadata_len)?;
+
+        let boot_sector = layout.boot_sector(&params);
+
+        storage.write_at_offset(0, &boot_sector)?;
+
+        let first_fat_sector = layout.first_fat_sector();
+
+        for i in 0..NUM_FATS as u32 {
+            let fat_sector = layout.reserved_sectors as u32 + i * layout.fat_size;
+
+            storage.write_at_offset(layout.sector_to_offset(fat_sector), &first_fat_sector)?;
+        }
+
+        if layout.fat_type == FatType::Fat32 {
+            let fs_info = layout.fs_info_sector();
+
+            for sector in [FAT32_FS_INFO_SECTOR, FAT32_BACKUP_BOOT_SECTOR + FAT32_FS_INFO_SECTOR] {
+                storage.write_at_offset(layout.sector_to_offset(sector as u32), &fs_info)?;
+            }
+
+            storage.write_at_offset(
+                layout.sector_to_offset(FAT32_BACKUP_BOOT_SECTOR as u32),
+                &boot_sector,
+            )?;
+        }
+
+        storage.flush()?;
+
+        Ok(FatFs::load(storage)?)
+    }
+}
@@ -1,17 +1,29 @@
+use crate::FatFsError;
 use crate::utils::load_u32_le;
 
-#[allow(dead_code)]
+const LEAD_SIG: u32 = 0x41615252;
+const STRUCT_SIG: u32 = 0x61417272;
+const TRAIL_SIG: u32 = 0xAA550000;
+
+/// value of `free_count` and `next_free` if they are unknown
+const UNKNOWN: u32 = 0xFFFFFFFF;
+
+#[derive(Debug, Clone)]
 pub struct FsInfo {
     free_count: u32,
     next_free: u32,
 }
 
 impl FsInfo {
-    pub fn load(bytes: &[u8]) -> anyhow::Result<FsInfo> {
+    pub fn load(bytes: &[u8]) -> Result<FsInfo, FatFsError> {
+        Self::parse(bytes).map_err(|err| FatFsError::from_anyhow(err, FatFsError::InvalidFsInfo))
+    }
+
+    fn parse(bytes: &[u8]) -> anyhow::Result<FsInfo> {
         let lead_sig = load_u32_le(&bytes[..4]);
 
         anyhow::ensure!(
-            lead_sig == 0x41615252,
+            lead_sig == LEAD_SIG,
             "invalid lead signature: 0x{:#08X} instead of 0x41615252",
             lead_sig
         );
@@ -19,7 +31,7 @@ impl FsInfo {
         let struct_sig = load_u32_le(&bytes[484..][..4]);
 
         anyhow::ensure!(
-            struct_sig == 0x61417272,
+            struct_sig == STRUCT_SIG,
             "invalid structural signature: 0x{:#08X} instead of 0x61417272",
             struct_sig
         );
@@ -27,7 +39,7 @@ impl FsInfo {
         let trail_sig = load_u32_le(&bytes[508..][..4]);
 
         anyhow::ensure!(
-            trail_sig == 0xAA550000,
+            trail_sig == TRAIL_SIG,
             "invalid trailing signature: 0x{:#08X} instead of 0xAA550000",
             trail_sig
         );
@@ -41,11 +53,44 @@ impl FsInfo {
         })
     }
 
+    /// serialize into a full FSInfo sector, all reserved bytes are zero
+    pub fn to_bytes(&self) -> [u8; 512] {
+        let mut bytes = [0; 512];
+
+        bytes[..4].copy_from_slice(&LEAD_SIG.to_le_bytes());
+        bytes[484..488].copy_from_slice(&STRUCT_SIG.to_le_bytes());
+        bytes[488..492].copy_from_slice(&self.free_count.to_le_bytes());
+        bytes[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        bytes[508..512].copy_from_slice(&TRAIL_SIG.to_le_bytes());
+
+        bytes
+    }
+
+    /// raw free count hint, 0xFFFFFFFF if unknown
     pub fn free_count(&self) -> u32 {
         self.free_count
     }
 
+    /// free count hint, None if unknown
+    pub fn free_count_hint(&self) -> Option<u32> {
+        (self.free_count != UNKNOWN).then_some(self.free_count)
+    }
+
+    pub fn set_free_count(&mut self, free_count: u32) {
+        self.free_count = free_count;
+    }
+
+    /// mark the free count as unknown
+    pub fn invalidate_free_count(&mut self) {
+        self.free_count = UNKNOWN;
+    }
+
+    /// hint where to start looking for a free cluster, None if unknown
     pub fn next_free(&self) -> Option<u32> {
-        Some(self.next_free)
+        (self.next_free != UNKNOWN).then_some(self.next_free)
+    }
+
+    pub fn set_next_free(&mut self, next_free: Option<u32>) {
+        self.next_free = next_free.unwrap_or(UNKNOWN);
     }
 }
@@ -0,0 +1,384 @@
+//! standalone validation of a FAT file system
+//!
+//! each validator collects all errors it finds instead of bailing at the first one
+
+use std::collections::BTreeMap;
+
+use crate::bpb::Bpb;
+use crate::dir::DirEntry;
+use crate::fat::{Fat, FatError};
+use crate::{FatFs, FatType};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BpbError {
+    #[error("number of FATs is zero")]
+    NoFats,
+    #[error("root entry count is zero")]
+    NoRootEntries,
+    #[error("root entry count {0} does not fill a whole number of sectors")]
+    MisalignedRootEntryCount(u16),
+    #[error("root entry count is {0}, but must be zero for FAT32")]
+    Fat32RootEntryCount(u16),
+    #[error("both total_sectors_16 and total_sectors_32 are zero")]
+    NoTotalSectors,
+    #[error("both total_sectors_16 ({0}) and total_sectors_32 ({1}) are set")]
+    AmbiguousTotalSectors(u16, u32),
+    #[error("FAT can only hold {capacity} entries, but needs {required}")]
+    FatTooSmall { capacity: u32, required: u32 },
+    #[error("root cluster {0} is not a valid data cluster")]
+    InvalidRootCluster(u32),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FatEntryError {
+    #[error("cluster {cluster} has invalid entry: {err}")]
+    InvalidEntry { cluster: u32, err: FatError },
+    #[error("cluster {cluster} points to free cluster {next}")]
+    PointsToFree { cluster: u32, next: u32 },
+    #[error("clusters {first} and {second} both point to cluster {next}")]
+    CrossLinked { first: u32, second: u32, next: u32 },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DirError {
+    #[error("{path}: first cluster {cluster} is not a valid data cluster")]
+    InvalidFirstCluster { path: String, cluster: u32 },
+    #[error("{path}: cluster {cluster} in chain is free")]
+    FreeClusterInChain { path: String, cluster: u32 },
+    #[error("{path}: cluster chain is broken at cluster {cluster}: {err}")]
+    BrokenChain {
+        path: String,
+        cluster: u32,
+        err: FatError,
+    },
+    #[error("{path}: cluster chain contains a cycle")]
+    CyclicChain { path: String },
+    #[error("{path}: cluster {cluster} is also used by {other_path}")]
+    SharedCluster {
+        path: String,
+        other_path: String,
+        cluster: u32,
+    },
+    #[error("{path}: file size {size} does not match chain of {n_clusters} clusters")]
+    SizeMismatch {
+        path: String,
+        size: u32,
+        n_clusters: u32,
+    },
+    #[error("{path}: directory loops back to its ancestor {ancestor}")]
+    DirectoryLoop { path: String, ancestor: String },
+    #[error("cluster {cluster} is allocated, but not used by any file or directory")]
+    OrphanCluster { cluster: u32 },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FsError {
+    #[error(transparent)]
+    Bpb(#[from] BpbError),
+    #[error(transparent)]
+    FatEntry(#[from] FatEntryError),
+    #[error(transparent)]
+    Dir(#[from] DirError),
+}
+
+/// check the BPB for inconsistencies that `Bpb::load` does not reject
+pub fn validate_bpb(bpb: &Bpb) -> Vec<BpbError> {
+    let mut errors = Vec::new();
+
+    if bpb.num_fats() == 0 {
+        errors.push(BpbError::NoFats);
+    }
+
+    match bpb.fat_type() {
+        FatType::Fat12 | FatType::Fat16 => {
+            let root_dir_bytes = 32 * bpb.root_entry_count() as u32;
+
+            if bpb.root_entry_count() == 0 {
+                errors.push(BpbError::NoRootEntries);
+            } else if !root_dir_bytes.is_multiple_of(bpb.bytes_per_sector() as u32) {
+                errors.push(BpbError::MisalignedRootEntryCount(bpb.root_entry_count()));
+            }
+        }
+        FatType::Fat32 => {
+            if bpb.root_entry_count() != 0 {
+                errors.push(BpbError::Fat32RootEntryCount(bpb.root_entry_count()));
+            }
+        }
+    }
+
+    match (bpb.total_sectors_16(), bpb.total_sectors_32()) {
+        (0, 0) => errors.push(BpbError::NoTotalSectors),
+        (0, _) | (_, 0) => {}
+        (n16, n32) => errors.push(BpbError::AmbiguousTotalSectors(n16, n32)),
+    }
+
+    let fat_len_bytes = bpb.fat_len_bytes() as u64;
+
+    let capacity = match bpb.fat_type() {
+        FatType::Fat12 => fat_len_bytes * 2 / 3,
+        FatType::Fat16 => fat_len_bytes / 2,
+        FatType::Fat32 => fat_len_bytes / 4,
+    };
+    let capacity = capacity.min(u32::MAX as u64) as u32;
+
+    // two reserved entries at the start
+    let required = bpb.count_of_clusters().saturating_add(2);
+
+    if capacity < required {
+        errors.push(BpbError::FatTooSmall { capacity, required });
+    }
+
+    if let Some(root_cluster) = bpb.root_cluster()
+        && (root_cluster < 2 || root_cluster > bpb.count_of_clusters().saturating_add(1))
+    {
+        errors.push(BpbError::InvalidRootCluster(root_cluster));
+    }
+
+    errors
+}
+
+/// check all allocated FAT entries for invalid, dangling, or cross-linked next clusters
+pub fn validate_fat_entries(fat: &Fat) -> Vec<FatEntryError> {
+    let mut errors = Vec::new();
+
+    // next cluster -> cluster pointing to it
+    let mut referenced_by: BTreeMap<u32, u32> = BTreeMap::new();
+
+    for cluster in fat.valid_clusters() {
+        if fat.is_free(cluster) {
+            continue;
+        }
+
+        match fat.get_next_cluster(cluster) {
+            Ok(Some(next)) => {
+                if fat.is_free(next) {
+                    errors.push(FatEntryError::PointsToFree { cluster, next });
+                }
+
+                if let Some(&first) = referenced_by.get(&next) {
+                    errors.push(FatEntryError::CrossLinked {
+                        first,
+                        second: cluster,
+                        next,
+                    });
+                } else {
+                    referenced_by.insert(next, cluster);
+                }
+            }
+            Ok(None) => {}
+            // cluster is marked as defective, which is perfectly valid
+            Err(FatError::DefectiveCluster) => {}
+            Err(err) => errors.push(FatEntryError::InvalidEntry { cluster, err }),
+        }
+    }
+
+    errors
+}
+
+/// walk the whole directory tree and check the cluster chain of every file and directory
+///
+/// afterwards, every allocated cluster that isn't owned by anything is reported as orphaned
+pub fn validate_dir_tree(fat_fs: &FatFs) -> Vec<DirError> {
+    struct Walker<'a> {
+        fat_fs: &'a FatFs,
+
+        // cluster -> path of the owning file or directory
+        owners: BTreeMap<u32, String>,
+
+        // first clusters of the directories we're currently in
+        ancestors: Vec<u32>,
+
+        errors: Vec<DirError>,
+    }
+
+    impl Walker<'_> {
+        /// check the chain of `dir_entry`, returns true if it is safe to descend into it
+        fn check_entry(&mut self, dir_entry: &DirEntry, path: &str) -> bool {
+            let first_cluster = dir_entry.first_cluster();
+
+            if first_cluster == 0 {
+                if dir_entry.is_file() && dir_entry.file_size() != 0 {
+                    self.errors.push(DirError::SizeMismatch {
+                        path: path.to_owned(),
+                        size: dir_entry.file_size(),
+                        n_clusters: 0,
+                    });
+                }
+
+                return false;
+            }
+
+            let fat = &self.fat_fs.fat;
+
+            if !fat.valid_clusters().contains(&first_cluster) {
+                self.errors.push(DirError::InvalidFirstCluster {
+                    path: path.to_owned(),
+                    cluster: first_cluster,
+                });
+
+                return false;
+            }
+
+            let mut n_clusters = 0;
+            let mut prev_cluster = first_cluster;
+
+            for cluster in self.fat_fs.iter_cluster_chain(first_cluster) {
+                let cur_cluster = match cluster {
+                    Ok(cluster) => cluster,
+                    Err(FatError::CyclicChain(_)) => {
+                        self.errors.push(DirError::CyclicChain {
+                            path: path.to_owned(),
+                        });
+
+                        return false;
+                    }
+                    Err(err) => {
+                        self.errors.push(DirError::BrokenChain {
+                            path: path.to_owned(),
+                            cluster: prev_cluster,
+                            err,
+                        });
+
+                        return false;
+                    }
+                };
+
+                n_clusters += 1;
+
+                if fat.is_free(cur_cluster) {
+                    self.errors.push(DirError::FreeClusterInChain {
+                        path: path.to_owned(),
+                        cluster: cur_cluster,
+                    });
+
+                    return false;
+                }
+
+                if let Some(other_path) = self.owners.get(&cur_cluster) {
+                    self.errors.push(DirError::SharedCluster {
+                        path: path.to_owned(),
+                        other_path: other_path.clone(),
+                        cluster: cur_cluster,
+                    });
+
+                    // we've already walked this chain, don't walk it again
+                    return false;
+                }
+
+                self.owners.insert(cur_cluster, path.to_owned());
+
+                prev_cluster = cur_cluster;
+            }
+
+            if dir_entry.is_file() {
+                let bytes_per_cluster = self.fat_fs.bytes_per_cluster() as u64;
+                let size = dir_entry.file_size() as u64;
+
+                if size.div_ceil(bytes_per_cluster) != n_clusters as u64 {
+                    self.errors.push(DirError::SizeMismatch {
+                        path: path.to_owned(),
+                        size: dir_entry.file_size(),
+                        n_clusters,
+                    });
+                }
+            }
+
+            true
+        }
+
+        fn walk(&mut self, iter: impl Iterator<Item = DirEntry>, dir_path: &str) {
+            for dir_entry in iter {
+                if dir_entry.is_dot_or_dotdot() {
+                    continue;
+                }
+
+                if !dir_entry.is_file() && !dir_entry.is_dir() {
+                    // volume id and the like
+                    continue;
+                }
+
+                let path = format!("{}/{}", dir_path, dir_entry.name_string());
+
+                let first_cluster = dir_entry.first_cluster();
+
+                if dir_entry.is_dir()
+                    && first_cluster != 0
+                    && self.ancestors.contains(&first_cluster)
+                {
+                    let ancestor = self.owners.get(&first_cluster).cloned().unwrap_or_default();
+
+                    self.errors.push(DirError::DirectoryLoop { path, ancestor });
+
+                    continue;
+                }
+
+                if self.check_entry(&dir_entry, &path) && dir_entry.is_dir() {
+                    let fat_fs = self.fat_fs;
+
+                    self.ancestors.push(first_cluster);
+                    self.walk(fat_fs.dir_iter(first_cluster), &path);
+                    self.ancestors.pop();
+                }
+            }
+        }
+    }
+
+    let mut walker = Walker {
+        fat_fs,
+        owners: BTreeMap::new(),
+        ancestors: fat_fs.root_cluster().into_iter().collect(),
+        errors: Vec::new(),
+    };
+
+    if let Some(root_cluster) = fat_fs.root_cluster() {
+        // FAT32 root dir lives in the data region, so it owns its clusters as well
+        for cluster in fat_fs.iter_cluster_chain(root_cluster).take_while_ok() {
+            if walker.owners.insert(cluster, "/".to_owned()).is_some() {
+                walker.errors.push(DirError::CyclicChain {
+                    path: "/".to_owned(),
+                });
+
+                break;
+            }
+        }
+    }
+
+    walker.walk(fat_fs.root_dir_iter(), "");
+
+    let fat = &fat_fs.fat;
+
+    for cluster in fat.valid_clusters() {
+        if fat.is_free(cluster) || walker.owners.contains_key(&cluster) {
+            continue;
+        }
+
+        // defective clusters are allocated, but never part of a chain
+        if let Err(FatError::DefectiveCluster) = fat.get_next_cluster(cluster) {
+            continue;
+        }
+
+        walker.errors.push(DirError::OrphanCluster { cluster });
+    }
+
+    walker.errors
+}
+
+/// run all validators
+pub fn validate_all(fat_fs: &FatFs) -> Vec<FsError> {
+    let mut errors: Vec<FsError> = Vec::new();
+
+    errors.extend(validate_bpb(&fat_fs.bpb).into_iter().map(FsError::from));
+    errors.extend(validate_fat_entries(&fat_fs.fat).into_iter().map(FsError::from));
+    errors.extend(validate_dir_tree(fat_fs).into_iter().map(FsError::from));
+
+    errors
+}
+
+impl FatFs {
+    /// check the whole FS for inconsistencies, see `validate_all`
+    ///
+    /// returns an empty Vec for a consistent FS
+    pub fn check_integrity(&self) -> Vec<FsError> {
+        validate_all(self)
+    }
+}
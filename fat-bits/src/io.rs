@@ -0,0 +1,82 @@
+//! the byte-level I/O the on-disk structures are read from and written to
+//!
+//! with the `std` feature these are implemented for everything that implements `std::io::Read`
+//! and `std::io::Write`, without it only for in-memory buffers
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+pub type IoError = std::io::Error;
+
+#[cfg(not(feature = "std"))]
+#[derive(Debug, thiserror::Error)]
+pub enum IoError {
+    #[error("failed to fill whole buffer")]
+    UnexpectedEof,
+    #[error("failed to write whole buffer")]
+    WriteZero,
+}
+
+pub trait ReadBytes {
+    /// fill all of `buf`, fails if there are fewer bytes left
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), IoError>;
+}
+
+pub trait WriteBytes {
+    /// write all of `buf`, fails if there is less space left
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), IoError>;
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> ReadBytes for R {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        self.read_exact(buf)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> WriteBytes for W {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        self.write_all(buf)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl ReadBytes for &[u8] {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        let Some((head, tail)) = self.split_at_checked(buf.len()) else {
+            return Err(IoError::UnexpectedEof);
+        };
+
+        buf.copy_from_slice(head);
+        *self = tail;
+
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl WriteBytes for &mut [u8] {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        if self.len() < buf.len() {
+            return Err(IoError::WriteZero);
+        }
+
+        let (head, tail) = core::mem::take(self).split_at_mut(buf.len());
+
+        head.copy_from_slice(buf);
+        *self = tail;
+
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl WriteBytes for Vec<u8> {
+    fn write_bytes(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        self.extend_from_slice(buf);
+
+        Ok(())
+    }
+}
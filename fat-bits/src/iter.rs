@@ -11,21 +11,35 @@ pub struct ClusterChainReader<'a> {
     sub_slice: SubSlice,
 
     next_cluster: Option<u32>,
+
+    bytes_consumed: u64,
 }
 
 impl<'a> ClusterChainReader<'a> {
     pub fn new(fat_fs: &'a FatFs, first_cluster: u32) -> Self {
-        let next_cluster = fat_fs.next_cluster(first_cluster).unwrap_or(None);
+        let (sub_slice, next_cluster) = match fat_fs.cluster_as_subslice(first_cluster) {
+            Ok(sub_slice) => (sub_slice, fat_fs.next_cluster(first_cluster).unwrap_or(None)),
+            Err(err) => {
+                debug!("can't read chain starting at cluster {first_cluster}: {err}");
 
-        let sub_slice = fat_fs.cluster_as_subslice(first_cluster);
+                // treat the chain as empty
+                (fat_fs.empty_subslice(), None)
+            }
+        };
 
         ClusterChainReader {
             fat_fs,
             sub_slice,
             next_cluster,
+            bytes_consumed: 0,
         }
     }
 
+    /// the FS this reader reads from
+    pub fn fat_fs(&self) -> &'a FatFs {
+        self.fat_fs
+    }
+
     pub fn root_dir_reader(fat_fs: &'a FatFs) -> Self {
         match fat_fs.fat_type() {
             FatType::Fat12 | FatType::Fat16 => {
@@ -38,12 +52,13 @@ impl<'a> ClusterChainReader<'a> {
                     fat_fs,
                     sub_slice,
                     next_cluster: None,
+                    bytes_consumed: 0,
                 }
             }
             FatType::Fat32 => {
                 // FAT is directory_like, so get a real chain reader
 
-                Self::new(fat_fs, fat_fs.bpb.root_cluster().unwrap())
+                Self::new(fat_fs, fat_fs.root_cluster().unwrap())
             }
         }
     }
@@ -53,8 +68,19 @@ impl<'a> ClusterChainReader<'a> {
             return false;
         };
 
+        let sub_slice = match self.fat_fs.cluster_as_subslice(next_cluster) {
+            Ok(sub_slice) => sub_slice,
+            Err(err) => {
+                debug!("can't move to cluster {next_cluster}: {err}");
+
+                self.next_cluster = None;
+
+                return false;
+            }
+        };
+
         self.next_cluster = self.fat_fs.next_cluster(next_cluster).unwrap_or(None);
-        self.sub_slice = self.fat_fs.cluster_as_subslice(next_cluster);
+        self.sub_slice = sub_slice;
 
         true
     }
@@ -64,6 +90,8 @@ impl<'a> ClusterChainReader<'a> {
 
         while bytes_to_skip > self.sub_slice.len() as u64 {
             bytes_to_skip -= self.sub_slice.len() as u64;
+            self.bytes_consumed += self.sub_slice.len() as u64;
+
             if !self.move_to_next_cluster() {
                 // ran out of bytes to seek
                 return n - bytes_to_skip;
@@ -71,29 +99,49 @@ impl<'a> ClusterChainReader<'a> {
         }
 
         if bytes_to_skip != 0 {
-            bytes_to_skip -= self.sub_slice.skip(bytes_to_skip as usize) as u64;
+            let skipped = self.sub_slice.skip(bytes_to_skip as usize) as u64;
+
+            bytes_to_skip -= skipped;
+            self.bytes_consumed += skipped;
         }
 
         // n should absolutely be zero here
         assert_eq!(bytes_to_skip, 0);
 
+        if self.sub_slice.is_empty() {
+            self.move_to_next_cluster();
+        }
+
         n
     }
 
+    /// byte offset of the next byte to be read in the underlying data
     pub fn current_offset(&self) -> u64 {
         self.sub_slice.offset()
     }
+
+    /// number of bytes consumed (read or skipped) since the start of the chain
+    pub fn position(&self) -> u64 {
+        self.bytes_consumed
+    }
 }
 
 impl Read for ClusterChainReader<'_> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.sub_slice.is_empty() && !self.move_to_next_cluster() {
+            return Ok(0);
+        }
+
+        let n = self.sub_slice.read(buf)?;
+
+        self.bytes_consumed += n as u64;
+
         if self.sub_slice.is_empty() {
-            if !self.move_to_next_cluster() {
-                return Ok(0);
-            }
+            // move on eagerly, so current_offset always points at the next byte to be read
+            self.move_to_next_cluster();
         }
 
-        self.sub_slice.read(buf)
+        Ok(n)
     }
 }
 
@@ -104,18 +152,27 @@ pub struct ClusterChainWriter<'a> {
 
     // next_cluster: Option<u32>,
     cur_cluster: u32,
+
+    // the FAT has changed and needs to be written back
+    fat_dirty: bool,
 }
 
 impl<'a> ClusterChainWriter<'a> {
     pub fn new(fat_fs: &'a mut FatFs, first_cluster: u32) -> Self {
         // let next_cluster = fat_fs.next_cluster(first_cluster).unwrap_or(None);
 
-        let sub_slice = fat_fs.cluster_as_subslice_mut(first_cluster);
+        let sub_slice = fat_fs.cluster_as_subslice_mut(first_cluster).unwrap_or_else(|err| {
+            debug!("can't write chain starting at cluster {first_cluster}: {err}");
+
+            // treat the chain as empty
+            fat_fs.empty_subslice_mut()
+        });
 
         ClusterChainWriter {
             fat_fs,
             sub_slice,
             cur_cluster: first_cluster,
+            fat_dirty: false,
         }
     }
 
@@ -131,26 +188,22 @@ impl<'a> ClusterChainWriter<'a> {
                     fat_fs,
                     sub_slice,
                     cur_cluster: 0,
+                    fat_dirty: false,
                 }
             }
             FatType::Fat32 => {
                 // FAT is directory_like, so get a real chain writer
 
-                Self::new(fat_fs, fat_fs.bpb.root_cluster().unwrap())
+                Self::new(fat_fs, fat_fs.root_cluster().unwrap())
             }
         }
     }
 
     fn move_to_next_cluster(&mut self) -> bool {
-        // TODO: should allocate a new cluster here!
-        // let Some(next_cluster) = self.next_cluster else {
-        //     let Some(new_cluster) = self.fat_fs.alloc_cluster() else {
-        //         // cluster allocation failed
-        //         return false;
-        //     };
-
-        //     return false;
-        // };
+        if self.cur_cluster == 0 {
+            // fixed root dir or empty chain, there is nothing to extend
+            return false;
+        }
 
         let Some(next_cluster) = self
             .fat_fs
@@ -163,7 +216,11 @@ impl<'a> ClusterChainWriter<'a> {
             .or_else(|| {
                 debug!("allocating new cluster");
 
-                self.fat_fs.alloc_cluster(Some(self.cur_cluster))
+                let new_cluster = self.fat_fs.alloc_cluster(Some(self.cur_cluster))?;
+
+                self.fat_dirty = true;
+
+                Some(new_cluster)
             })
         else {
             debug!("failed to allocate next cluster");
@@ -173,7 +230,16 @@ impl<'a> ClusterChainWriter<'a> {
 
         debug!("next cluster: {next_cluster}");
 
-        self.fat_fs.cluster_as_subslice_mut(next_cluster);
+        let sub_slice = match self.fat_fs.cluster_as_subslice_mut(next_cluster) {
+            Ok(sub_slice) => sub_slice,
+            Err(err) => {
+                debug!("can't move to cluster {next_cluster}: {err}");
+
+                return false;
+            }
+        };
+
+        self.sub_slice = sub_slice;
         self.cur_cluster = next_cluster;
 
         true
@@ -203,20 +269,32 @@ impl<'a> ClusterChainWriter<'a> {
     pub fn current_offset(&self) -> u64 {
         self.sub_slice.offset()
     }
+
+    /// write back the FAT if clusters were allocated while writing
+    ///
+    /// nothing is written back implicitly, so this has to be called after writing past the end
+    /// of the chain
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.flush()
+    }
 }
 
 impl Write for ClusterChainWriter<'_> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        if self.sub_slice.is_empty() {
-            if !(self.move_to_next_cluster()) {
-                return Ok(0);
-            }
+        if self.sub_slice.is_empty() && !self.move_to_next_cluster() {
+            return Ok(0);
         }
 
         self.sub_slice.write(buf)
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
+        if self.fat_dirty {
+            self.fat_fs.flush_fat()?;
+
+            self.fat_dirty = false;
+        }
+
         Ok(())
     }
 }
@@ -1,22 +1,67 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(not(feature = "std"))]
+use alloc::format;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(feature = "std")]
 use std::cell::RefCell;
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet, VecDeque};
+#[cfg(feature = "std")]
 use std::fmt::Display;
+#[cfg(feature = "std")]
+use std::io::{Read as _, Write as _};
+#[cfg(feature = "std")]
 use std::rc::Rc;
-
-use log::debug;
-
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+#[cfg(feature = "std")]
+use chrono::{DateTime, Local};
+#[cfg(feature = "std")]
+use compact_str::CompactString;
+#[cfg(feature = "std")]
+use log::{debug, warn};
+
+#[cfg(feature = "std")]
+use crate::datetime::{Date, Time};
+#[cfg(feature = "std")]
 use crate::dir::DirIter;
+pub use crate::dir::{Attr, DirEntry};
+#[cfg(feature = "std")]
 use crate::fat::FatError;
+#[cfg(feature = "std")]
+pub use crate::format::FormatParams;
+pub use crate::io::{IoError, ReadBytes, WriteBytes};
+#[cfg(feature = "std")]
 use crate::iter::ClusterChainReader;
+#[cfg(feature = "tokio")]
+pub use crate::slice_like::AsyncSliceLike;
+#[cfg(feature = "std")]
 pub use crate::slice_like::SliceLike;
+#[cfg(feature = "std")]
 use crate::subslice::{SubSlice, SubSliceMut};
 
 pub mod bpb;
+#[cfg(feature = "oem_cp437")]
+mod codepage;
 mod datetime;
 pub mod dir;
 pub mod fat;
+#[cfg(feature = "std")]
+mod format;
 pub mod fs_info;
+#[cfg(feature = "std")]
+pub mod integrity;
+mod io;
+#[cfg(feature = "std")]
 pub mod iter;
+#[cfg(feature = "std")]
 mod slice_like;
+#[cfg(feature = "std")]
 mod subslice;
 mod utils;
 
@@ -27,6 +72,107 @@ pub enum FatType {
     Fat32,
 }
 
+impl FatType {
+    /// size of a single FAT entry in bytes
+    pub fn entry_bytes(&self) -> f32 {
+        match self {
+            FatType::Fat12 => 1.5,
+            FatType::Fat16 => 2.0,
+            FatType::Fat32 => 4.0,
+        }
+    }
+
+    /// number of FAT entries in a sector of `bytes_per_sector` bytes
+    ///
+    /// FAT12 entries can straddle sector boundaries, in that case this counts an entry that
+    /// starts in the sector
+    pub fn clusters_per_sector(&self, bytes_per_sector: u16) -> u32 {
+        let bytes_per_sector = bytes_per_sector as u32;
+
+        match self {
+            FatType::Fat12 => (bytes_per_sector * 2).div_ceil(3),
+            FatType::Fat16 => bytes_per_sector / 2,
+            FatType::Fat32 => bytes_per_sector / 4,
+        }
+    }
+}
+
+/// errors returned when loading an FS or parsing its on-disk structures
+///
+/// since 0.2 the loading functions return this instead of an `anyhow::Error`, so callers can tell
+/// e.g. a missing boot signature apart from an I/O error
+#[derive(Debug, thiserror::Error)]
+pub enum FatFsError {
+    #[error(
+        "not a FAT file system: boot sector signature is [{:#X}, {:#X}] instead of [0x55, 0xAA]",
+        .found[0],
+        .found[1]
+    )]
+    NotAFatFilesystem { found: [u8; 2] },
+    #[error("image is too small: needs at least {required} bytes, but is only {actual} bytes")]
+    ImageTooSmall { required: u64, actual: u64 },
+    #[error("I/O error: {0}")]
+    Io(#[from] IoError),
+    #[error("invalid BPB: {0}")]
+    InvalidBpb(String),
+    #[error("invalid FAT: {0}")]
+    InvalidFat(String),
+    #[error("invalid dir entry: {0}")]
+    InvalidDirEntry(String),
+    #[error("invalid FSInfo: {0}")]
+    InvalidFsInfo(String),
+    #[error(transparent)]
+    FatCopyMismatch(#[from] FatCopyMismatch),
+}
+
+impl FatFsError {
+    /// turn an error from the anyhow-based internals into a FatFsError
+    ///
+    /// FatFsErrors and I/O errors are passed through, everything else gets its message wrapped
+    /// by `wrap`
+    pub(crate) fn from_anyhow(err: anyhow::Error, wrap: fn(String) -> FatFsError) -> FatFsError {
+        let err = match err.downcast::<FatFsError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+
+        match err.downcast::<IoError>() {
+            Ok(err) => FatFsError::Io(err),
+            Err(err) => wrap(format!("{err:#}")),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FatCopyMismatch {
+    #[error("FAT copy {copy} differs from the first FAT at entry {cluster}")]
+    Differs { copy: u8, cluster: u32 },
+    #[error("failed to read FAT copy {copy}: {err}")]
+    Unreadable { copy: u8, err: IoError },
+}
+
+/// how `FatFs::load_with_fat_check` treats FAT copies that differ from the first FAT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FatCopyCheck {
+    /// fail to load
+    Strict,
+    /// log a warning and use the first FAT
+    #[default]
+    Warn,
+    /// don't even read the other copies
+    Ignore,
+}
+
+/// what `FatFs::walk` should do after visiting an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkControl {
+    Continue,
+    /// don't descend into this directory, has no effect for files
+    SkipSubtree,
+    Stop,
+}
+
+#[cfg(feature = "std")]
 pub struct FatFs {
     inner: Rc<RefCell<dyn SliceLike>>,
 
@@ -35,7 +181,7 @@ pub struct FatFs {
     root_dir_offset: Option<u64>,
     root_dir_size: usize,
 
-    pub data_offset: u64,
+    data_offset: u64,
     // data_size: usize,
     bytes_per_cluster: usize,
 
@@ -45,40 +191,81 @@ pub struct FatFs {
 
     next_free: Option<u32>,
     free_count: u32,
+
+    fs_info: Option<fs_info::FsInfo>,
+
+    // match names exactly instead of ignoring case
+    case_sensitive: bool,
+
+    // first cluster -> number of clusters in the chain, cleared whenever the FAT changes
+    chain_lengths: RefCell<HashMap<u32, u32>>,
 }
 
+#[cfg(feature = "std")]
 impl Display for FatFs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "{}", self.bpb)?;
-        writeln!(f, "")?;
+        writeln!(f)?;
         writeln!(f, "{}", self.fat)?;
 
         Ok(())
     }
 }
 
+#[cfg(feature = "std")]
 unsafe impl Send for FatFs {}
 
+#[cfg(feature = "std")]
 impl Drop for FatFs {
     fn drop(&mut self) {
-        let fat_slice = SubSliceMut::new(
-            Rc::clone(&self.inner),
-            self.bpb.fat_offset(),
-            self.bpb.fat_len_bytes(),
-        );
-
-        if let Err(err) = self.fat.write_back(fat_slice) {
+        if let Err(err) = self.flush_fat() {
             debug!("writing FAT back to disk failed: {err}");
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl FatFs {
-    pub fn load<S>(data: S) -> anyhow::Result<FatFs>
+    /// load the FS, warning about FAT copies that differ from the first FAT
+    pub fn load<S>(data: S) -> Result<FatFs, FatFsError>
+    where
+        S: SliceLike + Send + 'static,
+    {
+        Self::load_with_fat_check(data, FatCopyCheck::default())
+    }
+
+    /// load a FAT file system from async storage, e.g. a `tokio::fs::File`
+    ///
+    /// all other I/O is synchronous, so the whole volume is read into memory up front and then
+    /// parsed just like by `load`. the returned FS is read-only: changes could never be written
+    /// back to `storage`, so every write fails with `ErrorKind::ReadOnlyFilesystem`
+    #[cfg(feature = "tokio")]
+    pub async fn load_async<S>(mut storage: S) -> Result<FatFs, FatFsError>
+    where
+        S: AsyncSliceLike,
+    {
+        let mut bpb_bytes = [0; 512];
+
+        storage.read_at_offset(0, &mut bpb_bytes).await?;
+
+        // bail out before reading a whole image that isn't a FAT file system
+        let bpb = bpb::Bpb::load(&bpb_bytes)?;
+
+        let image_len = storage.byte_len().await?;
+        let volume_len = bpb.total_sectors() as u64 * bpb.bytes_per_sector() as u64;
+
+        let mut data = vec![0; volume_len.min(image_len) as usize];
+
+        storage.read_at_offset(0, &mut data).await?;
+
+        Self::load(slice_like::AsyncSnapshot(data))
+    }
+
+    pub fn load_with_fat_check<S>(data: S, fat_check: FatCopyCheck) -> Result<FatFs, FatFsError>
     where
         S: SliceLike + Send + 'static,
     {
-        let data = Rc::new(RefCell::new(data));
+        let data: Rc<RefCell<dyn SliceLike>> = Rc::new(RefCell::new(data));
 
         let mut bpb_bytes = [0; 512];
 
@@ -86,10 +273,30 @@ impl FatFs {
 
         let bpb = bpb::Bpb::load(&bpb_bytes)?;
 
+        let image_len = data.borrow().byte_len()?;
+        let fat_end = bpb.fat_offset() + bpb.fat_len_bytes() as u64;
+
+        if image_len < fat_end {
+            return Err(FatFsError::ImageTooSmall {
+                required: fat_end,
+                actual: image_len,
+            });
+        }
+
         let mut fat_buf = vec![0; bpb.fat_len_bytes()];
 
-        data.borrow_mut()
-            .read_at_offset(bpb.fat_offset(), &mut fat_buf)?;
+        data.borrow_mut().read_at_offset(bpb.fat_offset(), &mut fat_buf)?;
+
+        // the other copies are only checked, all reads go through the in-memory copy of the first
+        match fat_check {
+            FatCopyCheck::Strict => check_fat_copies(&data, &bpb, &fat_buf)?,
+            FatCopyCheck::Warn => {
+                if let Err(err) = check_fat_copies(&data, &bpb, &fat_buf) {
+                    warn!("{err}, using the first FAT");
+                }
+            }
+            FatCopyCheck::Ignore => {}
+        }
 
         let fat = fat::Fat::new(bpb.fat_type(), &fat_buf, bpb.count_of_clusters());
 
@@ -122,7 +329,7 @@ impl FatFs {
         let next_free = fat.first_free_cluster();
         let free_count = fat.count_free_clusters();
 
-        Ok(FatFs {
+        let mut fat_fs = FatFs {
             inner: data,
             // fat_offset,
             // fat_size,
@@ -135,20 +342,265 @@ impl FatFs {
             fat,
             next_free,
             free_count,
-        })
+            fs_info: None,
+            case_sensitive: false,
+            chain_lengths: RefCell::new(HashMap::new()),
+        };
+
+        fat_fs
+            .load_fs_info()
+            .map_err(|err| FatFsError::from_anyhow(err, FatFsError::InvalidFsInfo))?;
+
+        Ok(fat_fs)
+    }
+
+    /// (re-)read the FSInfo sector
+    ///
+    /// leaves the FS without FSInfo if there is none or it is invalid. the free count hint is
+    /// invalidated if it doesn't match the actual number of free clusters in the FAT
+    pub fn load_fs_info(&mut self) -> anyhow::Result<()> {
+        self.fs_info = None;
+
+        // 0 and 0xFFFF both mean there is no FSInfo, otherwise it must lie in the reserved region
+        let sector = match self.bpb.fs_info_sector() {
+            Some(0) | Some(0xFFFF) | None => return Ok(()),
+            Some(sector) if sector >= self.bpb.reserved_sector_count() => {
+                debug!("FSInfo sector {sector} is outside of the reserved region, ignoring it");
+
+                return Ok(());
+            }
+            Some(sector) => sector,
+        };
+
+        let mut fs_info_bytes = [0; 512];
+
+        self.inner
+            .borrow_mut()
+            .read_at_offset(self.bpb.sector_to_offset(sector as u32), &mut fs_info_bytes)?;
+
+        let mut fs_info = match fs_info::FsInfo::load(&fs_info_bytes) {
+            Ok(fs_info) => fs_info,
+            Err(err) => {
+                debug!("ignoring invalid FSInfo sector: {err}");
+
+                return Ok(());
+            }
+        };
+
+        if let Some(free_count) = fs_info.free_count_hint()
+            && free_count != self.free_count
+        {
+            debug!(
+                "FSInfo free count {free_count} doesn't match the {} free clusters in the FAT",
+                self.free_count
+            );
+
+            fs_info.invalidate_free_count();
+        }
+
+        self.fs_info = Some(fs_info);
+
+        Ok(())
+    }
+
+    /// whether names are looked up case-sensitively, false by default
+    pub fn case_sensitive(&self) -> bool {
+        self.case_sensitive
+    }
+
+    pub fn set_case_sensitive(&mut self, case_sensitive: bool) {
+        self.case_sensitive = case_sensitive;
+    }
+
+    /// the FSInfo structure, if the FS has a valid one
+    pub fn fs_info(&self) -> Option<&fs_info::FsInfo> {
+        self.fs_info.as_ref()
     }
 
+    /// the boot sector the FS was loaded from
+    pub fn bpb(&self) -> &bpb::Bpb {
+        &self.bpb
+    }
+
+    /// the in-memory copy of the FAT
+    pub fn fat(&self) -> &fat::Fat {
+        &self.fat
+    }
+
+    /// whether this is a FAT12, FAT16, or FAT32 FS
     pub fn fat_type(&self) -> FatType {
         self.fat.fat_type()
     }
 
-    /// byte offset of data cluster
-    fn data_cluster_to_offset(&self, cluster: u32) -> u64 {
-        // assert!(cluster >= 2);
+    /// write the in-memory FAT back to disk
+    ///
+    /// updates all FAT copies, one after the other
+    pub fn flush_fat(&self) -> std::io::Result<()> {
+        for n in 0..self.bpb.num_fats() {
+            let fat_slice = SubSliceMut::new(
+                Rc::clone(&self.inner),
+                self.bpb.nth_fat_offset(n),
+                self.bpb.fat_len_bytes(),
+            );
+
+            self.fat.write_back(fat_slice)?;
+        }
+
+        Ok(())
+    }
+
+    /// update free count and next free cluster in the FSInfo sector
+    ///
+    /// does nothing if the FS has no (valid) FSInfo sector
+    pub fn flush_fs_info(&mut self) -> std::io::Result<()> {
+        let Some(fs_info) = self.fs_info.as_mut() else {
+            return Ok(());
+        };
+
+        let Some(fs_info_sector) = self.bpb.fs_info_sector() else {
+            return Ok(());
+        };
+
+        fs_info.set_free_count(self.free_count);
+        fs_info.set_next_free(self.next_free);
+
+        let offset = self.bpb.sector_to_offset(fs_info_sector as u32);
+
+        self.inner.borrow_mut().write_at_offset(offset, &fs_info.to_bytes())
+    }
+
+    /// bring the FSInfo sector up to date after clusters were allocated or freed
+    fn update_fs_info(&mut self) {
+        if let Err(err) = self.flush_fs_info() {
+            debug!("failed to update FSInfo: {err}");
+        }
+    }
+
+    /// flush the underlying data to its storage
+    pub fn flush_device(&self) -> std::io::Result<()> {
+        self.inner.borrow_mut().flush()
+    }
+
+    /// write the FAT and FSInfo back and flush everything to the underlying storage
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.flush_fat()?;
+        self.flush_fs_info()?;
+        self.flush_device()?;
+
+        Ok(())
+    }
+
+    /// grow or shrink the file system and the underlying data to `new_size_bytes`
+    ///
+    /// the FATs keep their size, so the new cluster count has to fit into them and must not
+    /// change the FAT type. shrinking fails if any of the removed clusters is in use
+    pub fn resize(&mut self, new_size_bytes: u64) -> anyhow::Result<()> {
+        let current_size = self.inner.borrow().byte_len()?;
+
+        anyhow::ensure!(
+            new_size_bytes != current_size,
+            "image already is {} bytes large",
+            current_size
+        );
+
+        anyhow::ensure!(
+            new_size_bytes > self.data_offset,
+            "new size {} doesn't leave any space for data (data region starts at {})",
+            new_size_bytes,
+            self.data_offset
+        );
+
+        let total_sectors = u32::try_from(new_size_bytes / self.bpb.bytes_per_sector() as u64)
+            .map_err(|_| anyhow::anyhow!("new size {} is too large", new_size_bytes))?;
+
+        let mut bpb_bytes = [0; 512];
+
+        self.inner.borrow_mut().read_at_offset(0, &mut bpb_bytes)?;
+
+        if self.bpb.total_sectors_16() != 0 && total_sectors <= u16::MAX as u32 {
+            bpb_bytes[19..21].copy_from_slice(&(total_sectors as u16).to_le_bytes());
+            bpb_bytes[32..36].copy_from_slice(&0u32.to_le_bytes());
+        } else {
+            bpb_bytes[19..21].copy_from_slice(&0u16.to_le_bytes());
+            bpb_bytes[32..36].copy_from_slice(&total_sectors.to_le_bytes());
+        }
+
+        let new_bpb = bpb::Bpb::load(&bpb_bytes)
+            .map_err(|e| anyhow::anyhow!("can't resize to {} bytes: {}", new_size_bytes, e))?;
+
+        anyhow::ensure!(
+            new_bpb.fat_type() == self.bpb.fat_type(),
+            "resizing to {} bytes would turn {:?} into {:?}",
+            new_size_bytes,
+            self.bpb.fat_type(),
+            new_bpb.fat_type()
+        );
+
+        if let Some(err) = integrity::validate_bpb(&new_bpb)
+            .into_iter()
+            .find(|err| matches!(err, integrity::BpbError::FatTooSmall { .. }))
+        {
+            anyhow::bail!("can't resize to {} bytes: {}", new_size_bytes, err);
+        }
+
+        let new_max = new_bpb.count_of_clusters();
+
+        if let Some(cluster) = self
+            .fat
+            .valid_clusters()
+            .find(|&cluster| cluster > new_max && !self.fat.is_free(cluster))
+        {
+            anyhow::bail!("cluster {} is in use, but would be removed", cluster);
+        }
+
+        // grow before and shrink after updating the FS, so the image never ends before the FS
+        // does
+        if new_size_bytes > current_size {
+            self.inner.borrow_mut().resize(new_size_bytes)?;
+        }
+
+        self.flush_fat()?;
+
+        self.inner.borrow_mut().write_at_offset(0, &bpb_bytes)?;
+
+        if let Some(backup_sector) = new_bpb.backup_boot_sector() {
+            self.inner
+                .borrow_mut()
+                .write_at_offset(new_bpb.sector_to_offset(backup_sector as u32), &bpb_bytes)?;
+        }
+
+        let mut fat_buf = vec![0; new_bpb.fat_len_bytes()];
+
+        self.inner
+            .borrow_mut()
+            .read_at_offset(new_bpb.fat_offset(), &mut fat_buf)?;
+
+        self.fat = fat::Fat::new(new_bpb.fat_type(), &fat_buf, new_max);
+        self.bpb = new_bpb;
+
+        self.chain_lengths.get_mut().clear();
+
+        self.next_free = self.fat.first_free_cluster();
+        self.free_count = self.fat.count_free_clusters();
+
+        if new_size_bytes < current_size {
+            self.inner.borrow_mut().resize(new_size_bytes)?;
+        }
+
+        self.flush_fs_info()?;
+
+        Ok(())
+    }
 
-        // assert!(self.fat.valid_entries().contains(&cluster));
+    /// byte offset of data cluster
+    ///
+    /// returns an error if `cluster` is not a valid data cluster
+    fn data_cluster_to_offset(&self, cluster: u32) -> Result<u64, FatError> {
+        if !self.fat.valid_clusters().contains(&cluster) {
+            return Err(FatError::InvalidEntry(cluster));
+        }
 
-        self.data_offset + (cluster - 2) as u64 * self.bytes_per_cluster as u64
+        Ok(self.data_offset + (cluster - 2) as u64 * self.bytes_per_cluster as u64)
     }
 
     pub fn free_clusters(&self) -> u32 {
@@ -156,20 +608,55 @@ impl FatFs {
         self.free_count
     }
 
+    /// byte size of all data clusters, i.e. the capacity available to files and directories
+    pub fn total_space_bytes(&self) -> u64 {
+        self.cluster_count() as u64 * self.bytes_per_cluster as u64
+    }
+
+    /// byte size of all free data clusters
+    ///
+    /// uses the FSInfo free count if there is a valid one
+    pub fn free_space_bytes(&self) -> u64 {
+        let free_clusters = self
+            .fs_info
+            .as_ref()
+            .and_then(|fs_info| fs_info.free_count_hint())
+            .unwrap_or(self.free_count);
+
+        free_clusters as u64 * self.bytes_per_cluster as u64
+    }
+
+    /// byte size of all allocated (or defective) data clusters
+    pub fn used_space_bytes(&self) -> u64 {
+        self.total_space_bytes() - self.free_space_bytes()
+    }
+
     pub fn alloc_cluster(&mut self, prev_cluster: Option<u32>) -> Option<u32> {
-        let Some(new_cluster) = self.next_free else {
-            // no free cluster
-            return None;
+        let new_cluster = match prev_cluster {
+            Some(prev_cluster) => match self.fat.allocate_cluster(prev_cluster) {
+                Ok(new_cluster) => new_cluster,
+                Err(err) => {
+                    debug!("failed to allocate cluster after {prev_cluster}: {err}");
+
+                    return None;
+                }
+            },
+            None => {
+                let Some(new_cluster) = self.next_free else {
+                    // no free cluster
+                    return None;
+                };
+
+                // set cluster as EOF
+                self.fat.set_next_cluster(new_cluster, None);
+
+                new_cluster
+            }
         };
 
-        debug!("next free cluster: {new_cluster}");
-
-        // set cluster as EOF
-        self.fat.set_next_cluster(new_cluster, None);
+        debug!("allocated cluster {new_cluster}");
 
-        if let Some(prev_cluster) = prev_cluster {
-            self.fat.set_next_cluster(prev_cluster, Some(new_cluster));
-        }
+        self.chain_lengths.get_mut().clear();
 
         // something went terribly wrong
         assert_ne!(self.free_count, 0);
@@ -179,6 +666,8 @@ impl FatFs {
         // find next free cluster
         self.next_free = self.fat.first_free_cluster();
 
+        self.update_fs_info();
+
         Some(new_cluster)
     }
 
@@ -186,99 +675,3249 @@ impl FatFs {
         // assert cluster is actually valid
         self.fat.free_cluster(cluster);
 
+        self.chain_lengths.get_mut().clear();
+
         if self.next_free.is_none() || self.next_free.unwrap() > cluster {
             self.next_free = Some(cluster);
         }
 
         self.free_count += 1;
-    }
 
-    pub fn bytes_per_sector(&self) -> u16 {
-        self.bpb.bytes_per_sector()
+        self.update_fs_info();
     }
 
-    pub fn sectors_per_cluster(&self) -> u8 {
-        self.bpb.sectors_per_cluster()
+    /// allocate `n` clusters as a single chain and return its first cluster
+    ///
+    /// the chain gets appended to `after`, which must be the last cluster of its chain. free
+    /// clusters are searched next-fit, starting at the next free hint, which afterwards points
+    /// past the new chain. only changes the FAT in memory
+    pub fn allocate_clusters(&mut self, n: u32, after: Option<u32>) -> Result<u32, FatError> {
+        if n == 0 {
+            return Err(FatError::EmptyChain);
+        }
+
+        if let Some(after) = after
+            && (!self.fat.valid_clusters().contains(&after)
+                || self.fat.is_free(after)
+                || self.fat.get_next_cluster(after)?.is_some())
+        {
+            return Err(FatError::InvalidEntry(after));
+        }
+
+        if n > self.free_count {
+            return Err(FatError::NoFreeCluster);
+        }
+
+        let valid_clusters = self.fat.valid_clusters();
+        let (min, max) = (*valid_clusters.start(), *valid_clusters.end());
+
+        let start = self.next_free.unwrap_or(min);
+
+        let clusters: Vec<u32> = (start..=max)
+            .chain(min..start)
+            .filter(|&cluster| self.fat.is_free(cluster))
+            .take(n as usize)
+            .collect();
+
+        if clusters.len() < n as usize {
+            return Err(FatError::NoFreeCluster);
+        }
+
+        // link back to front, so no cluster points to a free one at any time
+        let mut next_cluster = None;
+
+        for &cluster in clusters.iter().rev() {
+            self.fat.set_next_cluster(cluster, next_cluster);
+            next_cluster = Some(cluster);
+        }
+
+        if let Some(after) = after {
+            self.fat.set_next_cluster(after, Some(clusters[0]));
+        }
+
+        debug!("allocated {n} clusters starting at {}", clusters[0]);
+
+        self.chain_lengths.get_mut().clear();
+
+        self.free_count -= n;
+
+        let last = *clusters.last().unwrap();
+
+        self.next_free = (last + 1..=max)
+            .chain(min..=last)
+            .find(|&cluster| self.fat.is_free(cluster));
+
+        self.update_fs_info();
+
+        Ok(clusters[0])
     }
 
-    pub fn root_cluster(&self) -> Option<u32> {
-        self.bpb.root_cluster()
+    /// run `f`, undoing all its changes to the in-memory FAT if it fails
+    ///
+    /// data already written to disk by `f` is not rolled back. transactions can be nested, a
+    /// failing inner transaction only undoes its own changes
+    pub fn in_transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut FatFs) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let next_free = self.next_free;
+        let free_count = self.free_count;
+
+        let mark = self.fat.start_journal();
+
+        let res = f(self);
+
+        if res.is_ok() {
+            self.fat.commit_journal(mark);
+        } else {
+            debug!("transaction failed, rolling back FAT changes");
+
+            self.fat.rollback_journal(mark);
+            self.chain_lengths.get_mut().clear();
+
+            self.next_free = next_free;
+            self.free_count = free_count;
+
+            self.update_fs_info();
+        }
+
+        res
     }
 
-    /// next data cluster or None is cluster is EOF
+    /// free `first_cluster` and all clusters following it
     ///
-    /// giving an invalid cluster (free, reserved, or defective) returns an appropriate error
-    pub fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, FatError> {
-        self.fat.get_next_cluster(cluster)
+    /// only changes the FAT in memory, see `free_cluster_chain`
+    pub fn dealloc_chain(&mut self, first_cluster: u32) -> Result<(), FatError> {
+        let n_clusters = self.fat.free_cluster_chain(first_cluster)?;
+
+        self.chain_lengths.get_mut().clear();
+
+        self.free_count += n_clusters as u32;
+        self.next_free = self.fat.first_free_cluster();
+
+        self.update_fs_info();
+
+        Ok(())
     }
 
-    pub fn cluster_as_subslice(&self, cluster: u32) -> SubSlice {
-        if cluster == 0 {
-            // for cluster 0 simply return empty subslice
-            // this makes things a bit easier, since cluster 0 is used as a marker that a file/dir
-            // is empty
+    /// free the chain starting at `first_cluster` and write the FAT and FSInfo back
+    ///
+    /// returns the number of freed clusters
+    pub fn free_cluster_chain(&mut self, first_cluster: u32) -> anyhow::Result<usize> {
+        let free_count = self.free_count;
 
-            return SubSlice::new(self.inner.clone(), 0, 0);
-        }
+        self.dealloc_chain(first_cluster)?;
 
-        let offset = self.data_cluster_to_offset(cluster);
+        self.flush_fat()?;
+        self.flush_fs_info()?;
 
-        SubSlice::new(self.inner.clone(), offset, self.bytes_per_cluster)
+        Ok((self.free_count - free_count) as usize)
     }
 
-    pub fn cluster_as_subslice_mut(&self, cluster: u32) -> SubSliceMut {
-        if cluster == 0 {
-            // for cluster 0 simply return empty subslice
-            // this makes things a bit easier, since cluster 0 is used as a marker that a file/dir
-            // is empty
+    /// number of FAT copies
+    pub fn num_fats(&self) -> u8 {
+        self.bpb.num_fats()
+    }
+
+    /// check that all FAT copies on disk match the first one
+    ///
+    /// only looks at what is on disk, so changes that haven't been flushed yet are ignored
+    pub fn verify_fat_copies(&self) -> Result<(), FatCopyMismatch> {
+        let first = read_fat_copy(&self.inner, &self.bpb, 0)
+            .map_err(|err| FatCopyMismatch::Unreadable { copy: 0, err })?;
+
+        check_fat_copies(&self.inner, &self.bpb, &first)
+    }
+
+    /// overwrite all FAT copies with the in-memory FAT
+    ///
+    /// returns the number of entries that differed in the copies after the first
+    pub fn repair_fat_copies(&mut self) -> anyhow::Result<usize> {
+        let fat_len = self.bpb.fat_len_bytes();
+
+        let first_slice = SubSliceMut::new(Rc::clone(&self.inner), self.bpb.fat_offset(), fat_len);
+
+        self.fat.write_back(first_slice)?;
+
+        let first = read_fat_copy(&self.inner, &self.bpb, 0)?;
+
+        let mut fixed = 0;
+
+        for n in 1..self.bpb.num_fats() {
+            let copy = match read_fat_copy(&self.inner, &self.bpb, n) {
+                Ok(copy) => copy,
+                Err(err) => {
+                    warn!("failed to read FAT copy {n}, overwriting it anyway: {err}");
+
+                    vec![0; fat_len]
+                }
+            };
+
+            let max = self.bpb.count_of_clusters();
 
-            return SubSliceMut::new(self.inner.clone(), 0, 0);
+            let copy = fat::Fat::new(self.fat_type(), &copy, max);
+
+            fixed += self.fat.mismatched_entries(&copy).count();
+
+            self.inner
+                .borrow_mut()
+                .write_at_offset(self.bpb.nth_fat_offset(n), &first)?;
         }
 
-        let offset = self.data_cluster_to_offset(cluster);
+        Ok(fixed)
+    }
 
-        SubSliceMut::new(self.inner.clone(), offset, self.bytes_per_cluster)
+    pub fn bytes_per_sector(&self) -> u16 {
+        self.bpb.bytes_per_sector()
     }
 
-    fn root_dir_as_subslice(&self) -> SubSlice {
-        SubSlice::new(self.inner.clone(), self.root_dir_offset.unwrap(), self.root_dir_size)
+    pub fn sectors_per_cluster(&self) -> u8 {
+        self.bpb.sectors_per_cluster()
     }
 
-    fn root_dir_as_subslice_mut(&self) -> SubSliceMut {
-        SubSliceMut::new(self.inner.clone(), self.root_dir_offset.unwrap(), self.root_dir_size)
+    pub fn bytes_per_cluster(&self) -> usize {
+        self.bytes_per_cluster
     }
 
-    fn chain_reader(&'_ self, first_cluster: u32) -> iter::ClusterChainReader<'_> {
-        iter::ClusterChainReader::new(self, first_cluster)
+    /// count of data clusters
+    pub fn cluster_count(&self) -> u32 {
+        self.bpb.count_of_clusters()
     }
 
-    fn chain_writer(&'_ mut self, first_cluster: u32) -> iter::ClusterChainWriter<'_> {
-        iter::ClusterChainWriter::new(self, first_cluster)
+    /// total number of sectors in the image
+    pub fn total_sectors(&self) -> u32 {
+        self.bpb.total_sectors()
     }
 
-    pub fn root_dir_iter<'a>(&self) -> DirIter<'_> {
-        let reader = ClusterChainReader::root_dir_reader(self);
+    /// byte offset of the first FAT
+    pub fn fat_offset(&self) -> u64 {
+        self.bpb.fat_offset()
+    }
 
-        DirIter::new(reader)
+    /// byte size of a single FAT
+    pub fn fat_size_bytes(&self) -> usize {
+        self.bpb.fat_len_bytes()
     }
 
-    pub fn dir_iter<'a>(&self, first_cluster: u32) -> DirIter<'_> {
-        let cluster_iter = self.chain_reader(first_cluster);
+    /// byte offset of the fixed root directory; None for FAT32
+    pub fn root_dir_offset(&self) -> Option<u64> {
+        self.root_dir_offset
+    }
 
-        DirIter::new(cluster_iter)
+    /// byte size of the fixed root directory; 0 for FAT32
+    pub fn root_dir_size_bytes(&self) -> usize {
+        self.root_dir_size
     }
 
-    pub fn file_reader(&self, first_cluster: u32) -> iter::ClusterChainReader<'_> {
-        // TODO: needs to take file size into account
-        assert!(first_cluster >= 2);
+    /// byte offset of the data region, i.e. the first data cluster
+    pub fn data_offset(&self) -> u64 {
+        self.data_offset
+    }
 
-        self.chain_reader(first_cluster)
+    /// byte size of the data region, up to the end of the volume
+    pub fn data_size_bytes(&self) -> usize {
+        self.bpb.data_len_bytes()
     }
 
-    pub fn file_writer(&mut self, first_cluster: u32) -> iter::ClusterChainWriter<'_> {
-        // TODO: needs to take file size into account
+    pub fn root_cluster(&self) -> Option<u32> {
+        self.bpb.root_cluster()
+    }
+
+    /// first cluster of the root dir, or 1 for the fixed root dir of FAT12/16
+    ///
+    /// cluster 1 never is a data cluster, so it can't be confused with a real directory. note that
+    /// functions taking a directory cluster refer to the root dir with 0 instead, like .. entries
+    pub fn root_first_cluster(&self) -> u32 {
+        self.root_cluster().unwrap_or(1)
+    }
+
+    /// next data cluster or None is cluster is EOF
+    ///
+    /// giving an invalid cluster (free, reserved, or defective) returns an appropriate error
+    pub fn next_cluster(&self, cluster: u32) -> Result<Option<u32>, FatError> {
+        self.fat.get_next_cluster(cluster)
+    }
+
+    /// iterate over the clusters of the chain starting at `first_cluster`, see `Fat::chain`
+    pub fn iter_cluster_chain(&self, first_cluster: u32) -> fat::ClusterChainIter<'_> {
+        self.fat.chain(first_cluster)
+    }
+
+    /// number of clusters in the chain starting at `first_cluster`, 0 for an empty chain
+    ///
+    /// results are cached until the FAT changes. fails if `first_cluster` isn't an allocated data
+    /// cluster
+    pub fn count_clusters_in_chain(&self, first_cluster: u32) -> Result<u32, FatError> {
+        if first_cluster == 0 {
+            return Ok(0);
+        }
+
+        if !self.fat.valid_clusters().contains(&first_cluster) {
+            return Err(FatError::InvalidEntry(first_cluster));
+        }
+
+        if self.fat.is_free(first_cluster) {
+            return Err(FatError::FreeCluster);
+        }
+
+        if let Some(&n_clusters) = self.chain_lengths.borrow().get(&first_cluster) {
+            return Ok(n_clusters);
+        }
+
+        let mut n_clusters = 0;
+
+        for cluster in self.iter_cluster_chain(first_cluster) {
+            cluster?;
+
+            n_clusters += 1;
+        }
+
+        self.chain_lengths.borrow_mut().insert(first_cluster, n_clusters);
+
+        Ok(n_clusters)
+    }
+
+    /// number of bytes allocated to the chain starting at `first_cluster`
+    pub fn bytes_in_chain(&self, first_cluster: u32) -> Result<u64, FatError> {
+        Ok(self.count_clusters_in_chain(first_cluster)? as u64 * self.bytes_per_cluster as u64)
+    }
+
+    pub fn cluster_as_subslice(&self, cluster: u32) -> Result<SubSlice, FatError> {
+        if cluster == 0 {
+            // for cluster 0 simply return empty subslice
+            // this makes things a bit easier, since cluster 0 is used as a marker that a file/dir
+            // is empty
+
+            return Ok(self.empty_subslice());
+        }
+
+        let offset = self.data_cluster_to_offset(cluster)?;
+
+        Ok(SubSlice::new(self.inner.clone(), offset, self.bytes_per_cluster))
+    }
+
+    pub fn cluster_as_subslice_mut(&self, cluster: u32) -> Result<SubSliceMut, FatError> {
+        if cluster == 0 {
+            // for cluster 0 simply return empty subslice
+            // this makes things a bit easier, since cluster 0 is used as a marker that a file/dir
+            // is empty
+
+            return Ok(self.empty_subslice_mut());
+        }
+
+        let offset = self.data_cluster_to_offset(cluster)?;
+
+        Ok(SubSliceMut::new(self.inner.clone(), offset, self.bytes_per_cluster))
+    }
+
+    /// empty SubSlice, for chains that have no (valid) clusters
+    pub(crate) fn empty_subslice(&self) -> SubSlice {
+        SubSlice::new(self.inner.clone(), 0, 0)
+    }
+
+    /// empty SubSliceMut, for chains that have no (valid) clusters
+    pub(crate) fn empty_subslice_mut(&self) -> SubSliceMut {
+        SubSliceMut::new(self.inner.clone(), 0, 0)
+    }
+
+    /// the fixed root dir region of FAT12/16
+    ///
+    /// panics on FAT32, where the root dir is a regular cluster chain
+    pub fn root_dir_as_subslice(&self) -> SubSlice {
+        SubSlice::new(self.inner.clone(), self.fixed_root_dir_offset(), self.root_dir_size)
+    }
+
+    /// the fixed root dir region of FAT12/16
+    ///
+    /// panics on FAT32, where the root dir is a regular cluster chain
+    pub fn root_dir_as_subslice_mut(&self) -> SubSliceMut {
+        SubSliceMut::new(self.inner.clone(), self.fixed_root_dir_offset(), self.root_dir_size)
+    }
+
+    fn fixed_root_dir_offset(&self) -> u64 {
+        self.root_dir_offset.unwrap_or_else(|| {
+            panic!("{:?} has no fixed root dir, use root_cluster instead", self.fat_type())
+        })
+    }
+
+    fn chain_reader(&'_ self, first_cluster: u32) -> iter::ClusterChainReader<'_> {
+        iter::ClusterChainReader::new(self, first_cluster)
+    }
+
+    fn chain_writer(&'_ mut self, first_cluster: u32) -> iter::ClusterChainWriter<'_> {
+        iter::ClusterChainWriter::new(self, first_cluster)
+    }
+
+    pub fn root_dir_iter(&self) -> DirIter<ClusterChainReader<'_>> {
+        let reader = ClusterChainReader::root_dir_reader(self);
+
+        DirIter::new(reader)
+    }
+
+    pub fn dir_iter(&self, first_cluster: u32) -> DirIter<ClusterChainReader<'_>> {
+        let cluster_iter = self.chain_reader(first_cluster);
+
+        DirIter::new(cluster_iter)
+    }
+
+    /// set the write time, write date, and last access date of the DirEntry at `entry_offset` to
+    /// the current local time
+    pub fn touch(&mut self, entry_offset: u64) -> std::io::Result<()> {
+        self.touch_impl(entry_offset, true, true)
+    }
+
+    /// set only the last access date of the DirEntry at `entry_offset` to the current local date
+    pub fn touch_access(&mut self, entry_offset: u64) -> std::io::Result<()> {
+        self.touch_impl(entry_offset, true, false)
+    }
+
+    /// set only the write time and write date of the DirEntry at `entry_offset` to the current
+    /// local time
+    pub fn touch_write(&mut self, entry_offset: u64) -> std::io::Result<()> {
+        self.touch_impl(entry_offset, false, true)
+    }
+
+    fn touch_impl(&mut self, entry_offset: u64, access: bool, write: bool) -> std::io::Result<()> {
+        let now: DateTime<Local> = SystemTime::now().into();
+
+        let time = Time::from_datetime(now).map_err(std::io::Error::other)?;
+        let date = Date::from_datetime(now).map_err(std::io::Error::other)?;
+
+        let mut bytes = [0; 32];
+
+        self.inner
+            .borrow_mut()
+            .read_at_offset(entry_offset, &mut bytes)?;
+
+        if access {
+            bytes[18..20].copy_from_slice(&date.repr().to_le_bytes());
+        }
+
+        if write {
+            bytes[22..24].copy_from_slice(&time.repr().to_le_bytes());
+            bytes[24..26].copy_from_slice(&date.repr().to_le_bytes());
+        }
+
+        self.inner
+            .borrow_mut()
+            .write_at_offset(entry_offset, &bytes)
+    }
+
+    /// check whether the directory starting at `dir_first_cluster` contains an entry `name`
+    ///
+    /// a first cluster of 0 refers to the root directory
+    pub fn dir_contains(&self, dir_first_cluster: u32, name: &str) -> bool {
+        self.dir_iter_or_root(dir_first_cluster)
+            .any(|dir_entry| dir_entry.matches_name(name, self.case_sensitive))
+    }
+
+    /// read the DirEntry at `offset` straight from disk
+    ///
+    /// useful to check a just written entry without walking the whole directory
+    pub fn validate_dir_entry_at(&self, offset: u64) -> anyhow::Result<DirEntry> {
+        let mut bytes = [0; 32];
+
+        self.inner.borrow_mut().read_at_offset(offset, &mut bytes)?;
+
+        Ok(DirEntry::load(&bytes, offset)?)
+    }
+
+    /// erase `dir_entry`, which lives in the directory starting at `dir_cluster` (0 for the root
+    /// dir), together with its LFN slots
+    ///
+    /// does not touch the clusters of `dir_entry`
+    pub fn erase_dir_entry(&self, dir_cluster: u32, dir_entry: DirEntry) -> anyhow::Result<()> {
+        let n_slots = dir_entry.n_longname_slots() as usize;
+
+        let mut reader = if dir_cluster == 0 {
+            ClusterChainReader::root_dir_reader(self)
+        } else {
+            self.chain_reader(dir_cluster)
+        };
+
+        // the LFN slots may span a cluster boundary, so remember the offsets of the last n_slots
+        // entries before dir_entry
+        let mut slot_offsets = VecDeque::with_capacity(n_slots + 1);
+
+        loop {
+            let offset = reader.current_offset();
+
+            if offset == dir_entry.offset() {
+                break;
+            }
+
+            anyhow::ensure!(
+                reader.skip(32) == 32,
+                "DirEntry at offset {:#X} is not in directory at cluster {}",
+                dir_entry.offset(),
+                dir_cluster
+            );
+
+            slot_offsets.push_back(offset);
+
+            if slot_offsets.len() > n_slots {
+                slot_offsets.pop_front();
+            }
+        }
+
+        anyhow::ensure!(
+            slot_offsets.len() == n_slots,
+            "DirEntry has {} LFN slots, but only {} entries precede it",
+            n_slots,
+            slot_offsets.len()
+        );
+
+        for offset in slot_offsets {
+            self.inner.borrow_mut().write_at_offset(offset, &[0xE5])?;
+        }
+
+        dir_entry.erase(self)?;
+
+        Ok(())
+    }
+
+    /// erase `dir_entry` from the directory starting at `dir_cluster` (0 for the root dir) and
+    /// free its clusters
+    ///
+    /// the FAT is written back afterwards
+    pub fn remove_dir_entry(
+        &mut self,
+        dir_cluster: u32,
+        dir_entry: DirEntry,
+    ) -> anyhow::Result<()> {
+        let first_cluster = dir_entry.first_cluster();
+
+        self.erase_dir_entry(dir_cluster, dir_entry)?;
+
+        if first_cluster == 0 {
+            // empty file, nothing allocated
+            return Ok(());
+        }
+
+        // entry is already gone, so at worst this leaks the clusters
+        self.free_cluster_chain(first_cluster)?;
+
+        Ok(())
+    }
+
+    /// iterate over the directory starting at `first_cluster`, where 0 refers to the root dir
+    fn dir_iter_or_root(&self, first_cluster: u32) -> DirIter<ClusterChainReader<'_>> {
+        if first_cluster == 0 {
+            self.root_dir_iter()
+        } else {
+            self.dir_iter(first_cluster)
+        }
+    }
+
+    /// generate a unique 8.3 name for `name` in the directory starting at `dir_cluster`
+    fn generate_short_name(&self, dir_cluster: u32, name: &str) -> anyhow::Result<[u8; 11]> {
+        let (basis, lossy) = dir::short_name_basis(name);
+
+        anyhow::ensure!(basis[0] != b' ', "can't generate short name for \"{}\"", name);
+
+        let exists = |short_name: &[u8; 11]| {
+            self.dir_iter_or_root(dir_cluster)
+                .any(|dir_entry| dir_entry.name() == short_name)
+        };
+
+        if !lossy {
+            anyhow::ensure!(!exists(&basis), "\"{}\" already exists", name);
+
+            return Ok(basis);
+        }
+
+        for n in 1..1_000_000 {
+            let short_name = dir::short_name_with_tail(basis, n);
+
+            if !exists(&short_name) {
+                return Ok(short_name);
+            }
+        }
+
+        anyhow::bail!("could not find free short name for \"{}\"", name);
+    }
+
+    /// last cluster in the chain starting at `first_cluster`
+    fn last_cluster(&self, first_cluster: u32) -> Result<u32, FatError> {
+        let mut cluster = first_cluster;
+
+        while let Some(next_cluster) = self.next_cluster(cluster)? {
+            cluster = next_cluster;
+        }
+
+        Ok(cluster)
+    }
+
+    /// fill `cluster` with zeros
+    fn zero_cluster(&self, cluster: u32) -> anyhow::Result<()> {
+        let mut sub_slice = self.cluster_as_subslice_mut(cluster)?;
+
+        sub_slice.write_all(&vec![0; self.bytes_per_cluster])?;
+
+        Ok(())
+    }
+
+    /// find the byte offsets of `n` consecutive free slots in the directory starting at
+    /// `dir_cluster` (0 for the root dir)
+    ///
+    /// extends the directory by new clusters if necessary (not possible for the fixed FAT12/16
+    /// root dir)
+    fn find_free_dir_slots(&mut self, dir_cluster: u32, n: usize) -> anyhow::Result<Vec<u64>> {
+        let dir_cluster = match dir_cluster {
+            0 => self.root_cluster().unwrap_or(0),
+            cluster => cluster,
+        };
+
+        let mut slots = Vec::with_capacity(n);
+
+        {
+            let mut reader = if dir_cluster == 0 {
+                ClusterChainReader::root_dir_reader(self)
+            } else {
+                self.chain_reader(dir_cluster)
+            };
+
+            while slots.len() < n {
+                let offset = reader.current_offset();
+
+                let mut chunk = [0; 32];
+
+                if reader.read_exact(&mut chunk).is_err() {
+                    break;
+                }
+
+                if chunk[0] == 0x00 || chunk[0] == 0xE5 {
+                    slots.push(offset);
+                } else {
+                    // slots need to be consecutive
+                    slots.clear();
+                }
+            }
+        }
+
+        while slots.len() < n {
+            anyhow::ensure!(dir_cluster != 0, "root directory is full");
+
+            let last_cluster = self.last_cluster(dir_cluster)?;
+
+            let Some(new_cluster) = self.alloc_cluster(Some(last_cluster)) else {
+                anyhow::bail!("no free cluster left to extend directory");
+            };
+
+            self.zero_cluster(new_cluster)?;
+
+            let offset = self.data_cluster_to_offset(new_cluster)?;
+
+            slots.extend(
+                (0..self.bytes_per_cluster as u64 / 32)
+                    .map(|i| offset + 32 * i)
+                    .take(n - slots.len()),
+            );
+        }
+
+        Ok(slots)
+    }
+
+    /// write `dir_entry` under the name `name` to free slots in the directory starting at
+    /// `dir_cluster` (0 for the root dir), preceded by LFN entries if needed
+    ///
+    /// may extend the directory, so this should run inside a transaction
+    fn insert_dir_entry(
+        &mut self,
+        dir_cluster: u32,
+        name: &str,
+        mut dir_entry: DirEntry,
+    ) -> anyhow::Result<DirEntry> {
+        let checksum = DirEntry::checksum(dir_entry.name());
+
+        let long_name_entries = if dir_entry.name_string() != name {
+            dir::long_name_entries(name, checksum)?
+        } else {
+            Vec::new()
+        };
+
+        let slots = self.find_free_dir_slots(dir_cluster, long_name_entries.len() + 1)?;
+
+        let (lfn_slots, &[short_slot]) = slots.split_at(long_name_entries.len()) else {
+            unreachable!("one slot is left for the 8.3 entry");
+        };
+
+        if let Some(&first_slot) = lfn_slots.first()
+            && lfn_slots.windows(2).all(|pair| pair[1] == pair[0] + 32)
+        {
+            dir::write_lfn_entries(self, first_slot, name, checksum)?;
+        } else {
+            // the LFN entries continue in a newly allocated cluster
+            for (&slot, bytes) in lfn_slots.iter().zip(&long_name_entries) {
+                SubSliceMut::new(self.inner.clone(), slot, 32).write_all(bytes)?;
+            }
+        }
+
+        dir_entry.write_at(self, short_slot)?;
+
+        if !long_name_entries.is_empty() {
+            dir_entry.set_long_name(name.into(), long_name_entries.len() as u8);
+        }
+
+        Ok(dir_entry)
+    }
+
+    /// create a new, empty directory `name` in the directory starting at `parent_dir_cluster` (0
+    /// for the root dir)
+    ///
+    /// the FAT and FSInfo are written back afterwards
+    pub fn create_dir(&mut self, parent_dir_cluster: u32, name: &str) -> anyhow::Result<DirEntry> {
+        anyhow::ensure!(
+            !self.dir_contains(parent_dir_cluster, name),
+            "\"{}\" already exists",
+            name
+        );
+
+        let short_name = self.generate_short_name(parent_dir_cluster, name)?;
+
+        // also takes back a new cluster for the parent dir, should we have needed one
+        let dir_entry = self.in_transaction(|fat_fs| {
+            let Some(cluster) = fat_fs.alloc_cluster(None) else {
+                anyhow::bail!("no free cluster left");
+            };
+
+            fat_fs.zero_cluster(cluster)?;
+
+            let mut dir_entry = DirEntry::create(short_name, Attr::Directory)?;
+            dir_entry.set_first_cluster(cluster);
+
+            let mut dot = DirEntry::create(*b".          ", Attr::Directory)?;
+            dot.set_first_cluster(cluster);
+
+            // .. in a direct child of the root dir points to cluster 0, even on FAT32
+            let parent_cluster = if Some(parent_dir_cluster) == fat_fs.root_cluster() {
+                0
+            } else {
+                parent_dir_cluster
+            };
+
+            let mut dotdot = DirEntry::create(*b"..         ", Attr::Directory)?;
+            dotdot.set_first_cluster(parent_cluster);
+
+            let offset = fat_fs.data_cluster_to_offset(cluster)?;
+
+            dot.write_at(fat_fs, offset)?;
+            dotdot.write_at(fat_fs, offset + 32)?;
+
+            fat_fs.insert_dir_entry(parent_dir_cluster, name, dir_entry)
+        })?;
+
+        self.flush_fat()?;
+        self.flush_fs_info()?;
+
+        Ok(dir_entry)
+    }
+
+    /// create an empty file `name` in the directory starting at `dir_cluster` (0 for the root dir)
+    ///
+    /// LFN entries are written in front of the new entry if `name` isn't a plain 8.3 name
+    pub fn create_file(
+        &mut self,
+        dir_cluster: u32,
+        name: &str,
+        attr: Attr,
+    ) -> anyhow::Result<DirEntry> {
+        anyhow::ensure!(!self.dir_contains(dir_cluster, name), "\"{}\" already exists", name);
+
+        anyhow::ensure!(
+            !attr.intersects(Attr::Directory | Attr::VolumeId),
+            "invalid attributes for a file: {}",
+            attr
+        );
+
+        let short_name = self.generate_short_name(dir_cluster, name)?;
+
+        let dir_entry = DirEntry::create(short_name, attr)?;
+
+        self.in_transaction(|fat_fs| fat_fs.insert_dir_entry(dir_cluster, name, dir_entry))
+    }
+
+    /// the volume label without trailing spaces, if there is one
+    ///
+    /// a VolumeId entry in the root dir takes precedence over the label in the BPB, like on
+    /// Windows
+    pub fn volume_label(&self) -> Option<CompactString> {
+        let label = match self.root_dir_iter().find(|dir_entry| dir_entry.is_volume_id()) {
+            Some(dir_entry) => dir::decode_volume_label(dir_entry.name()),
+            None => dir::decode_volume_label(self.bpb.volume_label()?),
+        };
+
+        // formatting tools write "NO NAME" if no label was given
+        (!label.is_empty() && label != "NO NAME").then_some(label)
+    }
+
+    /// set the volume label in the root dir, creating a VolumeId entry if there is none, and in
+    /// the BPB, if it has a valid label field
+    pub fn set_volume_label(&mut self, label: &str) -> anyhow::Result<()> {
+        let label = dir::encode_volume_label(label)?;
+
+        let volume_id = self.root_dir_iter().find(|dir_entry| dir_entry.is_volume_id());
+
+        match volume_id {
+            Some(dir_entry) => {
+                // the label is the name of the entry, nothing else needs to change
+                self.inner.borrow_mut().write_at_offset(dir_entry.offset(), &label)?;
+            }
+            None => {
+                let dir_entry = DirEntry::create(label, Attr::VolumeId)?;
+                let name = dir_entry.name_string();
+
+                self.in_transaction(|fat_fs| fat_fs.insert_dir_entry(0, &name, dir_entry))?;
+            }
+        }
+
+        let Some(label_offset) = self.bpb.volume_label_offset() else {
+            return Ok(());
+        };
+
+        let mut bpb_bytes = [0; 512];
+
+        self.inner.borrow_mut().read_at_offset(0, &mut bpb_bytes)?;
+
+        bpb_bytes[label_offset..][..11].copy_from_slice(&label);
+
+        let new_bpb = bpb::Bpb::load(&bpb_bytes)?;
+
+        self.inner.borrow_mut().write_at_offset(0, &bpb_bytes)?;
+
+        if let Some(backup_sector) = new_bpb.backup_boot_sector() {
+            self.inner
+                .borrow_mut()
+                .write_at_offset(new_bpb.sector_to_offset(backup_sector as u32), &bpb_bytes)?;
+        }
+
+        self.bpb = new_bpb;
+
+        Ok(())
+    }
+
+    /// resolve an absolute path like `/boot/grub/grub.cfg` to its DirEntry
+    ///
+    /// empty components are ignored and names are matched according to `case_sensitive`. returns
+    /// `Ok(None)` if any component doesn't exist or isn't a directory. the root dir has no
+    /// DirEntry, so resolving it is an error
+    pub fn find_entry(&self, path: &str) -> anyhow::Result<Option<DirEntry>> {
+        let mut components = path.split('/').filter(|component| !component.is_empty()).peekable();
+
+        anyhow::ensure!(components.peek().is_some(), "path {path:?} refers to the root dir");
+
+        let mut dir_cluster = 0;
+
+        while let Some(name) = components.next() {
+            if components.peek().is_some() {
+                // resolve . and .. structurally instead of by name, so they also work in the root
+                // dir, which doesn't have those entries
+                match name {
+                    "." => continue,
+                    ".." => {
+                        if dir_cluster != 0 {
+                            let Some(dotdot) = self.dir_iter(dir_cluster).find(DirEntry::is_dotdot)
+                            else {
+                                return Ok(None);
+                            };
+
+                            // .. pointing at the root dir has a first cluster of 0
+                            dir_cluster = dotdot.first_cluster();
+                        }
+
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+
+            let Some(dir_entry) = self.dir_iter_or_root(dir_cluster).find_by_name(name) else {
+                return Ok(None);
+            };
+
+            if components.peek().is_none() {
+                return Ok(Some(dir_entry));
+            }
+
+            if !dir_entry.is_dir() {
+                return Ok(None);
+            }
+
+            dir_cluster = dir_entry.first_cluster();
+        }
+
+        unreachable!()
+    }
+
+    /// call `f` with the absolute path of every entry in the FS, depth first
+    ///
+    /// . and .. are visited, but never descended into. directories that were already visited,
+    /// e.g. because the tree contains a cycle, are skipped
+    pub fn walk<F>(&self, mut f: F) -> anyhow::Result<()>
+    where
+        F: FnMut(&str, &DirEntry) -> WalkControl,
+    {
+        let mut visited = HashSet::new();
+
+        visited.insert(self.root_first_cluster());
+
+        self.walk_dir(self.root_dir_iter(), "", &mut visited, &mut f)?;
+
+        Ok(())
+    }
+
+    /// returns false if the walk was stopped
+    fn walk_dir(
+        &self,
+        dir_iter: DirIter<ClusterChainReader<'_>>,
+        dir_path: &str,
+        visited: &mut HashSet<u32>,
+        f: &mut impl FnMut(&str, &DirEntry) -> WalkControl,
+    ) -> anyhow::Result<bool> {
+        for dir_entry in dir_iter {
+            let path = format!("{}/{}", dir_path, dir_entry.name_string());
+
+            match f(&path, &dir_entry) {
+                WalkControl::Continue => {}
+                WalkControl::SkipSubtree => continue,
+                WalkControl::Stop => return Ok(false),
+            }
+
+            if !dir_entry.is_dir() || dir_entry.is_dot_or_dotdot() {
+                continue;
+            }
+
+            let cluster = dir_entry.first_cluster();
+
+            anyhow::ensure!(cluster != 0, "directory {} has no first cluster", path);
+
+            if !visited.insert(cluster) {
+                debug!("directory {path} at cluster {cluster} was already visited, skipping");
+                continue;
+            }
+
+            if !self.walk_dir(self.dir_iter(cluster), &path, visited, f)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// check whether the directory starting at `dir_cluster` is `ancestor` or lies below it
+    ///
+    /// walks up the tree via the .. entries, 0 refers to the root dir
+    fn is_in_subtree(&self, dir_cluster: u32, ancestor: u32) -> anyhow::Result<bool> {
+        let root_cluster = self.root_cluster().unwrap_or(0);
+
+        let mut cluster = dir_cluster;
+
+        for _ in 0..self.cluster_count() {
+            if cluster == ancestor {
+                return Ok(true);
+            }
+
+            if cluster == 0 || cluster == root_cluster {
+                return Ok(false);
+            }
+
+            let Some(dotdot) = self.dir_iter(cluster).find(DirEntry::is_dotdot) else {
+                anyhow::bail!("directory at cluster {} has no .. entry", cluster);
+            };
+
+            cluster = dotdot.first_cluster();
+        }
+
+        anyhow::bail!("directory tree above cluster {} contains a cycle", dir_cluster);
+    }
+
+    /// move the entry `src_name` in the directory starting at `src_dir` to `dst_name` in the
+    /// directory starting at `dst_dir` (0 for the root dir in both cases)
+    ///
+    /// an existing `dst_name` is replaced, as long as it is a file or an empty directory of the
+    /// same kind as the source
+    pub fn rename(
+        &mut self,
+        src_dir: u32,
+        src_name: &str,
+        dst_dir: u32,
+        dst_name: &str,
+    ) -> anyhow::Result<DirEntry> {
+        let Some(src) = self.dir_iter_or_root(src_dir).find_by_name(src_name) else {
+            anyhow::bail!("\"{}\" does not exist", src_name);
+        };
+
+        anyhow::ensure!(
+            !src.is_dot() && !src.is_dotdot(),
+            "can't rename \"{}\"",
+            src_name
+        );
+
+        if src.is_dir() {
+            anyhow::ensure!(
+                !self.is_in_subtree(dst_dir, src.first_cluster())?,
+                "can't move \"{}\" into itself",
+                src_name
+            );
+        }
+
+        let mut case_only = false;
+
+        // dst can be src itself, e.g. if only the case of the name changes
+        let dst = match self.dir_iter_or_root(dst_dir).find_by_name(dst_name) {
+            Some(dst) if dst.offset() == src.offset() => {
+                if src.name_string() == dst_name {
+                    // renamed to itself, nothing to do
+                    return Ok(src);
+                }
+
+                case_only = true;
+
+                None
+            }
+            dst => dst,
+        };
+
+        if let Some(dst) = dst {
+            anyhow::ensure!(
+                !dst.is_dot() && !dst.is_dotdot(),
+                "can't replace \"{}\"",
+                dst_name
+            );
+
+            anyhow::ensure!(
+                dst.is_dir() == src.is_dir(),
+                "can't replace \"{}\" with \"{}\": kinds differ",
+                dst_name,
+                src_name
+            );
+
+            if dst.is_dir() {
+                anyhow::ensure!(
+                    self.dir_iter(dst.first_cluster()).is_empty_dir(),
+                    "\"{}\" is not empty",
+                    dst_name
+                );
+            }
+
+            self.remove_dir_entry(dst_dir, dst)?;
+        }
+
+        // the short name is still taken by src, but also matches dst_name
+        let short_name = if case_only {
+            src.name().try_into().unwrap()
+        } else {
+            self.generate_short_name(dst_dir, dst_name)?
+        };
+
+        // add the new entry first, so a failure can't lose the file
+        let renamed = self.in_transaction(|fat_fs| {
+            fat_fs.insert_dir_entry(dst_dir, dst_name, src.renamed(short_name))
+        })?;
+
+        self.erase_dir_entry(src_dir, src)?;
+
+        if renamed.is_dir() && src_dir != dst_dir {
+            // .. in a direct child of the root dir points to cluster 0, even on FAT32
+            let parent_cluster = if Some(dst_dir) == self.root_cluster() {
+                0
+            } else {
+                dst_dir
+            };
+
+            let dotdot_offset = self.data_cluster_to_offset(renamed.first_cluster())? + 32;
+
+            let mut dotdot = self.validate_dir_entry_at(dotdot_offset)?;
+
+            anyhow::ensure!(dotdot.is_dotdot(), "second entry of \"{}\" is not ..", dst_name);
+
+            dotdot.set_first_cluster(parent_cluster);
+            dotdot.write_back(self)?;
+        }
+
+        Ok(renamed)
+    }
+
+    /// shrink or grow the chain starting at `first_cluster` to hold exactly `new_size` bytes
+    ///
+    /// added clusters are zeroed. returns the new first cluster, which is 0 for a size of 0 and a
+    /// fresh cluster if the chain was empty before, so the caller has to update the DirEntry
+    pub fn truncate(&mut self, first_cluster: u32, new_size: u64) -> anyhow::Result<u32> {
+        let n_clusters = new_size.div_ceil(self.bytes_per_cluster as u64);
+
+        if n_clusters == 0 {
+            if first_cluster != 0 {
+                self.free_cluster_chain(first_cluster)?;
+            }
+
+            return Ok(0);
+        }
+
+        let first_cluster = self.in_transaction(|fat_fs| {
+            let alloc_zeroed =
+                |fat_fs: &mut FatFs, prev_cluster: Option<u32>| -> anyhow::Result<u32> {
+                    let Some(cluster) = fat_fs.alloc_cluster(prev_cluster) else {
+                        anyhow::bail!("no free cluster left");
+                    };
+
+                    fat_fs.zero_cluster(cluster)?;
+
+                    Ok(cluster)
+                };
+
+            let first_cluster = match first_cluster {
+                0 => alloc_zeroed(fat_fs, None)?,
+                cluster => cluster,
+            };
+
+            let mut cluster = first_cluster;
+
+            for _ in 1..n_clusters {
+                cluster = match fat_fs.next_cluster(cluster)? {
+                    Some(next_cluster) => next_cluster,
+                    None => alloc_zeroed(fat_fs, Some(cluster))?,
+                };
+            }
+
+            let n_freed = fat_fs.fat.truncate_chain(cluster)?;
+
+            fat_fs.chain_lengths.get_mut().clear();
+
+            fat_fs.free_count += n_freed as u32;
+            fat_fs.next_free = fat_fs.fat.first_free_cluster();
+
+            Ok(first_cluster)
+        })?;
+
+        self.flush_fat()?;
+        self.flush_fs_info()?;
+
+        Ok(first_cluster)
+    }
+
+    pub fn file_reader(&self, first_cluster: u32) -> iter::ClusterChainReader<'_> {
+        // TODO: needs to take file size into account
+        assert!(first_cluster >= 2);
+
+        self.chain_reader(first_cluster)
+    }
+
+    pub fn file_writer(&mut self, first_cluster: u32) -> iter::ClusterChainWriter<'_> {
+        // TODO: needs to take file size into account
         assert!(first_cluster >= 2);
 
-        self.chain_writer(first_cluster)
+        self.chain_writer(first_cluster)
+    }
+
+    /// read the whole contents of a file into memory
+    ///
+    /// reads exactly `file_size` bytes, so whatever is left in the last cluster after the logical
+    /// end of the file is not returned
+    pub fn read_file_to_vec(&self, first_cluster: u32, file_size: u32) -> anyhow::Result<Vec<u8>> {
+        let mut data = vec![0; file_size as usize];
+
+        if file_size == 0 {
+            return Ok(data);
+        }
+
+        anyhow::ensure!(
+            first_cluster >= 2,
+            "file of size {file_size} has invalid first cluster {first_cluster}"
+        );
+
+        self.file_reader(first_cluster).read_exact(&mut data)?;
+
+        Ok(data)
+    }
+
+    /// replace the contents of a file with `data`
+    ///
+    /// the chain is shrunk or grown to fit `data` exactly. returns the new first cluster, the
+    /// caller has to update first cluster and size of the DirEntry
+    pub fn write_file_from_slice(
+        &mut self,
+        first_cluster: u32,
+        data: &[u8],
+    ) -> anyhow::Result<u32> {
+        let first_cluster = self.truncate(first_cluster, data.len() as u64)?;
+
+        if !data.is_empty() {
+            let mut writer = self.file_writer(first_cluster);
+
+            writer.write_all(data)?;
+            writer.finish()?;
+        }
+
+        Ok(first_cluster)
+    }
+}
+
+/// read the `n`th FAT copy from disk
+#[cfg(feature = "std")]
+fn read_fat_copy(data: &RefCell<dyn SliceLike>, bpb: &bpb::Bpb, n: u8) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0; bpb.fat_len_bytes()];
+
+    data.borrow_mut().read_at_offset(bpb.nth_fat_offset(n), &mut buf)?;
+
+    Ok(buf)
+}
+
+/// compare all FAT copies after the first with `first`
+#[cfg(feature = "std")]
+fn check_fat_copies(
+    data: &RefCell<dyn SliceLike>,
+    bpb: &bpb::Bpb,
+    first: &[u8],
+) -> Result<(), FatCopyMismatch> {
+    for copy in 1..bpb.num_fats() {
+        let copy_buf = read_fat_copy(data, bpb, copy)
+            .map_err(|err| FatCopyMismatch::Unreadable { copy, err })?;
+
+        if copy_buf == first {
+            continue;
+        }
+
+        let first = fat::Fat::new(bpb.fat_type(), first, bpb.count_of_clusters());
+        let other = fat::Fat::new(bpb.fat_type(), &copy_buf, bpb.count_of_clusters());
+
+        // only bytes past the last entry may differ, which doesn't matter
+        if let Some(cluster) = first.mismatched_entries(&other).next() {
+            return Err(FatCopyMismatch::Differs { copy, cluster });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Attr, DirEntry, FatFs, FatType, FormatParams, SliceLike, WalkControl, dir, fs_info,
+    };
+
+    const RESERVED_SECTORS: u16 = 32;
+    const FAT_SECTORS: u32 = 513;
+    const DATA_CLUSTERS: u32 = 65600;
+
+    /// number of data clusters actually backed by the image
+    const IMAGE_DATA_CLUSTERS: usize = 8;
+
+    /// build a FAT32 image with one sector per cluster
+    ///
+    /// the image is truncated after the first few data clusters, so only those may be allocated
+    fn fat32_image(fs_info_sector: u16) -> Vec<u8> {
+        let image_sectors = RESERVED_SECTORS as usize + FAT_SECTORS as usize + IMAGE_DATA_CLUSTERS;
+
+        let mut image = vec![0; image_sectors * 512];
+
+        let total_sectors = RESERVED_SECTORS as u32 + FAT_SECTORS + DATA_CLUSTERS;
+
+        let bpb = &mut image[..512];
+
+        bpb[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        bpb[3..11].copy_from_slice(b"MSWIN4.1");
+        bpb[11..13].copy_from_slice(&512u16.to_le_bytes());
+        bpb[13] = 1;
+        bpb[14..16].copy_from_slice(&RESERVED_SECTORS.to_le_bytes());
+        bpb[16] = 1;
+        bpb[21] = 0xF8;
+        bpb[32..36].copy_from_slice(&total_sectors.to_le_bytes());
+        bpb[36..40].copy_from_slice(&FAT_SECTORS.to_le_bytes());
+        bpb[44..48].copy_from_slice(&2u32.to_le_bytes());
+        bpb[48..50].copy_from_slice(&fs_info_sector.to_le_bytes());
+        bpb[82..90].copy_from_slice(b"FAT32   ");
+        bpb[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        // a valid FSInfo in sector 1, so we can tell whether it gets picked up
+        let fs_info = &mut image[512..1024];
+
+        fs_info[..4].copy_from_slice(&0x41615252u32.to_le_bytes());
+        fs_info[484..488].copy_from_slice(&0x61417272u32.to_le_bytes());
+        fs_info[488..492].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+        fs_info[492..496].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+        fs_info[508..512].copy_from_slice(&0xAA550000u32.to_le_bytes());
+
+        // root dir is a single cluster and must not be handed out by the allocator
+        let fat = &mut image[RESERVED_SECTORS as usize * 512..];
+
+        fat[8..12].copy_from_slice(&0xFFFFFFFFu32.to_le_bytes());
+
+        image
+    }
+
+    fn load(image: Vec<u8>) -> FatFs {
+        let data: &'static mut [u8] = Box::leak(image.into_boxed_slice());
+
+        FatFs::load(data).unwrap()
+    }
+
+    #[test]
+    fn fs_info_present() {
+        let fat_fs = load(fat32_image(1));
+
+        assert!(fat_fs.fs_info().is_some());
+    }
+
+    #[test]
+    fn fs_info_free_count() {
+        let mut image = fat32_image(1);
+
+        // wrong hint gets invalidated
+        image[512 + 488..][..4].copy_from_slice(&1234u32.to_le_bytes());
+
+        let mut fat_fs = load(image);
+
+        let fs_info = fat_fs.fs_info().unwrap();
+
+        assert_eq!(fs_info.free_count_hint(), None);
+        assert!(fs_info::FsInfo::load(&fs_info.to_bytes()).is_ok());
+
+        let free_count = fat_fs.free_clusters();
+
+        let cluster = fat_fs.alloc_cluster(None).unwrap();
+
+        let on_disk = |fat_fs: &FatFs| {
+            let mut bytes = [0; 512];
+            fat_fs.inner.borrow_mut().read_at_offset(512, &mut bytes).unwrap();
+
+            fs_info::FsInfo::load(&bytes).unwrap()
+        };
+
+        // updated in memory and on disk
+        assert_eq!(fat_fs.fs_info().unwrap().free_count_hint(), Some(free_count - 1));
+        assert_eq!(on_disk(&fat_fs).free_count_hint(), Some(free_count - 1));
+        assert_eq!(on_disk(&fat_fs).next_free(), fat_fs.next_free);
+
+        fat_fs.dealloc_cluster(cluster);
+
+        assert_eq!(on_disk(&fat_fs).free_count_hint(), Some(free_count));
+        assert_eq!(on_disk(&fat_fs).next_free(), Some(cluster));
+
+        // a correct hint is kept
+        fat_fs.load_fs_info().unwrap();
+
+        assert_eq!(fat_fs.fs_info().unwrap().free_count_hint(), Some(free_count));
+    }
+
+    #[test]
+    fn fs_info_not_present() {
+        for fs_info_sector in [0, 0xFFFF, RESERVED_SECTORS] {
+            let mut fat_fs = load(fat32_image(fs_info_sector));
+
+            assert!(fat_fs.fs_info().is_none());
+
+            // must not try to touch the (non-existent) FSInfo sector
+            fat_fs.flush_fs_info().unwrap();
+        }
+    }
+
+    #[test]
+    fn create_dir_entries_on_disk() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let cluster = fat_fs.create_dir(0, "SUBDIR").unwrap().first_cluster();
+
+        // first slot of the empty root dir
+        let dir_entry = fat_fs.validate_dir_entry_at(fat_fs.data_offset()).unwrap();
+
+        assert_eq!(dir_entry.name(), b"SUBDIR     ");
+        assert!(dir_entry.is_dir());
+        assert_eq!(dir_entry.first_cluster(), cluster);
+
+        let offset = fat_fs.data_cluster_to_offset(cluster).unwrap();
+
+        let dot = fat_fs.validate_dir_entry_at(offset).unwrap();
+
+        assert!(dot.is_dot());
+        assert_eq!(dot.first_cluster(), cluster);
+
+        let dotdot = fat_fs.validate_dir_entry_at(offset + 32).unwrap();
+
+        assert!(dotdot.is_dotdot());
+        assert_eq!(dotdot.first_cluster(), 0);
+    }
+
+    #[test]
+    fn vec_backed() {
+        use std::io::{Read as _, Write as _};
+
+        let mut fat_fs = FatFs::load(fat32_image(1)).unwrap();
+
+        let contents: Vec<u8> = (0..3 * fat_fs.bytes_per_cluster()).map(|i| i as u8).collect();
+
+        fat_fs.create_file(0, "DATA.BIN", Attr::Archive).unwrap();
+        let first_cluster = fat_fs.truncate(0, contents.len() as u64).unwrap();
+
+        fat_fs.file_writer(first_cluster).write_all(&contents).unwrap();
+
+        let mut buf = vec![0; contents.len()];
+        fat_fs.file_reader(first_cluster).read_exact(&mut buf).unwrap();
+
+        assert_eq!(buf, contents);
+        assert!(fat_fs.dir_contains(0, "DATA.BIN"));
+    }
+
+    #[test]
+    fn format() {
+        use std::io::{Read as _, Write as _};
+
+        for (fat_type, total_size) in [
+            (FatType::Fat12, 1024 * 1024),
+            (FatType::Fat16, 32 * 1024 * 1024),
+            (FatType::Fat32, 40 * 1024 * 1024),
+        ] {
+            let params = FormatParams {
+                fat_type: Some(fat_type),
+                volume_serial_number: 0x1234_5678,
+                ..FormatParams::new(total_size)
+            };
+
+            let mut fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+            assert_eq!(fat_fs.fat_type(), fat_type);
+            assert_eq!(fat_fs.bpb.num_fats(), 2);
+            assert_eq!(fat_fs.root_dir_iter().count(), 0);
+
+            let count_of_clusters = fat_fs.bpb.count_of_clusters();
+
+            // only the FAT32 root dir cluster is in use
+            if fat_type == FatType::Fat32 {
+                assert_eq!(fat_fs.next_free, Some(3));
+                assert!(fat_fs.fs_info().is_some());
+
+                let mut fs_info = [0; 512];
+                fat_fs.inner.borrow_mut().read_at_offset(512, &mut fs_info).unwrap();
+
+                let free_count = u32::from_le_bytes(fs_info[488..492].try_into().unwrap());
+
+                assert_eq!(free_count, count_of_clusters - 1);
+            } else {
+                assert_eq!(fat_fs.next_free, Some(2));
+                assert!(fat_fs.fs_info().is_none());
+            }
+
+            assert!(fat_fs.free_clusters() >= count_of_clusters - 2);
+
+            // both FAT copies are identical
+            let fat_len = fat_fs.bpb.fat_len_bytes();
+            let mut fats = vec![0; 2 * fat_len];
+
+            fat_fs.inner.borrow_mut().read_at_offset(fat_fs.bpb.fat_offset(), &mut fats).unwrap();
+
+            assert_eq!(fats[..fat_len], fats[fat_len..]);
+
+            // and the FS is usable
+            fat_fs.create_file(0, "HELLO.TXT", Attr::Archive).unwrap();
+            let first_cluster = fat_fs.truncate(0, 5).unwrap();
+
+            fat_fs.file_writer(first_cluster).write_all(b"hello").unwrap();
+
+            let mut buf = [0; 5];
+            fat_fs.file_reader(first_cluster).read_exact(&mut buf).unwrap();
+
+            assert_eq!(&buf, b"hello");
+            assert!(fat_fs.dir_contains(0, "HELLO.TXT"));
+        }
+
+        // too many clusters for FAT12
+        let params = FormatParams {
+            fat_type: Some(FatType::Fat12),
+            sectors_per_cluster: Some(1),
+            ..FormatParams::new(32 * 1024 * 1024)
+        };
+
+        assert!(FatFs::format(Vec::new(), params).is_err());
+
+        // fixed-size storage can't grow
+        let storage: &'static mut [u8] = Box::leak(vec![0; 1024].into_boxed_slice());
+
+        assert!(FatFs::format(storage, FormatParams::new(1024 * 1024)).is_err());
+    }
+
+    #[test]
+    fn format_reload_high_cluster() {
+        // FAT16 with the default layout for the size
+        for (fat_type, total_size) in [
+            (Some(FatType::Fat12), 1024 * 1024),
+            (None, 64 * 1024 * 1024),
+            (Some(FatType::Fat32), 40 * 1024 * 1024),
+        ] {
+            let params = FormatParams {
+                fat_type,
+                ..FormatParams::new(total_size)
+            };
+
+            let mut fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+            let fat_type = fat_fs.fat_type();
+            let cluster = *fat_fs.fat.valid_clusters().end();
+
+            fat_fs.fat.set_next_cluster(cluster, None);
+            fat_fs.free_count -= 1;
+            fat_fs.flush().unwrap();
+
+            let mut image = vec![0; fat_fs.inner.borrow().byte_len().unwrap() as usize];
+            fat_fs.inner.borrow_mut().read_at_offset(0, &mut image).unwrap();
+
+            let reloaded = FatFs::load(image).unwrap();
+
+            assert_eq!(reloaded.fat_type(), fat_type);
+            assert!(!reloaded.fat.is_free(cluster), "{fat_type:?}");
+            assert_eq!(reloaded.fat.count_free_clusters(), fat_fs.free_clusters(), "{fat_type:?}");
+            assert!(reloaded.verify_fat_copies().is_ok());
+        }
+    }
+
+    #[test]
+    fn root_first_cluster() {
+        for (fat_type, total_size, root_first_cluster) in [
+            (FatType::Fat12, 1024 * 1024, 1),
+            (FatType::Fat16, 32 * 1024 * 1024, 1),
+            (FatType::Fat32, 40 * 1024 * 1024, 2),
+        ] {
+            let params = FormatParams {
+                fat_type: Some(fat_type),
+                ..FormatParams::new(total_size)
+            };
+
+            let fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+            assert_eq!(fat_fs.root_first_cluster(), root_first_cluster);
+            assert_eq!(fat_fs.root_cluster().is_some(), fat_type == FatType::Fat32);
+        }
+
+        let fat_fs = load(fat32_image(1));
+
+        assert_eq!(Some(fat_fs.root_first_cluster()), fat_fs.root_cluster());
+    }
+
+    #[test]
+    fn fat_copies() {
+        use super::{FatCopyCheck, FatCopyMismatch, FatFsError};
+
+        let params = FormatParams {
+            fat_type: Some(FatType::Fat16),
+            ..FormatParams::new(8 * 1024 * 1024)
+        };
+
+        let mut fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+        assert!(fat_fs.verify_fat_copies().is_ok());
+
+        // mark entry 5 as EOF in the second copy only
+        let offset = fat_fs.bpb.nth_fat_offset(1) + 5 * 2;
+        fat_fs.inner.borrow_mut().write_at_offset(offset, &[0xFF, 0xFF]).unwrap();
+
+        assert!(matches!(
+            fat_fs.verify_fat_copies(),
+            Err(FatCopyMismatch::Differs { copy: 1, cluster: 5 })
+        ));
+
+        let mut image = vec![0; fat_fs.inner.borrow().byte_len().unwrap() as usize];
+        fat_fs.inner.borrow_mut().read_at_offset(0, &mut image).unwrap();
+
+        assert!(matches!(
+            FatFs::load_with_fat_check(image.clone(), FatCopyCheck::Strict),
+            Err(FatFsError::FatCopyMismatch(FatCopyMismatch::Differs { copy: 1, cluster: 5 }))
+        ));
+        assert!(FatFs::load_with_fat_check(image.clone(), FatCopyCheck::Warn).is_ok());
+        assert!(FatFs::load_with_fat_check(image, FatCopyCheck::Ignore).is_ok());
+
+        assert_eq!(fat_fs.repair_fat_copies().unwrap(), 1);
+        assert!(fat_fs.verify_fat_copies().is_ok());
+        assert_eq!(fat_fs.repair_fat_copies().unwrap(), 0);
+    }
+
+    #[test]
+    fn both_fats_written() {
+        let params = FormatParams {
+            fat_type: Some(FatType::Fat16),
+            ..FormatParams::new(8 * 1024 * 1024)
+        };
+
+        let mut fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+        assert_eq!(fat_fs.num_fats(), 2);
+
+        let read_fat = |fat_fs: &FatFs, n: u8| {
+            let mut buf = vec![0; fat_fs.bpb.fat_len_bytes()];
+
+            fat_fs
+                .inner
+                .borrow_mut()
+                .read_at_offset(fat_fs.bpb.nth_fat_offset(n), &mut buf)
+                .unwrap();
+
+            buf
+        };
+
+        let before = read_fat(&fat_fs, 1);
+
+        let cluster = fat_fs.alloc_cluster(None).unwrap();
+        fat_fs.alloc_cluster(Some(cluster)).unwrap();
+
+        fat_fs.flush_fat().unwrap();
+
+        assert_ne!(read_fat(&fat_fs, 1), before);
+        assert_eq!(read_fat(&fat_fs, 0), read_fat(&fat_fs, 1));
+
+        // a differing copy is only a warning
+        let offset = fat_fs.bpb.nth_fat_offset(1) + 2 * cluster as u64;
+        fat_fs.inner.borrow_mut().write_at_offset(offset, &[0, 0]).unwrap();
+
+        let mut image = vec![0; fat_fs.inner.borrow().byte_len().unwrap() as usize];
+        fat_fs.inner.borrow_mut().read_at_offset(0, &mut image).unwrap();
+
+        assert!(FatFs::load(image).is_ok());
+    }
+
+    #[test]
+    fn fat16_high_cluster_survives_flush() {
+        use super::{FatCopyCheck, FatCopyMismatch};
+
+        let params = FormatParams {
+            fat_type: Some(FatType::Fat16),
+            ..FormatParams::new(32 * 1024 * 1024)
+        };
+
+        let mut fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+        // the last cluster, in the upper half of the FAT
+        let cluster = *fat_fs.fat.valid_clusters().end();
+
+        fat_fs.fat.set_next_cluster(cluster, None);
+        fat_fs.flush_fat().unwrap();
+
+        let mut image = vec![0; fat_fs.inner.borrow().byte_len().unwrap() as usize];
+        fat_fs.inner.borrow_mut().read_at_offset(0, &mut image).unwrap();
+
+        let reloaded = FatFs::load_with_fat_check(image, FatCopyCheck::Strict).unwrap();
+
+        assert!(!reloaded.fat.is_free(cluster));
+        assert_eq!(reloaded.fat.get_next_cluster(cluster).unwrap(), None);
+        assert_eq!(reloaded.fat.count_free_clusters(), fat_fs.fat.count_free_clusters());
+
+        // a difference that high up is found as well
+        let offset = fat_fs.bpb.nth_fat_offset(1) + 2 * cluster as u64;
+        fat_fs.inner.borrow_mut().write_at_offset(offset, &[0, 0]).unwrap();
+
+        assert!(matches!(
+            fat_fs.verify_fat_copies(),
+            Err(FatCopyMismatch::Differs { copy: 1, cluster: c }) if c == cluster
+        ));
+    }
+
+    #[test]
+    fn create_file_short_name() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let dir_entry = fat_fs.create_file(0, "FILE.TXT", Attr::Archive).unwrap();
+
+        assert_eq!(dir_entry.offset(), fat_fs.data_offset());
+        assert_eq!(dir_entry.long_name(), None);
+
+        let on_disk = fat_fs.validate_dir_entry_at(fat_fs.data_offset()).unwrap();
+
+        assert_eq!(on_disk.name(), b"FILE    TXT");
+        assert!(on_disk.is_file());
+        assert_eq!(on_disk.first_cluster(), 0);
+        assert_eq!(on_disk.file_size(), 0);
+
+        assert!(fat_fs.create_file(0, "FILE.TXT", Attr::Archive).is_err());
+    }
+
+    #[test]
+    fn create_file_long_name() {
+        let mut fat_fs = load(fat32_image(1));
+
+        fat_fs.create_file(0, "FILE.TXT", Attr::Archive).unwrap();
+
+        // 26 UTF-16 code units, so two LFN entries without null terminator
+        let name = "A Rather Long File Name.md";
+
+        let dir_entry = fat_fs.create_file(0, name, Attr::Archive).unwrap();
+
+        assert_eq!(dir_entry.name(), b"ARATHE~1MD ");
+        assert_eq!(dir_entry.long_name(), Some(name));
+        assert_eq!(dir_entry.n_longname_slots(), 2);
+
+        // after the first file and the two LFN entries
+        assert_eq!(dir_entry.offset(), fat_fs.data_offset() + 3 * 32);
+
+        let found = fat_fs.dir_iter_or_root(0).find_by_name(name).unwrap();
+
+        assert_eq!(found.offset(), dir_entry.offset());
+        assert_eq!(found.n_longname_slots(), 2);
+
+        let lower = fat_fs.create_file(0, "lower.txt", Attr::Archive).unwrap();
+
+        assert_eq!(lower.name(), b"LOWER   TXT");
+        assert_eq!(lower.n_longname_slots(), 1);
+        assert!(fat_fs.dir_iter_or_root(0).find_by_name("lower.txt").is_some());
+    }
+
+    #[test]
+    fn create_file_long_name_across_clusters() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let slots_per_cluster = fat_fs.bytes_per_cluster() / 32;
+
+        // leave two free slots at the end of the root dir's only cluster
+        for i in 0..slots_per_cluster - 2 {
+            fat_fs.create_file(0, &format!("FILE{i}"), Attr::Archive).unwrap();
+        }
+
+        // three LFN entries, the last one ends up in a new cluster together with the 8.3 entry
+        let name = "Some Document With A Long Name.txt";
+
+        let dir_entry = fat_fs.create_file(0, name, Attr::Archive).unwrap();
+
+        assert_eq!(dir_entry.n_longname_slots(), 3);
+        assert_eq!(fat_fs.count_clusters_in_chain(fat_fs.root_cluster().unwrap()).unwrap(), 2);
+
+        let found = fat_fs.dir_iter_or_root(0).find_by_name(name).unwrap();
+
+        assert_eq!(found.long_name(), Some(name));
+        assert_eq!(found.offset(), dir_entry.offset());
+    }
+
+    #[test]
+    fn write_lfn_entries() {
+        let fat_fs = load(fat32_image(1));
+
+        let name = "Some Document With A Long Name.txt";
+        let short_name = *b"SOMEDO~1TXT";
+
+        let checksum = DirEntry::checksum(&short_name);
+
+        let offset = dir::write_lfn_entries(&fat_fs, fat_fs.data_offset(), name, checksum).unwrap();
+
+        // 34 UTF-16 code units need three entries
+        assert_eq!(offset, fat_fs.data_offset() + 3 * 32);
+
+        let mut dir_entry = DirEntry::create(short_name, Attr::Archive).unwrap();
+        dir_entry.write_at(&fat_fs, offset).unwrap();
+
+        let dir_entry = fat_fs.root_dir_iter().next().unwrap();
+
+        assert_eq!(dir_entry.name(), &short_name);
+        assert_eq!(dir_entry.long_name(), Some(name));
+        assert_eq!(dir_entry.n_longname_slots(), 3);
+        assert_eq!(dir_entry.offset(), offset);
+
+        assert!(dir::write_lfn_entries(&fat_fs, offset, "", 0).is_err());
+    }
+
+    #[test]
+    fn slice_dir_reader() {
+        use crate::dir::{DirIter, SliceDirReader};
+
+        let name = "Some Document With A Long Name.txt";
+        let short_name = *b"SOMEDO~1TXT";
+
+        let mut bytes: Vec<u8> = dir::long_name_entries(name, DirEntry::checksum(&short_name))
+            .unwrap()
+            .concat();
+
+        bytes.extend(DirEntry::create(short_name, Attr::Archive).unwrap().to_bytes());
+        bytes.extend(DirEntry::create(*b"OTHER   TXT", Attr::Archive).unwrap().to_bytes());
+
+        // a sentinel, the entry behind it must not be returned anymore
+        bytes.extend([0; 32]);
+        bytes.extend(DirEntry::create(*b"HIDDEN  TXT", Attr::Archive).unwrap().to_bytes());
+
+        let mut entries = DirIter::new(SliceDirReader::new(&bytes, 0x1000));
+
+        let dir_entry = entries.next().unwrap();
+
+        assert_eq!(dir_entry.long_name(), Some(name));
+        assert_eq!(dir_entry.offset(), 0x1000 + 3 * 32);
+
+        let dir_entry = entries.next().unwrap();
+
+        assert_eq!(dir_entry.name_string(), "OTHER.TXT");
+        assert_eq!(dir_entry.offset(), 0x1000 + 4 * 32);
+
+        assert!(entries.next().is_none());
+
+        // running out of bytes ends the directory as well
+        let mut entries = DirIter::new(SliceDirReader::new(&bytes[..3 * 32 + 16], 0));
+
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn slice_dir_reader_bad_lfn_ordinal() {
+        use crate::dir::{DirIter, SliceDirReader};
+
+        let lfn_entries = |name: &str, short_name: &[u8; 11]| {
+            dir::long_name_entries(name, DirEntry::checksum(short_name)).unwrap()
+        };
+
+        let mut bytes = Vec::new();
+
+        // start of a name that gets cut off by an entry with ordinal 0, which must not end up as
+        // the name of the following entry
+        bytes.extend(lfn_entries("A Rather Long File Name.md", b"ARATHE~1MD ")[0]);
+
+        let mut bad_entry = lfn_entries("plain.txt", b"PLAIN   TXT")[0];
+        bad_entry[0] = 0x40;
+        bytes.extend(bad_entry);
+
+        bytes.extend(DirEntry::create(*b"PLAIN   TXT", Attr::Archive).unwrap().to_bytes());
+
+        // ordinal 21 exceeds the maximum of 20 entries
+        let name = "Some Document With A Long Name.txt";
+        let short_name = *b"SOMEDO~1TXT";
+
+        let mut bad_entry = lfn_entries(name, &short_name)[0];
+        bad_entry[0] = 0x40 | 21;
+        bytes.extend(bad_entry);
+
+        bytes.extend(lfn_entries(name, &short_name).concat());
+        bytes.extend(DirEntry::create(short_name, Attr::Archive).unwrap().to_bytes());
+
+        let mut entries = DirIter::new(SliceDirReader::new(&bytes, 0));
+
+        let dir_entry = entries.next().unwrap();
+
+        assert_eq!(dir_entry.name_string(), "PLAIN.TXT");
+        assert_eq!(dir_entry.long_name(), None);
+        assert_eq!(dir_entry.offset(), 2 * 32);
+
+        let dir_entry = entries.next().unwrap();
+
+        assert_eq!(dir_entry.name(), &short_name);
+        assert_eq!(dir_entry.long_name(), Some(name));
+        assert_eq!(dir_entry.n_longname_slots(), 3);
+        assert_eq!(dir_entry.offset(), 7 * 32);
+
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn create_dir() {
+        let mut fat_fs = FatFs::format(Vec::new(), FormatParams::new(1024 * 1024)).unwrap();
+
+        let parent = fat_fs.create_dir(0, "PARENT").unwrap();
+
+        assert!(parent.is_dir());
+        assert_eq!(fat_fs.root_dir_iter().next().unwrap().offset(), parent.offset());
+
+        // fill the first cluster of PARENT past . and .., so it has to grow by a cluster
+        let n = fat_fs.bytes_per_cluster / 32;
+
+        let created: Vec<DirEntry> = (0..n)
+            .map(|i| fat_fs.create_dir(parent.first_cluster(), &format!("DIR{i}")).unwrap())
+            .collect();
+
+        assert_eq!(fat_fs.count_clusters_in_chain(parent.first_cluster()).unwrap(), 2);
+
+        let found: Vec<DirEntry> = fat_fs
+            .dir_iter(parent.first_cluster())
+            .filter(|dir_entry| !dir_entry.is_dot_or_dotdot())
+            .collect();
+
+        assert_eq!(found.len(), created.len());
+
+        for (found, created) in found.iter().zip(&created) {
+            assert_eq!(found.name(), created.name());
+            assert_eq!(found.first_cluster(), created.first_cluster());
+            assert_eq!(found.create_time(), created.create_time());
+
+            // also holds for the entries at the start of the second cluster
+            assert_eq!(found.offset(), created.offset());
+            assert_eq!(fat_fs.validate_dir_entry_at(found.offset()).unwrap().name(), found.name());
+        }
+
+        assert!(fat_fs.check_integrity().is_empty());
+    }
+
+    #[test]
+    fn create_nested_dirs() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let dir = fat_fs.create_dir(0, "DIR").unwrap();
+        let subdir = fat_fs.create_dir(dir.first_cluster(), "Sub Directory").unwrap();
+
+        assert!(subdir.is_dir());
+        assert_eq!(subdir.long_name(), Some("Sub Directory"));
+
+        // the new dir lives in the first cluster of DIR, right behind . and .. and one LFN entry
+        let dir_offset = fat_fs.data_cluster_to_offset(dir.first_cluster()).unwrap();
+
+        assert_eq!(subdir.offset(), dir_offset + 3 * 32);
+
+        let found = fat_fs.dir_iter(dir.first_cluster()).find_by_name("Sub Directory").unwrap();
+
+        assert_eq!(found.first_cluster(), subdir.first_cluster());
+
+        let mut entries = fat_fs.dir_iter(subdir.first_cluster());
+
+        let dot = entries.next().unwrap();
+        let dotdot = entries.next().unwrap();
+
+        assert!(dot.is_dot());
+        assert_eq!(dot.first_cluster(), subdir.first_cluster());
+
+        assert!(dotdot.is_dotdot());
+        assert_eq!(dotdot.first_cluster(), dir.first_cluster());
+
+        assert!(entries.next().is_none());
+
+        assert!(fat_fs.create_dir(dir.first_cluster(), "Sub Directory").is_err());
+    }
+
+    #[test]
+    fn dir_entry_round_trip() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let offset = fat_fs.create_file(0, "FILE.TXT", Attr::Archive).unwrap().offset();
+
+        let read_raw = |fat_fs: &FatFs| {
+            let mut buf = [0; 32];
+
+            fat_fs.inner.borrow_mut().read_at_offset(offset, &mut buf).unwrap();
+
+            buf
+        };
+
+        // set a reserved bit, which has to survive being written back
+        let mut raw = read_raw(&fat_fs);
+        raw[12] = 0x08;
+        fat_fs.inner.borrow_mut().write_at_offset(offset, &raw).unwrap();
+
+        let mut dir_entry = fat_fs.validate_dir_entry_at(offset).unwrap();
+
+        dir_entry.set_file_size(1234);
+        dir_entry.write_back(&fat_fs).unwrap();
+
+        let reloaded = fat_fs.validate_dir_entry_at(offset).unwrap();
+
+        assert_eq!(reloaded.file_size(), 1234);
+        assert_eq!(reloaded.name(), dir_entry.name());
+        assert_eq!(reloaded.attr(), dir_entry.attr());
+        assert_eq!(reloaded.first_cluster(), dir_entry.first_cluster());
+        assert_eq!(reloaded.create_time(), dir_entry.create_time());
+        assert_eq!(reloaded.write_time(), dir_entry.write_time());
+
+        // only the size changed
+        raw[28..].copy_from_slice(&1234u32.to_le_bytes());
+
+        assert_eq!(read_raw(&fat_fs), raw);
+    }
+
+    #[test]
+    fn set_name_from_str() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let mut dir_entry = fat_fs.create_file(0, "A Long File Name", Attr::Archive).unwrap();
+
+        dir_entry.set_name_from_str("readme.md").unwrap();
+
+        assert_eq!(dir_entry.name(), b"README  MD ");
+        assert_eq!(dir_entry.name_string(), "README.MD");
+
+        dir_entry.set_name_from_str("NOEXT").unwrap();
+        assert_eq!(dir_entry.name(), b"NOEXT      ");
+
+        for invalid in ["", ".TXT", "TOOLONGNAME.TXT", "FILE.TEXT", "A B.TXT", "A+B", "€.TXT"] {
+            assert!(dir_entry.set_name_from_str(invalid).is_err(), "{invalid}");
+        }
+
+        // failed attempts leave the name alone
+        assert_eq!(dir_entry.name(), b"NOEXT      ");
+    }
+
+    #[cfg(feature = "oem_cp437")]
+    #[test]
+    fn oem_cp437_names() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let offset = fat_fs.create_file(0, "FILE.TXT", Attr::Archive).unwrap().offset();
+
+        // box drawing characters, as found in old DOS menu dirs
+        let mut raw = [0; 32];
+        fat_fs.inner.borrow_mut().read_at_offset(offset, &mut raw).unwrap();
+        raw[..11].copy_from_slice(b"\xC9\xCD\xBB     \xB3  ");
+        fat_fs.inner.borrow_mut().write_at_offset(offset, &raw).unwrap();
+
+        let mut dir_entry = fat_fs.validate_dir_entry_at(offset).unwrap();
+
+        assert_eq!(dir_entry.name_string(), "╔═╗.│");
+
+        dir_entry.set_name_from_str("╚═╝.░▒▓").unwrap();
+        assert_eq!(dir_entry.name(), b"\xC8\xCD\xBC     \xB0\xB1\xB2");
+        assert_eq!(dir_entry.name_string(), "╚═╝.░▒▓");
+
+        // a leading 0xE5 has to be escaped as 0x05
+        dir_entry.set_name_from_str("σ.TXT").unwrap();
+        assert_eq!(dir_entry.name()[0], 0x05);
+        assert_eq!(dir_entry.name_string(), "σ.TXT");
+
+        // not part of CP437
+        assert!(dir_entry.set_name_from_str("€.TXT").is_err());
+    }
+
+    #[cfg(not(feature = "oem_cp437"))]
+    #[test]
+    fn non_ascii_short_names() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let mut dir_entry = fat_fs.create_file(0, "FILE.TXT", Attr::Archive).unwrap();
+
+        assert!(dir_entry.set_name_from_str("╔═╗.TXT").is_err());
+
+        let offset = dir_entry.offset();
+
+        let mut raw = [0; 32];
+        fat_fs.inner.borrow_mut().read_at_offset(offset, &mut raw).unwrap();
+        raw[..3].copy_from_slice(&[0xC9, 0xCD, 0xBB]);
+        fat_fs.inner.borrow_mut().write_at_offset(offset, &raw).unwrap();
+
+        assert_eq!(fat_fs.validate_dir_entry_at(offset).unwrap().name_string(), "???E.TXT");
+    }
+
+    #[test]
+    fn date_time_display() {
+        use chrono::{Local, TimeZone as _};
+
+        use crate::datetime::{Date, Time};
+
+        let dt = Local.with_ymd_and_hms(2020, 5, 7, 3, 42, 10).unwrap();
+
+        let date = Date::from_datetime(dt).unwrap();
+        let time = Time::from_datetime(dt).unwrap();
+
+        assert_eq!(date.to_string(), "2020-05-07");
+        assert_eq!(time.to_string(), "03:42:10");
+
+        assert_eq!(format!("{date:?}"), "Date { year: 2020, month: 5, day: 7 }");
+        assert_eq!(format!("{time:?}"), "Time { hour: 3, minute: 42, second: 10 }");
+
+        // odd seconds can't be represented
+        let time = Time::from_datetime(Local.with_ymd_and_hms(2020, 5, 7, 23, 59, 59).unwrap());
+        assert_eq!(time.unwrap().to_string(), "23:59:58");
+
+        let mut fat_fs = load(fat32_image(1));
+
+        let mut dir_entry = fat_fs.create_file(0, "FILE.TXT", Attr::Archive).unwrap();
+        dir_entry.update_write_time(&fat_fs, dt).unwrap();
+
+        assert!(dir_entry.to_string().contains("2020-05-07 03:42:10"));
+    }
+
+    #[test]
+    fn update_timestamps() {
+        use chrono::{Local, NaiveDate, TimeZone as _};
+
+        let mut fat_fs = load(fat32_image(1));
+
+        let mut dir_entry = fat_fs.create_file(0, "FILE.TXT", Attr::Archive).unwrap();
+
+        let dt = Local.with_ymd_and_hms(2020, 5, 17, 13, 42, 10).unwrap();
+
+        dir_entry.update_write_time(&fat_fs, dt).unwrap();
+        dir_entry.update_access_date(&fat_fs, dt).unwrap();
+
+        let reloaded = fat_fs.validate_dir_entry_at(dir_entry.offset()).unwrap();
+
+        assert_eq!(reloaded.write_time(), dt.naive_local());
+        assert_eq!(reloaded.last_access_date(), NaiveDate::from_ymd_opt(2020, 5, 17).unwrap());
+        assert_eq!(reloaded.create_time(), dir_entry.create_time());
+        assert_eq!(reloaded.name(), b"FILE    TXT");
+    }
+
+    #[test]
+    fn case_insensitive_lookup() {
+        let mut fat_fs = FatFs::format(Vec::new(), FormatParams::new(1024 * 1024)).unwrap();
+
+        let dir_entry = fat_fs.create_file(0, "Hello.txt", Attr::Archive).unwrap();
+
+        assert_eq!(dir_entry.name(), b"HELLO   TXT");
+
+        for name in ["hello.txt", "HELLO.TXT", "Hello.Txt", "Hello.txt"] {
+            let found = fat_fs.root_dir_iter().find_by_name(name).unwrap();
+
+            assert_eq!(found.offset(), dir_entry.offset(), "{name}");
+            assert!(fat_fs.dir_contains(0, name));
+        }
+
+        // differs only in case, so it already exists
+        assert!(fat_fs.create_file(0, "HELLO.TXT", Attr::Archive).is_err());
+
+        // Unicode long names ignore case as well
+        fat_fs.create_file(0, "Ärger.txt", Attr::Archive).unwrap();
+        assert!(fat_fs.dir_contains(0, "äRGER.TXT"));
+
+        fat_fs.set_case_sensitive(true);
+
+        assert!(fat_fs.dir_contains(0, "Hello.txt"));
+        assert!(!fat_fs.dir_contains(0, "hello.txt"));
+        assert!(!fat_fs.dir_contains(0, "HELLO.TXT"));
+        assert!(!fat_fs.dir_contains(0, "äRGER.TXT"));
+
+        fat_fs.set_case_sensitive(false);
+
+        // only changing the case is a real rename
+        let renamed = fat_fs.rename(0, "hello.txt", 0, "HELLO.txt").unwrap();
+
+        assert_eq!(renamed.name(), b"HELLO   TXT");
+        assert_eq!(renamed.name_string(), "HELLO.txt");
+        assert_eq!(fat_fs.root_dir_iter().filter(|e| e.name() == b"HELLO   TXT").count(), 1);
+        let found = fat_fs.root_dir_iter().find_by_name("hello.txt").unwrap();
+        assert_eq!(found.name_string(), "HELLO.txt");
+    }
+
+    #[test]
+    fn resize() {
+        let mut fat_fs = FatFs::format(Vec::new(), FormatParams::new(1024 * 1024)).unwrap();
+
+        let size = fat_fs.inner.borrow().byte_len().unwrap();
+        let cluster_count = fat_fs.cluster_count();
+        let free_clusters = fat_fs.free_clusters();
+        let bytes_per_cluster = fat_fs.bytes_per_cluster as u64;
+
+        assert!(fat_fs.resize(size).is_err());
+
+        // the last FAT sector has room for a few more entries
+        fat_fs.resize(size + 2 * bytes_per_cluster).unwrap();
+
+        assert_eq!(fat_fs.inner.borrow().byte_len().unwrap(), size + 2 * bytes_per_cluster);
+        assert_eq!(fat_fs.cluster_count(), cluster_count + 2);
+        assert_eq!(fat_fs.free_clusters(), free_clusters + 2);
+        assert!(fat_fs.check_integrity().is_empty());
+
+        // but not for thousands
+        assert!(fat_fs.resize(size + 4096 * bytes_per_cluster).is_err());
+
+        // fill the whole disk, so the new clusters are in use
+        let data = vec![0xAB; (free_clusters + 2) as usize * bytes_per_cluster as usize];
+        let first_cluster = fat_fs.write_file_from_slice(0, &data).unwrap();
+
+        assert_eq!(fat_fs.free_clusters(), 0);
+
+        let err = fat_fs.resize(size).unwrap_err();
+        assert!(err.to_string().contains("is in use"), "{err}");
+
+        // nothing changed
+        assert_eq!(fat_fs.inner.borrow().byte_len().unwrap(), size + 2 * bytes_per_cluster);
+        assert_eq!(fat_fs.cluster_count(), cluster_count + 2);
+
+        fat_fs.write_file_from_slice(first_cluster, &[]).unwrap();
+
+        fat_fs.resize(size).unwrap();
+
+        assert_eq!(fat_fs.inner.borrow().byte_len().unwrap(), size);
+        assert_eq!(fat_fs.cluster_count(), cluster_count);
+        assert_eq!(fat_fs.free_clusters(), free_clusters);
+        assert!(fat_fs.check_integrity().is_empty());
+
+        // the shrunk image loads with the new size
+        let mut image = vec![0; size as usize];
+        fat_fs.inner.borrow_mut().read_at_offset(0, &mut image).unwrap();
+
+        let reloaded = FatFs::load(image).unwrap();
+
+        assert_eq!(reloaded.cluster_count(), cluster_count);
+        assert_eq!(reloaded.free_clusters(), free_clusters);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn load_async() {
+        use super::FatFsError;
+
+        let image = fat32_image(1);
+
+        let path = std::env::temp_dir().join(format!("fat-bits-load-async-{}", std::process::id()));
+
+        tokio::fs::write(&path, &image).await.unwrap();
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+
+        let res = FatFs::load_async(file).await;
+
+        let mut fat_fs = res.unwrap();
+        let expected = load(image.clone());
+
+        assert_eq!(fat_fs.fat_type(), expected.fat_type());
+        assert_eq!(fat_fs.free_clusters(), expected.free_clusters());
+        assert_eq!(fat_fs.volume_label(), expected.volume_label());
+
+        let names = |fat_fs: &FatFs| -> Vec<_> {
+            fat_fs.root_dir_iter().map(|dir_entry| dir_entry.name_string()).collect()
+        };
+
+        assert_eq!(names(&fat_fs), names(&expected));
+
+        // changes could never reach the file, so they fail instead
+        let err = fat_fs.create_dir(0, "NEW").unwrap_err();
+
+        assert_eq!(
+            err.downcast_ref::<std::io::Error>().map(std::io::Error::kind),
+            Some(std::io::ErrorKind::ReadOnlyFilesystem)
+        );
+
+        drop(fat_fs);
+
+        assert_eq!(tokio::fs::read(&path).await.unwrap(), image);
+
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        // not a FAT file system, fails before reading everything
+        let path = std::env::temp_dir().join(format!("fat-bits-not-fat-{}", std::process::id()));
+
+        tokio::fs::write(&path, vec![0; 4096]).await.unwrap();
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+
+        let res = FatFs::load_async(file).await;
+
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert!(matches!(res, Err(FatFsError::NotAFatFilesystem { .. })));
+    }
+
+    #[test]
+    fn find_entry() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let a = fat_fs.create_dir(0, "A").unwrap().first_cluster();
+        let b = fat_fs.create_dir(a, "B").unwrap().first_cluster();
+        let deep = fat_fs.create_file(b, "DEEP.TXT", Attr::Archive).unwrap();
+
+        let found = fat_fs.find_entry("/A/B/DEEP.TXT").unwrap().unwrap();
+        assert_eq!(found.offset(), deep.offset());
+
+        // case-insensitive, redundant slashes and .. are fine
+        let found = fat_fs.find_entry("//a/b/../B/deep.txt").unwrap().unwrap();
+        assert_eq!(found.offset(), deep.offset());
+
+        // .. in the root dir stays in the root dir
+        let found = fat_fs.find_entry("/../A/./B/../../A/B/DEEP.TXT").unwrap().unwrap();
+        assert_eq!(found.offset(), deep.offset());
+
+        assert_eq!(fat_fs.find_entry("/A/B").unwrap().unwrap().first_cluster(), b);
+        assert_eq!(fat_fs.find_entry("/A/..").unwrap().unwrap().first_cluster(), 0);
+
+        assert!(fat_fs.find_entry("/A/MISSING").unwrap().is_none());
+        assert!(fat_fs.find_entry("/A/B/DEEP.TXT/X").unwrap().is_none());
+
+        assert!(fat_fs.find_entry("/").is_err());
+        assert!(fat_fs.find_entry("").is_err());
+    }
+
+    #[test]
+    fn walk() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let a = fat_fs.create_dir(0, "A").unwrap().first_cluster();
+        let b = fat_fs.create_dir(a, "B").unwrap().first_cluster();
+        fat_fs.create_file(b, "DEEP.TXT", Attr::Archive).unwrap();
+        fat_fs.create_file(0, "TOP.TXT", Attr::Archive).unwrap();
+
+        let mut paths = Vec::new();
+
+        fat_fs
+            .walk(|path, _| {
+                paths.push(path.to_owned());
+                WalkControl::Continue
+            })
+            .unwrap();
+
+        assert_eq!(
+            paths,
+            [
+                "/A",
+                "/A/.",
+                "/A/..",
+                "/A/B",
+                "/A/B/.",
+                "/A/B/..",
+                "/A/B/DEEP.TXT",
+                "/TOP.TXT"
+            ]
+        );
+
+        paths.clear();
+
+        fat_fs
+            .walk(|path, dir_entry| {
+                paths.push(path.to_owned());
+
+                if dir_entry.name_string() == "B" {
+                    WalkControl::SkipSubtree
+                } else if path == "/A/B/DEEP.TXT" {
+                    unreachable!()
+                } else {
+                    WalkControl::Continue
+                }
+            })
+            .unwrap();
+
+        assert_eq!(paths, ["/A", "/A/.", "/A/..", "/A/B", "/TOP.TXT"]);
+
+        paths.clear();
+
+        fat_fs
+            .walk(|path, _| {
+                paths.push(path.to_owned());
+
+                if path == "/A/B" {
+                    WalkControl::Stop
+                } else {
+                    WalkControl::Continue
+                }
+            })
+            .unwrap();
+
+        assert_eq!(paths, ["/A", "/A/.", "/A/..", "/A/B"]);
+
+        // point B back to A, walking must still terminate
+        let mut dir_b = fat_fs.dir_iter(a).find_by_name("B").unwrap();
+        dir_b.set_first_cluster(a);
+        dir_b.write_back(&fat_fs).unwrap();
+
+        let mut n = 0;
+
+        fat_fs
+            .walk(|_, _| {
+                n += 1;
+                WalkControl::Continue
+            })
+            .unwrap();
+
+        // A and its entries, B, and TOP.TXT
+        assert_eq!(n, 5);
+    }
+
+    #[test]
+    fn remove_dir_entry() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let free_clusters = fat_fs.free_clusters();
+
+        let dir = fat_fs.create_dir(0, "Long Directory Name").unwrap();
+        let cluster = dir.first_cluster();
+
+        assert_eq!(dir.n_longname_slots(), 2);
+        assert_eq!(fat_entry_on_disk(&fat_fs, cluster), 0x0FFFFFFF);
+
+        let offset = dir.offset();
+
+        fat_fs.remove_dir_entry(0, dir).unwrap();
+
+        assert_eq!(fat_fs.free_clusters(), free_clusters);
+        assert_eq!(fat_entry_on_disk(&fat_fs, cluster), 0);
+
+        // short entry and both LFN slots are marked free
+        for slot in 0..3 {
+            let mut first_byte = [0];
+
+            fat_fs
+                .inner
+                .borrow_mut()
+                .read_at_offset(offset - 32 * slot, &mut first_byte)
+                .unwrap();
+
+            assert_eq!(first_byte, [0xE5]);
+        }
+
+        assert!(fat_fs.root_dir_iter().next().is_none());
+    }
+
+    #[test]
+    fn rename_file() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let file = fat_fs.create_file(0, "OLD.TXT", Attr::Archive).unwrap();
+
+        let mut dir_entry = fat_fs.validate_dir_entry_at(file.offset()).unwrap();
+        dir_entry.set_file_size(42);
+        dir_entry.write_back(&fat_fs).unwrap();
+
+        let renamed = fat_fs.rename(0, "OLD.TXT", 0, "A New Name.txt").unwrap();
+
+        assert_eq!(renamed.long_name(), Some("A New Name.txt"));
+        assert_eq!(renamed.file_size(), 42);
+
+        assert!(!fat_fs.dir_contains(0, "OLD.TXT"));
+        assert_eq!(fat_fs.root_dir_iter().find_by_name("A New Name.txt").unwrap().file_size(), 42);
+
+        // replacing an existing file
+        fat_fs.create_file(0, "OTHER", Attr::Archive).unwrap();
+        fat_fs.rename(0, "OTHER", 0, "A New Name.txt").unwrap();
+
+        assert_eq!(fat_fs.root_dir_iter().count(), 1);
+        assert_eq!(fat_fs.root_dir_iter().find_by_name("A New Name.txt").unwrap().file_size(), 0);
+
+        // renaming to itself does nothing
+        fat_fs.rename(0, "A New Name.txt", 0, "A New Name.txt").unwrap();
+
+        assert_eq!(fat_fs.root_dir_iter().count(), 1);
+
+        assert!(fat_fs.rename(0, "MISSING", 0, "NEW").is_err());
+    }
+
+    #[test]
+    fn rename_dir() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let dir = fat_fs.create_dir(0, "DIR").unwrap();
+        fat_fs.create_file(dir.first_cluster(), "FILE", Attr::Archive).unwrap();
+
+        let renamed = fat_fs.rename(0, "DIR", 0, "RENAMED").unwrap();
+
+        assert_eq!(renamed.first_cluster(), dir.first_cluster());
+        assert!(!fat_fs.dir_contains(0, "DIR"));
+        assert!(fat_fs.dir_contains(renamed.first_cluster(), "FILE"));
+
+        // can't replace a non-empty dir or a file with a dir
+        fat_fs.create_dir(0, "EMPTY").unwrap();
+        fat_fs.create_file(0, "FILE", Attr::Archive).unwrap();
+
+        assert!(fat_fs.rename(0, "EMPTY", 0, "RENAMED").is_err());
+        assert!(fat_fs.rename(0, "EMPTY", 0, "FILE").is_err());
+
+        let free_clusters = fat_fs.free_clusters();
+
+        // replacing an empty dir frees its cluster
+        fat_fs.rename(0, "RENAMED", 0, "EMPTY").unwrap();
+
+        assert_eq!(fat_fs.free_clusters(), free_clusters + 1);
+        assert!(fat_fs.dir_contains(dir.first_cluster(), "FILE"));
+    }
+
+    #[test]
+    fn move_between_dirs() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let a = fat_fs.create_dir(0, "A").unwrap().first_cluster();
+        let b = fat_fs.create_dir(0, "B").unwrap().first_cluster();
+
+        fat_fs.create_file(a, "FILE", Attr::Archive).unwrap();
+        let sub = fat_fs.create_dir(a, "SUB").unwrap().first_cluster();
+
+        fat_fs.rename(a, "FILE", b, "MOVED").unwrap();
+
+        assert!(!fat_fs.dir_contains(a, "FILE"));
+        assert!(fat_fs.dir_contains(b, "MOVED"));
+
+        fat_fs.rename(a, "SUB", b, "SUB").unwrap();
+
+        let dotdot = fat_fs.dir_iter(sub).find(|dir_entry| dir_entry.is_dotdot()).unwrap();
+
+        assert_eq!(dotdot.first_cluster(), b);
+
+        // and back to the root, where .. points to cluster 0
+        fat_fs.rename(b, "SUB", 0, "SUB").unwrap();
+
+        let dotdot = fat_fs.dir_iter(sub).find(|dir_entry| dir_entry.is_dotdot()).unwrap();
+
+        assert_eq!(dotdot.first_cluster(), 0);
+
+        // a dir can't be moved into itself
+        assert!(fat_fs.rename(0, "B", b, "B").is_err());
+        assert!(fat_fs.rename(0, "SUB", sub, "X").is_err());
+    }
+
+    /// follow the chain starting at `first_cluster` in the FAT on disk
+    fn chain_on_disk(fat_fs: &FatFs, first_cluster: u32) -> Vec<u32> {
+        let mut chain = vec![first_cluster];
+
+        loop {
+            let entry = fat_entry_on_disk(fat_fs, *chain.last().unwrap());
+
+            if entry >= 0x0FFFFFF8 {
+                return chain;
+            }
+
+            chain.push(entry);
+        }
+    }
+
+    #[test]
+    fn truncate() {
+        use std::io::{Read as _, Write as _};
+
+        let mut fat_fs = load(fat32_image(1));
+
+        let bytes_per_cluster = fat_fs.bytes_per_cluster() as u64;
+        let free_clusters = fat_fs.free_clusters();
+
+        let first_cluster = fat_fs.truncate(0, 3 * bytes_per_cluster).unwrap();
+
+        assert_eq!(chain_on_disk(&fat_fs, first_cluster).len(), 3);
+        assert_eq!(fat_fs.free_clusters(), free_clusters - 3);
+
+        fat_fs
+            .file_writer(first_cluster)
+            .write_all(&vec![0xAA; 3 * bytes_per_cluster as usize])
+            .unwrap();
+
+        // to the middle of the second cluster
+        assert_eq!(
+            fat_fs.truncate(first_cluster, bytes_per_cluster * 3 / 2).unwrap(),
+            first_cluster
+        );
+
+        assert_eq!(chain_on_disk(&fat_fs, first_cluster).len(), 2);
+        assert_eq!(fat_fs.free_clusters(), free_clusters - 2);
+
+        // grown again, new clusters are zeroed
+        assert_eq!(fat_fs.truncate(first_cluster, 4 * bytes_per_cluster).unwrap(), first_cluster);
+
+        let chain = chain_on_disk(&fat_fs, first_cluster);
+
+        assert_eq!(chain.len(), 4);
+        assert_eq!(fat_fs.free_clusters(), free_clusters - 4);
+
+        let mut buf = vec![0; 4 * bytes_per_cluster as usize];
+        fat_fs.file_reader(first_cluster).read_exact(&mut buf).unwrap();
+
+        assert!(buf[..2 * bytes_per_cluster as usize].iter().all(|&b| b == 0xAA));
+        assert!(buf[2 * bytes_per_cluster as usize..].iter().all(|&b| b == 0));
+
+        // same number of clusters, nothing changes
+        assert_eq!(
+            fat_fs.truncate(first_cluster, 4 * bytes_per_cluster - 1).unwrap(),
+            first_cluster
+        );
+        assert_eq!(chain_on_disk(&fat_fs, first_cluster), chain);
+
+        assert_eq!(fat_fs.truncate(first_cluster, 0).unwrap(), 0);
+
+        assert_eq!(fat_fs.free_clusters(), free_clusters);
+        assert!(chain.iter().all(|&cluster| fat_entry_on_disk(&fat_fs, cluster) == 0));
+    }
+
+    #[test]
+    fn volume_label() {
+        let params = FormatParams {
+            volume_label: *b"BPB LABEL  ",
+            ..FormatParams::new(1024 * 1024)
+        };
+
+        let mut fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+        assert_eq!(fat_fs.volume_label().as_deref(), Some("BPB LABEL"));
+
+        fat_fs.set_volume_label("my disk").unwrap();
+
+        assert_eq!(fat_fs.volume_label().as_deref(), Some("MY DISK"));
+        assert_eq!(fat_fs.bpb().volume_label(), Some(&b"MY DISK    "[..]));
+
+        let entries: Vec<DirEntry> = fat_fs.root_dir_iter().collect();
+
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].is_volume_id());
+
+        // the existing entry is updated in place
+        fat_fs.set_volume_label("other").unwrap();
+
+        assert_eq!(fat_fs.root_dir_iter().count(), 1);
+        assert_eq!(fat_fs.volume_label().as_deref(), Some("OTHER"));
+
+        assert!(fat_fs.set_volume_label("").is_err());
+        assert!(fat_fs.set_volume_label("A.B").is_err());
+        assert!(fat_fs.set_volume_label("TWELVE CHARS").is_err());
+
+        // no valid label field in the BPB, only the root dir is used
+        let mut fat_fs = load(fat32_image(1));
+
+        assert_eq!(fat_fs.volume_label(), None);
+
+        fat_fs.set_volume_label("X").unwrap();
+
+        assert_eq!(fat_fs.volume_label().as_deref(), Some("X"));
+        assert_eq!(fat_fs.bpb().volume_label(), None);
+    }
+
+    #[test]
+    fn attr_predicates() {
+        let system_file = DirEntry::create(*b"IO      SYS", Attr::System | Attr::Hidden).unwrap();
+
+        assert!(system_file.is_file());
+        assert!(!system_file.is_dir());
+        assert!(system_file.is_system());
+        assert!(system_file.is_hidden());
+
+        let system_dir = DirEntry::create(*b"SYSTEM     ", Attr::System | Attr::Directory).unwrap();
+
+        assert!(system_dir.is_dir());
+        assert!(!system_dir.is_file());
+        assert!(system_dir.is_system());
+        assert!(!system_dir.is_hidden());
+
+        let volume_id = DirEntry::create(*b"MY VOLUME  ", Attr::VolumeId).unwrap();
+
+        assert!(volume_id.is_volume_id());
+        assert!(!volume_id.is_file());
+        assert!(!volume_id.is_dir());
+        assert!(!volume_id.is_system());
+
+        let dot = DirEntry::create(*b".          ", Attr::Directory).unwrap();
+        let dotdot = DirEntry::create(*b"..         ", Attr::Directory).unwrap();
+
+        assert!(dot.is_dot_or_dotdot() && dotdot.is_dot_or_dotdot());
+        assert!(!dot.is_hidden() && !dotdot.is_hidden());
+        assert!(!system_dir.is_dot_or_dotdot());
+
+        // the kind of an entry can't be changed through set_attr
+        let mut system_dir = system_dir;
+        system_dir.set_attr(Attr::Hidden);
+
+        assert_eq!(system_dir.attr(), Attr::Hidden | Attr::Directory);
+
+        let mut system_file = system_file;
+        system_file.set_attr(Attr::ReadOnly | Attr::Directory);
+
+        assert_eq!(system_file.attr(), Attr::ReadOnly);
+    }
+
+    #[test]
+    fn check_integrity() {
+        use super::integrity::{DirError, FsError};
+
+        let mut fat_fs = load(fat32_image(1));
+
+        let a = fat_fs.create_dir(0, "A").unwrap().first_cluster();
+        let b = fat_fs.create_dir(a, "B").unwrap().first_cluster();
+
+        let first_cluster = fat_fs.write_file_from_slice(0, &[1; 700]).unwrap();
+        let mut file = fat_fs.create_file(b, "FILE.BIN", Attr::Archive).unwrap();
+        file.set_first_cluster(first_cluster);
+        file.set_file_size(700);
+        file.write_back(&fat_fs).unwrap();
+
+        assert!(fat_fs.check_integrity().is_empty());
+
+        let orphan = fat_fs.alloc_cluster(None).unwrap();
+
+        let errors = fat_fs.check_integrity();
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0],
+            FsError::Dir(DirError::OrphanCluster { cluster }) if cluster == orphan
+        ));
+
+        fat_fs.dealloc_cluster(orphan);
+
+        // /A/B/LOOP points back at /A
+        let mut looping = fat_fs.create_dir(b, "LOOP").unwrap();
+        let lost = looping.first_cluster();
+        looping.set_first_cluster(a);
+        looping.write_back(&fat_fs).unwrap();
+
+        let errors = fat_fs.check_integrity();
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|err| matches!(
+            err,
+            FsError::Dir(DirError::DirectoryLoop { path, ancestor })
+                if path == "/A/B/LOOP" && ancestor == "/A"
+        )));
+        assert!(errors.iter().any(|err| matches!(
+            err,
+            FsError::Dir(DirError::OrphanCluster { cluster }) if *cluster == lost
+        )));
+    }
+
+    #[test]
+    fn validate_bpb() {
+        use super::bpb::Bpb;
+        use super::integrity::{BpbError, validate_bpb};
+
+        let image = fat32_image(1);
+
+        assert!(validate_bpb(&Bpb::load(&image).unwrap()).is_empty());
+
+        // a single FAT sector holds 128 entries, far fewer than the clusters of the volume
+        let mut bytes = image[..512].to_vec();
+        bytes[36..40].copy_from_slice(&1u32.to_le_bytes());
+
+        let errors = validate_bpb(&Bpb::load(&bytes).unwrap());
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0],
+            BpbError::FatTooSmall { capacity: 128, required } if required > 128
+        ));
+
+        // total sectors fit into 16 bits, so set total_sectors_32 to the same value as well
+        let fat_fs = FatFs::format(
+            Vec::new(),
+            FormatParams {
+                fat_type: Some(FatType::Fat12),
+                ..FormatParams::new(1024 * 1024)
+            },
+        )
+        .unwrap();
+
+        let mut bytes = [0; 512];
+        fat_fs.inner.borrow_mut().read_at_offset(0, &mut bytes).unwrap();
+
+        assert!(validate_bpb(&Bpb::load(&bytes).unwrap()).is_empty());
+
+        let total_sectors = u16::from_le_bytes([bytes[19], bytes[20]]);
+        bytes[32..36].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+
+        let errors = validate_bpb(&Bpb::load(&bytes).unwrap());
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0],
+            BpbError::AmbiguousTotalSectors(n16, n32) if n16 == total_sectors && n32 == 2048
+        ));
+    }
+
+    #[test]
+    fn validate_fat_entries() {
+        use super::integrity::{FatEntryError, validate_fat_entries};
+
+        let mut fat_fs = load(fat32_image(1));
+
+        assert!(validate_fat_entries(&fat_fs.fat).is_empty());
+
+        // cluster 4 is still free
+        fat_fs.fat.set_next_cluster(3, Some(4));
+
+        let errors = validate_fat_entries(&fat_fs.fat);
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0], FatEntryError::PointsToFree { cluster: 3, next: 4 }));
+
+        // both 3 and 5 continue at 4
+        fat_fs.fat.set_next_cluster(4, None);
+        fat_fs.fat.set_next_cluster(5, Some(4));
+
+        let errors = validate_fat_entries(&fat_fs.fat);
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            errors[0],
+            FatEntryError::CrossLinked {
+                first: 3,
+                second: 5,
+                next: 4
+            }
+        ));
+    }
+
+    #[test]
+    fn typed_load_errors() {
+        use super::FatFsError;
+
+        assert!(matches!(
+            FatFs::load(vec![0; 512]),
+            Err(FatFsError::NotAFatFilesystem { found: [0, 0] })
+        ));
+
+        // valid signature, but 0 bytes per sector
+        let mut image = vec![0; 512];
+        image[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        assert!(matches!(FatFs::load(image), Err(FatFsError::InvalidBpb(_))));
+
+        // only the boot sector of an otherwise valid image
+        let image = fat32_image(1)[..512].to_vec();
+
+        assert!(matches!(FatFs::load(image), Err(FatFsError::ImageTooSmall { .. })));
+
+        assert!(matches!(FatFs::load(Vec::new()), Err(FatFsError::Io(_))));
+
+        assert!(matches!(fs_info::FsInfo::load(&[0; 512]), Err(FatFsError::InvalidFsInfo(_))));
+
+        // DIR_CrtTimeTenth out of range
+        let mut bytes = [0; 32];
+        bytes[..11].copy_from_slice(b"FILE    TXT");
+        bytes[13] = 200;
+
+        assert!(matches!(DirEntry::load(&bytes, 0), Err(FatFsError::InvalidDirEntry(_))));
+    }
+
+    #[test]
+    fn cluster_zero_is_empty() {
+        use std::io::Read as _;
+
+        let fat_fs = load(fat32_image(1));
+
+        assert!(fat_fs.cluster_as_subslice(0).unwrap().is_empty());
+        assert!(fat_fs.cluster_as_subslice_mut(0).unwrap().is_empty());
+
+        // a directory with first cluster 0 has no entries, not even . and ..
+        assert_eq!(fat_fs.dir_iter(0).count(), 0);
+        assert_eq!(fat_fs.chain_reader(0).read(&mut [0; 32]).unwrap(), 0);
+    }
+
+    #[test]
+    fn public_accessors() {
+        let fat_fs = load(fat32_image(1));
+
+        let bpb: &super::bpb::Bpb = fat_fs.bpb();
+        let fat: &super::fat::Fat = fat_fs.fat();
+
+        assert_eq!(fat_fs.fat_type(), FatType::Fat32);
+        assert_eq!(bpb.fat_type(), fat_fs.fat_type());
+        assert_eq!(fat.fat_type(), fat_fs.fat_type());
+
+        assert_eq!(bpb.num_fats(), fat_fs.num_fats());
+        assert_eq!(fat.count_free_clusters(), fat_fs.free_clusters());
+
+        assert_eq!(fat_fs.fat_size_bytes(), FAT_SECTORS as usize * 512);
+        assert_eq!(fat_fs.data_size_bytes(), DATA_CLUSTERS as usize * 512);
+        assert_eq!(
+            fat_fs.data_offset() + fat_fs.data_size_bytes() as u64,
+            fat_fs.total_sectors() as u64 * 512
+        );
+    }
+
+    #[test]
+    fn bpb_to_bytes() {
+        let mut images = vec![fat32_image(1)];
+
+        for (fat_type, total_size) in [
+            (FatType::Fat12, 1024 * 1024),
+            (FatType::Fat16, 32 * 1024 * 1024),
+            (FatType::Fat32, 40 * 1024 * 1024),
+        ] {
+            let params = FormatParams {
+                fat_type: Some(fat_type),
+                volume_serial_number: 0x1234_5678,
+                volume_label: *b"ROUND TRIP ",
+                ..FormatParams::new(total_size)
+            };
+
+            let fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+            let mut image = vec![0; 512];
+            fat_fs.inner.borrow_mut().read_at_offset(0, &mut image).unwrap();
+
+            images.push(image);
+        }
+
+        for image in images {
+            let bpb = super::bpb::Bpb::load(&image).unwrap();
+            let bytes = bpb.to_bytes();
+
+            assert_eq!(super::bpb::Bpb::load(&bytes).unwrap(), bpb);
+
+            // everything but the jump instruction is encoded as it was on disk
+            assert_eq!(bytes[3..], image[3..512]);
+        }
+    }
+
+    #[test]
+    fn space_bytes() {
+        use std::io::Write as _;
+
+        for fat_type in [FatType::Fat12, FatType::Fat32] {
+            let total_size = match fat_type {
+                FatType::Fat32 => 40 * 1024 * 1024,
+                _ => 1024 * 1024,
+            };
+
+            let params = FormatParams {
+                fat_type: Some(fat_type),
+                ..FormatParams::new(total_size)
+            };
+
+            let mut fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+            let bytes_per_cluster = fat_fs.bytes_per_cluster() as u64;
+
+            assert_eq!(
+                fat_fs.total_space_bytes(),
+                fat_fs.cluster_count() as u64 * bytes_per_cluster
+            );
+            assert_eq!(
+                fat_fs.used_space_bytes() + fat_fs.free_space_bytes(),
+                fat_fs.total_space_bytes()
+            );
+
+            let used = fat_fs.used_space_bytes();
+
+            // a file of two and a bit clusters takes up three whole clusters
+            let data = vec![0xAB; 2 * bytes_per_cluster as usize + 1];
+
+            fat_fs.create_file(0, "DATA.BIN", Attr::Archive).unwrap();
+            let first_cluster = fat_fs.truncate(0, data.len() as u64).unwrap();
+            fat_fs.file_writer(first_cluster).write_all(&data).unwrap();
+
+            assert_eq!(fat_fs.used_space_bytes(), used + 3 * bytes_per_cluster);
+            assert_eq!(
+                fat_fs.free_space_bytes(),
+                fat_fs.free_clusters() as u64 * bytes_per_cluster
+            );
+        }
+    }
+
+    #[test]
+    fn dir_entry_to_bytes() {
+        // xorshift32, deterministic so failures are reproducible
+        let mut state: u32 = 0x1234_5678;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+
+        for _ in 0..256 {
+            let mut bytes = [0; 32];
+
+            for byte in &mut bytes {
+                *byte = next() as u8;
+            }
+
+            // only values that load accepts and keeps as they are
+            bytes[11] &= 0x3F;
+            bytes[13] %= 200;
+
+            for offset in [16, 18, 24] {
+                let month = (next() % 13) as u8;
+
+                bytes[offset] = (bytes[offset] & !0xE0) | (month & 0x07) << 5;
+                bytes[offset + 1] = (bytes[offset + 1] & !0x01) | month >> 3;
+            }
+
+            if bytes[11] & Attr::VolumeId.bits() != 0 {
+                bytes[20..22].fill(0);
+                bytes[26..28].fill(0);
+            }
+
+            if bytes[11] & Attr::Directory.bits() != 0 {
+                bytes[28..].fill(0);
+            }
+
+            let dir_entry = DirEntry::load(&bytes, 64).unwrap();
+
+            assert_eq!(dir_entry.to_bytes(), bytes);
+            assert_eq!(DirEntry::load(&dir_entry.to_bytes(), 64).unwrap(), dir_entry);
+        }
+    }
+
+    #[test]
+    fn long_name_entries() {
+        // name characters of an LFN entry, in order
+        fn chars(entry: &[u8; 32]) -> Vec<u16> {
+            entry[1..11]
+                .chunks_exact(2)
+                .chain(entry[14..26].chunks_exact(2))
+                .chain(entry[28..32].chunks_exact(2))
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect()
+        }
+
+        let name = "a long file name.txt";
+
+        let entries = dir::long_name_entries(name, 0xA5).unwrap();
+
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0][0], 0x42);
+        assert_eq!(entries[1][0], 0x01);
+
+        for entry in &entries {
+            assert_eq!(entry[11], Attr::LongName.bits());
+            assert_eq!(entry[12], 0);
+            assert_eq!(entry[13], 0xA5);
+            assert_eq!(entry[26..28], [0, 0]);
+        }
+
+        let mut expected: Vec<u16> = name.encode_utf16().collect();
+        expected.push(0);
+        expected.resize(26, 0xFFFF);
+
+        let mut encoded = chars(&entries[1]);
+        encoded.extend(chars(&entries[0]));
+
+        assert_eq!(encoded, expected);
+
+        // exactly filling an entry means no terminator
+        let entries = dir::long_name_entries("thirteen char", 0).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0][0], 0x41);
+        assert_eq!(chars(&entries[0]), "thirteen char".encode_utf16().collect::<Vec<_>>());
+
+        assert!(dir::long_name_entries("", 0).is_err());
+        assert!(dir::long_name_entries(&"a".repeat(256), 0).is_err());
+        assert_eq!(dir::long_name_entries(&"a".repeat(255), 0).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn read_write_file_vec() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let bytes_per_cluster = fat_fs.bytes_per_cluster();
+        let free_clusters = fat_fs.free_clusters();
+
+        let data: Vec<u8> = (0..bytes_per_cluster * 2 + 17).map(|i| i as u8).collect();
+
+        let first_cluster = fat_fs.write_file_from_slice(0, &data).unwrap();
+
+        assert_eq!(chain_on_disk(&fat_fs, first_cluster).len(), 3);
+        assert_eq!(fat_fs.free_clusters(), free_clusters - 3);
+
+        assert_eq!(fat_fs.read_file_to_vec(first_cluster, data.len() as u32).unwrap(), data);
+
+        // shorter contents shrink the chain, nothing past the size is returned
+        let first_cluster = fat_fs.write_file_from_slice(first_cluster, &data[..10]).unwrap();
+
+        assert_eq!(chain_on_disk(&fat_fs, first_cluster).len(), 1);
+        assert_eq!(fat_fs.read_file_to_vec(first_cluster, 10).unwrap(), data[..10]);
+
+        // size larger than the chain
+        assert!(fat_fs.read_file_to_vec(first_cluster, 2 * bytes_per_cluster as u32).is_err());
+
+        assert_eq!(fat_fs.write_file_from_slice(first_cluster, &[]).unwrap(), 0);
+        assert_eq!(fat_fs.free_clusters(), free_clusters);
+
+        assert!(fat_fs.read_file_to_vec(0, 0).unwrap().is_empty());
+        assert!(fat_fs.read_file_to_vec(0, 1).is_err());
+    }
+
+    #[test]
+    fn root_dir_as_subslice() {
+        use std::io::{Read as _, Write as _};
+
+        let params = FormatParams {
+            fat_type: Some(FatType::Fat16),
+            ..FormatParams::new(8 * 1024 * 1024)
+        };
+
+        let fat_fs = FatFs::format(Vec::new(), params).unwrap();
+
+        let root_dir_len = fat_fs.bpb.root_entry_count() as usize * 32;
+
+        let mut sub_slice = fat_fs.root_dir_as_subslice_mut();
+
+        assert_eq!(sub_slice.offset(), fat_fs.bpb.root_directory_offset().unwrap());
+        assert_eq!(sub_slice.len(), root_dir_len);
+
+        sub_slice.write_all(&[0xAB; 32]).unwrap();
+
+        let mut sub_slice = fat_fs.root_dir_as_subslice();
+        let mut buf = vec![0; root_dir_len];
+
+        sub_slice.read_exact(&mut buf).unwrap();
+
+        assert!(buf[..32].iter().all(|&b| b == 0xAB));
+        assert!(buf[32..].iter().all(|&b| b == 0));
+        assert!(sub_slice.is_empty());
+    }
+
+    #[test]
+    fn allocate_clusters() {
+        use super::fat::FatError;
+
+        let mut fat_fs = load(fat32_image(1));
+
+        let free_clusters = fat_fs.free_clusters();
+
+        // root dir is at cluster 2
+        let first_cluster = fat_fs.allocate_clusters(3, None).unwrap();
+
+        assert_eq!(first_cluster, 3);
+        assert_eq!(fat_fs.next_cluster(3).unwrap(), Some(4));
+        assert_eq!(fat_fs.next_cluster(4).unwrap(), Some(5));
+        assert_eq!(fat_fs.next_cluster(5).unwrap(), None);
+        assert_eq!(fat_fs.free_clusters(), free_clusters - 3);
+        assert_eq!(fat_fs.next_free, Some(6));
+
+        assert_eq!(fat_fs.allocate_clusters(2, Some(5)).unwrap(), 6);
+        assert_eq!(fat_fs.count_clusters_in_chain(first_cluster).unwrap(), 5);
+
+        // not the end of a chain, or not allocated at all
+        assert!(matches!(fat_fs.allocate_clusters(1, Some(3)), Err(FatError::InvalidEntry(3))));
+        assert!(matches!(fat_fs.allocate_clusters(1, Some(8)), Err(FatError::InvalidEntry(8))));
+
+        assert!(matches!(
+            fat_fs.allocate_clusters(fat_fs.free_clusters() + 1, None),
+            Err(FatError::NoFreeCluster)
+        ));
+        assert!(matches!(fat_fs.allocate_clusters(0, None), Err(FatError::EmptyChain)));
+
+        assert_eq!(fat_fs.free_clusters(), free_clusters - 5);
+        assert_eq!(fat_fs.next_free, Some(8));
+    }
+
+    #[test]
+    fn count_clusters_in_chain() {
+        use super::fat::FatError;
+
+        let mut fat_fs = load(fat32_image(1));
+
+        let bytes_per_cluster = fat_fs.bytes_per_cluster() as u64;
+
+        assert_eq!(fat_fs.count_clusters_in_chain(0).unwrap(), 0);
+        assert_eq!(fat_fs.count_clusters_in_chain(2).unwrap(), 1);
+
+        let first_cluster = fat_fs.truncate(0, 3 * bytes_per_cluster).unwrap();
+
+        assert_eq!(fat_fs.count_clusters_in_chain(first_cluster).unwrap(), 3);
+        assert_eq!(fat_fs.bytes_in_chain(first_cluster).unwrap(), 3 * bytes_per_cluster);
+
+        // cached lengths must not survive changes to the chain
+        fat_fs.truncate(first_cluster, bytes_per_cluster).unwrap();
+        assert_eq!(fat_fs.count_clusters_in_chain(first_cluster).unwrap(), 1);
+
+        let last_cluster = fat_fs.alloc_cluster(Some(first_cluster)).unwrap();
+        assert_eq!(fat_fs.count_clusters_in_chain(first_cluster).unwrap(), 2);
+
+        fat_fs.fat.set_next_cluster(last_cluster, Some(first_cluster));
+        fat_fs.chain_lengths.get_mut().clear();
+
+        assert!(matches!(
+            fat_fs.count_clusters_in_chain(first_cluster),
+            Err(FatError::CyclicChain(cluster)) if cluster == first_cluster
+        ));
+
+        // free or out of range start clusters are rejected and never cached
+        assert!(matches!(fat_fs.count_clusters_in_chain(9), Err(FatError::FreeCluster)));
+        assert!(matches!(fat_fs.count_clusters_in_chain(1), Err(FatError::InvalidEntry(1))));
+        assert!(fat_fs.count_clusters_in_chain(u32::MAX).is_err());
+        assert!(!fat_fs.chain_lengths.borrow().contains_key(&9));
+    }
+
+    #[test]
+    fn truncate_out_of_space() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let free_clusters = fat_fs.free_clusters();
+
+        // only a few clusters are backed by the image, so zeroing the rest fails
+        assert!(fat_fs.truncate(0, 100 * fat_fs.bytes_per_cluster() as u64).is_err());
+
+        assert_eq!(fat_fs.free_clusters(), free_clusters);
+        assert!(fat_fs.fat.is_free(3));
+    }
+
+    /// image that can be read, but refuses all writes
+    struct WriteProtected(&'static mut [u8]);
+
+    impl SliceLike for WriteProtected {
+        fn read_at_offset(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+            self.0.read_at_offset(offset, buf)
+        }
+
+        fn write_at_offset(&mut self, _offset: u64, _bytes: &[u8]) -> std::io::Result<()> {
+            Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+        }
+
+        fn byte_len(&self) -> std::io::Result<u64> {
+            self.0.byte_len()
+        }
+    }
+
+    #[test]
+    fn transaction_rollback() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let free_clusters = fat_fs.free_clusters();
+
+        let res: anyhow::Result<()> = fat_fs.in_transaction(|fat_fs| {
+            let first = fat_fs.alloc_cluster(None).unwrap();
+            fat_fs.alloc_cluster(Some(first)).unwrap();
+
+            anyhow::bail!("oops");
+        });
+
+        assert!(res.is_err());
+        assert_eq!(fat_fs.free_clusters(), free_clusters);
+        assert!(fat_fs.fat.is_free(3));
+        assert!(fat_fs.fat.is_free(4));
+    }
+
+    #[test]
+    fn nested_transaction_rollback() {
+        let mut fat_fs = load(fat32_image(1));
+
+        let free_clusters = fat_fs.free_clusters();
+
+        let outer = fat_fs
+            .in_transaction(|fat_fs| {
+                let outer = fat_fs.alloc_cluster(None).unwrap();
+
+                let res: anyhow::Result<()> = fat_fs.in_transaction(|fat_fs| {
+                    fat_fs.alloc_cluster(Some(outer)).unwrap();
+
+                    anyhow::bail!("oops");
+                });
+
+                assert!(res.is_err());
+
+                Ok(outer)
+            })
+            .unwrap();
+
+        // only the inner allocation got undone
+        assert_eq!(fat_fs.free_clusters(), free_clusters - 1);
+        assert!(!fat_fs.fat.is_free(outer));
+        assert_eq!(fat_fs.next_cluster(outer).unwrap(), None);
+    }
+
+    #[test]
+    fn create_dir_write_protected() {
+        let data: &'static mut [u8] = Box::leak(fat32_image(1).into_boxed_slice());
+
+        let mut fat_fs = FatFs::load(WriteProtected(data)).unwrap();
+
+        let free_clusters = fat_fs.free_clusters();
+
+        assert!(fat_fs.create_dir(0, "subdir").is_err());
+
+        assert_eq!(fat_fs.free_clusters(), free_clusters);
+        assert!(fat_fs.fat.is_free(3));
+    }
+
+    fn fat_entry_on_disk(fat_fs: &FatFs, cluster: u32) -> u32 {
+        let mut buf = [0; 4];
+
+        fat_fs
+            .inner
+            .borrow_mut()
+            .read_at_offset(fat_fs.fat_offset() + 4 * cluster as u64, &mut buf)
+            .unwrap();
+
+        u32::from_le_bytes(buf)
+    }
+
+    #[test]
+    fn writer_extends_chain() {
+        use std::io::{Read as _, Write as _};
+
+        let mut fat_fs = load(fat32_image(1));
+
+        let cluster = fat_fs.alloc_cluster(None).unwrap();
+        let free_clusters = fat_fs.free_clusters();
+
+        // a bit more than two and a half clusters
+        let data: Vec<u8> = (0..fat_fs.bytes_per_cluster() * 5 / 2 + 7)
+            .map(|i| i as u8)
+            .collect();
+
+        {
+            let mut writer = fat_fs.file_writer(cluster);
+
+            writer.write_all(&data).unwrap();
+            writer.finish().unwrap();
+        }
+
+        assert_eq!(fat_fs.free_clusters(), free_clusters - 2);
+
+        // chain is contiguous and written back
+        assert_eq!(fat_entry_on_disk(&fat_fs, cluster), cluster + 1);
+        assert_eq!(fat_entry_on_disk(&fat_fs, cluster + 1), cluster + 2);
+        assert_eq!(fat_entry_on_disk(&fat_fs, cluster + 2), 0x0FFFFFFF);
+
+        let mut buf = vec![0; data.len()];
+
+        fat_fs.file_reader(cluster).read_exact(&mut buf).unwrap();
+
+        assert_eq!(buf, data);
     }
 }
@@ -5,6 +5,32 @@ pub trait SliceLike {
     fn read_at_offset(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()>;
 
     fn write_at_offset(&mut self, offset: u64, bytes: &[u8]) -> std::io::Result<()>;
+
+    /// total length in bytes
+    fn byte_len(&self) -> std::io::Result<u64>;
+
+    fn is_empty(&self) -> std::io::Result<bool> {
+        Ok(self.byte_len()? == 0)
+    }
+
+    /// make sure all writes have reached the underlying storage
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// grow or shrink to `new_len` bytes
+    ///
+    /// fails for fixed-size data
+    fn resize(&mut self, new_len: u64) -> std::io::Result<()> {
+        if new_len == self.byte_len()? {
+            return Ok(());
+        }
+
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "can't resize fixed-size data",
+        ))
+    }
 }
 
 impl SliceLike for &mut [u8] {
@@ -37,6 +63,39 @@ impl SliceLike for &mut [u8] {
 
         Ok(())
     }
+
+    fn byte_len(&self) -> std::io::Result<u64> {
+        Ok(<[u8]>::len(self) as u64)
+    }
+}
+
+impl SliceLike for Vec<u8> {
+    fn read_at_offset(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        self.as_mut_slice().read_at_offset(offset, buf)
+    }
+
+    /// grows the vector (padding with zeros) if the write extends past the end
+    fn write_at_offset(&mut self, offset: u64, bytes: &[u8]) -> std::io::Result<()> {
+        let end = offset as usize + bytes.len();
+
+        if end > Vec::len(self) {
+            Vec::resize(self, end, 0);
+        }
+
+        self[offset as usize..end].copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    fn byte_len(&self) -> std::io::Result<u64> {
+        Ok(Vec::len(self) as u64)
+    }
+
+    fn resize(&mut self, new_len: u64) -> std::io::Result<()> {
+        Vec::resize(self, new_len as usize, 0);
+
+        Ok(())
+    }
 }
 
 impl SliceLike for File {
@@ -55,4 +114,272 @@ impl SliceLike for File {
 
         Ok(())
     }
+
+    fn byte_len(&self) -> std::io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::Write::flush(self)?;
+
+        self.sync_data()
+    }
+
+    fn resize(&mut self, new_len: u64) -> std::io::Result<()> {
+        self.set_len(new_len)
+    }
+}
+
+#[cfg(feature = "memmap")]
+impl SliceLike for memmap2::MmapMut {
+    fn read_at_offset(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        (&mut self[..]).read_at_offset(offset, buf)
+    }
+
+    fn write_at_offset(&mut self, offset: u64, bytes: &[u8]) -> std::io::Result<()> {
+        (&mut self[..]).write_at_offset(offset, bytes)
+    }
+
+    fn byte_len(&self) -> std::io::Result<u64> {
+        Ok(<[u8]>::len(self) as u64)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        memmap2::MmapMut::flush(self)
+    }
+}
+
+/// read-only mapping, all writes fail
+#[cfg(feature = "memmap")]
+impl SliceLike for memmap2::Mmap {
+    fn read_at_offset(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        if offset as usize + buf.len() > <[u8]>::len(self) {
+            return Err(std::io::Error::other(anyhow::anyhow!(
+                "reading {} bytes at offset {} is out of bounds for mapping of len {}",
+                buf.len(),
+                offset,
+                <[u8]>::len(self)
+            )));
+        }
+
+        buf.copy_from_slice(&self[offset as usize..][..buf.len()]);
+
+        Ok(())
+    }
+
+    fn write_at_offset(&mut self, offset: u64, bytes: &[u8]) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("can't write {} bytes at offset {} to read-only mapping", bytes.len(), offset),
+        ))
+    }
+
+    fn byte_len(&self) -> std::io::Result<u64> {
+        Ok(<[u8]>::len(self) as u64)
+    }
+}
+
+/// async counterpart of `SliceLike`, used by `FatFs::load_async`
+#[cfg(feature = "tokio")]
+pub trait AsyncSliceLike {
+    fn read_at_offset(
+        &mut self,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> impl Future<Output = std::io::Result<()>>;
+
+    fn write_at_offset(
+        &mut self,
+        offset: u64,
+        bytes: &[u8],
+    ) -> impl Future<Output = std::io::Result<()>>;
+
+    /// total length in bytes
+    fn byte_len(&self) -> impl Future<Output = std::io::Result<u64>>;
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncSliceLike for tokio::fs::File {
+    async fn read_at_offset(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        use tokio::io::{AsyncReadExt as _, AsyncSeekExt as _};
+
+        self.seek(SeekFrom::Start(offset)).await?;
+
+        self.read_exact(buf).await?;
+
+        Ok(())
+    }
+
+    async fn write_at_offset(&mut self, offset: u64, bytes: &[u8]) -> std::io::Result<()> {
+        use tokio::io::{AsyncSeekExt as _, AsyncWriteExt as _};
+
+        self.seek(SeekFrom::Start(offset)).await?;
+
+        self.write_all(bytes).await?;
+
+        Ok(())
+    }
+
+    async fn byte_len(&self) -> std::io::Result<u64> {
+        Ok(self.metadata().await?.len())
+    }
+}
+
+/// in-memory copy of a volume read by `FatFs::load_async`
+///
+/// changes could never reach the async storage the copy was read from, so all writes fail
+/// instead of silently only changing the copy
+#[cfg(feature = "tokio")]
+pub(crate) struct AsyncSnapshot(pub(crate) Vec<u8>);
+
+#[cfg(feature = "tokio")]
+impl SliceLike for AsyncSnapshot {
+    fn read_at_offset(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        self.0.read_at_offset(offset, buf)
+    }
+
+    fn write_at_offset(&mut self, _offset: u64, _bytes: &[u8]) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::ReadOnlyFilesystem,
+            "FS was loaded with load_async and is read-only",
+        ))
+    }
+
+    fn byte_len(&self) -> std::io::Result<u64> {
+        self.0.byte_len()
+    }
+}
+
+/// run a standard battery of tests against a SliceLike implementation
+///
+/// `make` has to return a zero-initialised SliceLike of the given length
+#[cfg(test)]
+pub fn test_slice_like<S: SliceLike>(make: impl Fn(usize) -> S) {
+    const LEN: usize = 64;
+
+    fn check(slice_like: &mut impl SliceLike, reference: &[u8]) {
+        let mut buf = vec![0; reference.len()];
+
+        slice_like.read_at_offset(0, &mut buf).unwrap();
+
+        assert_eq!(buf, reference);
+    }
+
+    let pattern: Vec<u8> = (1..=8).collect();
+
+    // write-then-read at start, middle, and last valid offset
+    for offset in [0, LEN / 2, LEN - pattern.len()] {
+        let mut slice_like = make(LEN);
+        let mut reference = vec![0; LEN];
+
+        slice_like.write_at_offset(offset as u64, &pattern).unwrap();
+        reference[offset..][..pattern.len()].copy_from_slice(&pattern);
+
+        let mut buf = vec![0; pattern.len()];
+        slice_like.read_at_offset(offset as u64, &mut buf).unwrap();
+        assert_eq!(buf, pattern);
+
+        check(&mut slice_like, &reference);
+    }
+
+    // out of bounds reads and writes fail
+    {
+        let mut slice_like = make(LEN);
+
+        let mut buf = [0; 2];
+        assert!(slice_like.read_at_offset(LEN as u64 - 1, &mut buf).is_err());
+        assert!(slice_like.read_at_offset(LEN as u64 + 1, &mut buf).is_err());
+
+        assert!(slice_like.write_at_offset(LEN as u64 - 1, &[1, 2]).is_err());
+        assert!(slice_like.write_at_offset(LEN as u64 + 1, &[1, 2]).is_err());
+
+        check(&mut slice_like, &[0; LEN]);
+    }
+
+    // length matches the requested length
+    {
+        let slice_like = make(LEN);
+
+        assert_eq!(slice_like.byte_len().unwrap(), LEN as u64);
+    }
+
+    // zero-length reads and writes succeed
+    {
+        let mut slice_like = make(LEN);
+
+        slice_like.read_at_offset(0, &mut []).unwrap();
+        slice_like.read_at_offset(LEN as u64, &mut []).unwrap();
+
+        slice_like.write_at_offset(0, &[]).unwrap();
+        slice_like.write_at_offset(LEN as u64, &[]).unwrap();
+
+        check(&mut slice_like, &[0; LEN]);
+    }
+
+    // overlapping writes: later write wins
+    {
+        let mut slice_like = make(LEN);
+        let mut reference = vec![0; LEN];
+
+        slice_like.write_at_offset(4, &[1; 8]).unwrap();
+        slice_like.write_at_offset(8, &[2; 8]).unwrap();
+
+        reference[4..12].fill(1);
+        reference[8..16].fill(2);
+
+        check(&mut slice_like, &reference);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SliceLike, test_slice_like};
+
+    #[test]
+    fn slice() {
+        test_slice_like(|n| -> &'static mut [u8] { Box::leak(vec![0; n].into_boxed_slice()) });
+    }
+
+    #[cfg(feature = "memmap")]
+    #[test]
+    fn mmap() {
+        test_slice_like(|n| {
+            let mut mmap = memmap2::MmapMut::map_anon(n).unwrap();
+            mmap.fill(0);
+            mmap
+        });
+
+        let mut mmap = memmap2::MmapMut::map_anon(16).unwrap();
+        mmap.write_at_offset(4, &[1; 4]).unwrap();
+
+        let mut mmap = mmap.make_read_only().unwrap();
+
+        let mut buf = [0; 8];
+        mmap.read_at_offset(0, &mut buf).unwrap();
+        assert_eq!(buf, [0, 0, 0, 0, 1, 1, 1, 1]);
+
+        assert!(mmap.write_at_offset(0, &[2]).is_err());
+        assert!(mmap.read_at_offset(12, &mut buf).is_err());
+    }
+
+    #[test]
+    fn vec_grows_on_write() {
+        let mut vec: Vec<u8> = vec![1; 4];
+
+        vec.write_at_offset(2, &[2; 4]).unwrap();
+        assert_eq!(vec, [1, 1, 2, 2, 2, 2]);
+
+        // gap between old end and write offset is zero-filled
+        vec.write_at_offset(8, &[3]).unwrap();
+        assert_eq!(vec, [1, 1, 2, 2, 2, 2, 0, 0, 3]);
+        assert_eq!(vec.byte_len().unwrap(), 9);
+
+        let mut buf = [0; 2];
+        assert!(vec.read_at_offset(8, &mut buf).is_err());
+        vec.read_at_offset(7, &mut buf).unwrap();
+        assert_eq!(buf, [0, 3]);
+
+        SliceLike::resize(&mut vec, 2).unwrap();
+        assert_eq!(vec, [1, 1]);
+    }
 }
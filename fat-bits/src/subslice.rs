@@ -21,11 +21,14 @@ impl Debug for SubSlice {
     }
 }
 
-impl<'a> SubSlice {
+impl SubSlice {
     pub fn new(data: Rc<RefCell<dyn SliceLike>>, offset: u64, len: usize) -> SubSlice {
         SubSlice { data, offset, len }
     }
 
+    /// absolute offset in the underlying data of the next byte to be read or written
+    ///
+    /// advances with every read, write, and skip
     pub fn offset(&self) -> u64 {
         self.offset
     }
@@ -63,12 +66,18 @@ impl Read for SubSlice {
     }
 }
 
+/// called with the offset and bytes of every write, see `SubSliceMut::with_write_callback`
+type WriteCallback = Box<dyn Fn(u64, &[u8])>;
+
 pub struct SubSliceMut {
     // fat_fs: &'a FatFs,
     data: Rc<RefCell<dyn SliceLike>>,
 
     offset: u64,
     len: usize,
+
+    write_callback: Option<WriteCallback>,
+    dry_run: bool,
 }
 
 impl Debug for SubSliceMut {
@@ -76,17 +85,43 @@ impl Debug for SubSliceMut {
         f.debug_struct("SubSliceMut")
             .field("offset", &self.offset)
             .field("len", &self.len)
+            .field("dry_run", &self.dry_run)
             .finish()
     }
 }
 
 impl SubSliceMut {
     pub fn new(data: Rc<RefCell<dyn SliceLike>>, offset: u64, len: usize) -> SubSliceMut {
-        SubSliceMut { data, offset, len }
+        SubSliceMut {
+            data,
+            offset,
+            len,
+            write_callback: None,
+            dry_run: false,
+        }
+    }
+
+    /// call `f` with the offset and bytes of every successful write
+    pub fn with_write_callback(mut self, f: impl Fn(u64, &[u8]) + 'static) -> Self {
+        self.write_callback = Some(Box::new(f));
+
+        self
+    }
+
+    /// don't actually write anything, but pretend all writes succeed
+    ///
+    /// a write callback still gets called for every (simulated) write
+    pub fn dry_run_mode(mut self) -> Self {
+        self.dry_run = true;
+
+        self
     }
 }
 
-impl<'a> SubSliceMut {
+impl SubSliceMut {
+    /// absolute offset in the underlying data of the next byte to be read or written
+    ///
+    /// advances with every read, write, and skip
     pub fn offset(&self) -> u64 {
         self.offset
     }
@@ -128,9 +163,15 @@ impl Write for SubSliceMut {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let bytes_to_write = self.len.min(buf.len());
 
-        self.data
-            .borrow_mut()
-            .write_at_offset(self.offset, &buf[..bytes_to_write])?;
+        if !self.dry_run {
+            self.data
+                .borrow_mut()
+                .write_at_offset(self.offset, &buf[..bytes_to_write])?;
+        }
+
+        if let Some(write_callback) = &self.write_callback {
+            write_callback(self.offset, &buf[..bytes_to_write]);
+        }
 
         self.offset += bytes_to_write as u64;
         self.len -= bytes_to_write;
@@ -142,3 +183,54 @@ impl Write for SubSliceMut {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::Write as _;
+    use std::rc::Rc;
+
+    use super::SubSliceMut;
+
+    #[test]
+    fn write_callback() {
+        let data = Rc::new(RefCell::new(vec![0u8; 16]));
+        let writes = Rc::new(RefCell::new(Vec::new()));
+
+        let mut sub_slice = SubSliceMut::new(data.clone(), 4, 8).with_write_callback({
+            let writes = writes.clone();
+            move |offset, bytes| writes.borrow_mut().push((offset, bytes.to_vec()))
+        });
+
+        sub_slice.write_all(&[1, 2, 3]).unwrap();
+        sub_slice.write_all(&[4, 5]).unwrap();
+
+        // only the part that fits gets written and reported
+        assert_eq!(sub_slice.write(&[6; 8]).unwrap(), 3);
+
+        assert_eq!(*writes.borrow(), [(4, vec![1, 2, 3]), (7, vec![4, 5]), (9, vec![6, 6, 6])]);
+        assert_eq!(data.borrow()[..], [0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 6, 6, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn dry_run_mode() {
+        let data = Rc::new(RefCell::new(vec![0xAAu8; 16]));
+        let writes = Rc::new(RefCell::new(Vec::new()));
+
+        let mut sub_slice = SubSliceMut::new(data.clone(), 2, 4)
+            .with_write_callback({
+                let writes = writes.clone();
+                move |offset, bytes| writes.borrow_mut().push((offset, bytes.to_vec()))
+            })
+            .dry_run_mode();
+
+        assert_eq!(sub_slice.write(&[1, 2]).unwrap(), 2);
+        sub_slice.write_all(&[3, 4]).unwrap();
+
+        assert!(sub_slice.is_empty());
+        assert_eq!(sub_slice.offset(), 6);
+
+        assert_eq!(*writes.borrow(), [(2, vec![1, 2]), (4, vec![3, 4])]);
+        assert_eq!(data.borrow()[..], [0xAA; 16]);
+    }
+}
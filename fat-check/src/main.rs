@@ -0,0 +1,72 @@
+use fat_bits::FatFs;
+use fat_bits::integrity::{DirError, FsError};
+
+const USAGE: &str = "usage: fat-check [--fix] <path>";
+
+pub fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let (path, fix) = match args.as_slice() {
+        [path] => (path, false),
+        [flag, path] if flag == "--fix" => (path, true),
+        _ => anyhow::bail!("{}", USAGE),
+    };
+
+    let file = std::fs::File::options().read(true).write(fix).open(path)?;
+
+    let mut fat_fs = FatFs::load(file)?;
+
+    let errors = fat_fs.check_integrity();
+    let copy_mismatch = fat_fs.verify_fat_copies().err();
+
+    for err in errors.iter() {
+        println!("{}: {}", path, err);
+    }
+
+    if let Some(err) = &copy_mismatch {
+        println!("{}: {}", path, err);
+    }
+
+    let n_errors = errors.len() + copy_mismatch.is_some() as usize;
+
+    if n_errors == 0 {
+        println!("{}: no errors found", path);
+
+        return Ok(());
+    }
+
+    if !fix {
+        anyhow::bail!("found {} errors", n_errors);
+    }
+
+    repair(&mut fat_fs, &errors)?;
+
+    // repairing only covers some kinds of errors, report whatever is left
+    let remaining = fat_fs.check_integrity();
+
+    for err in remaining.iter() {
+        println!("{}: not fixed: {}", path, err);
+    }
+
+    anyhow::ensure!(remaining.is_empty(), "{} errors remain", remaining.len());
+
+    println!("{}: fixed {} errors", path, n_errors);
+
+    Ok(())
+}
+
+/// free orphaned clusters and bring all FAT copies in sync, then write everything back
+fn repair(fat_fs: &mut FatFs, errors: &[FsError]) -> anyhow::Result<()> {
+    for err in errors {
+        if let FsError::Dir(DirError::OrphanCluster { cluster }) = err {
+            fat_fs.dealloc_cluster(*cluster);
+        }
+    }
+
+    // also writes the freed clusters to every copy
+    fat_fs.repair_fat_copies()?;
+
+    fat_fs.flush()?;
+
+    Ok(())
+}
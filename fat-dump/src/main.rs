@@ -1,14 +1,63 @@
-use fat_bits::FatFs;
-use fat_bits::dir::DirEntry;
+use std::collections::HashMap;
+use std::io::Read as _;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use fat_bits::{Attr, DirEntry, FatFs, FatType, FormatParams, WalkControl};
+use walkdir::WalkDir;
+
+const USAGE: &str = "usage: dump [--check] <path>
+       dump --export-all [--abort-on-error] <path> <output-dir>
+       dump extract <path> <fat-path> <output-path>
+       dump inject <path> <local-file> <fat-path>
+       dump --import <host-dir> --output <image-file> [--fat-type fat12|fat16|fat32] [--size <bytes>]";
+
+/// smallest image `--import` creates if no size is given, large enough for FAT32
+const MIN_IMPORT_SIZE: u64 = 64 * 1024 * 1024;
 
 pub fn main() -> anyhow::Result<()> {
-    let args = std::env::args();
+    let args: Vec<String> = std::env::args().skip(1).collect();
 
-    if args.len() != 2 {
-        anyhow::bail!("usage: dump <path>");
-    }
+    let (path, check_only) = match args.as_slice() {
+        [path] => (path, false),
+        [flag, path] if flag == "--check" => (path, true),
+        [flag, path, output_dir] if flag == "--export-all" => {
+            let fat_fs = FatFs::load(std::fs::File::open(path)?)?;
+
+            return export_all(&fat_fs, Path::new(output_dir), false);
+        }
+        [flag, abort, path, output_dir]
+            if flag == "--export-all" && abort == "--abort-on-error" =>
+        {
+            let fat_fs = FatFs::load(std::fs::File::open(path)?)?;
+
+            return export_all(&fat_fs, Path::new(output_dir), true);
+        }
+        [cmd, path, fat_path, output_path] if cmd == "extract" => {
+            let fat_fs = FatFs::load(std::fs::File::open(path)?)?;
+
+            return extract(&fat_fs, fat_path, Path::new(output_path));
+        }
+        [cmd, path, local_file, fat_path] if cmd == "inject" => {
+            let file = std::fs::File::options().read(true).write(true).open(path)?;
+
+            let mut fat_fs = FatFs::load(file)?;
+
+            return inject(&mut fat_fs, Path::new(local_file), fat_path);
+        }
+        [flag, host_dir, options @ ..] if flag == "--import" => {
+            return import(Path::new(host_dir), options);
+        }
+        _ => anyhow::bail!("{}", USAGE),
+    };
+
+    let file = std::fs::File::open(path)?;
+
+    if check_only {
+        let fat_fs = FatFs::load(file)?;
 
-    let file = std::fs::File::open(args.skip(1).next().unwrap())?;
+        return check(&fat_fs);
+    }
 
     // let mut buf = [0; 512];
 
@@ -20,58 +69,367 @@ pub fn main() -> anyhow::Result<()> {
 
     let fat_fs = FatFs::load(file)?;
 
-    // println!("{}", fat_fs.bpb());
-    // println!();
-    // println!("{}", fat_fs.fat());
-
     println!("{}", fat_fs);
     println!();
+    println!("free clusters: {} ({} bytes)", fat_fs.free_clusters(), fat_fs.free_space_bytes());
+    println!("used space: {} of {} bytes", fat_fs.used_space_bytes(), fat_fs.total_space_bytes());
+    println!("volume label: {}", fat_fs.volume_label().as_deref().unwrap_or("<none>"));
+
+    let total_bytes = fat_fs.total_sectors() as u64 * fat_fs.bytes_per_sector() as u64;
+
     println!(
-        "free clusters: {} ({} bytes)",
-        fat_fs.free_clusters(),
-        fat_fs.free_clusters() as usize
-            * fat_fs.bytes_per_sector() as usize
-            * fat_fs.sectors_per_cluster() as usize
+        "Layout: reserved=[0, {:#X}), FAT=[{:#X}, {:#X}), data=[{:#X}, {:#X})",
+        fat_fs.fat_offset(),
+        fat_fs.fat_offset(),
+        fat_fs.data_offset(),
+        fat_fs.data_offset(),
+        total_bytes
     );
 
     println!();
     println!();
 
-    tree(&fat_fs, false);
+    tree(&fat_fs, false)
+}
+
+fn tree(fat_fs: &FatFs, show_hidden: bool) -> anyhow::Result<()> {
+    fat_fs.walk(|path, dir_entry| {
+        if !show_hidden && (dir_entry.is_hidden() || dir_entry.is_dot_or_dotdot()) {
+            return WalkControl::SkipSubtree;
+        }
+
+        // one level of indentation per parent dir
+        let indent = path.matches('/').count() - 1;
+
+        println!("{}{}", "    ".repeat(indent), dir_entry);
+
+        WalkControl::Continue
+    })
+}
+
+fn check(fat_fs: &FatFs) -> anyhow::Result<()> {
+    let errors = fat_fs.check_integrity();
+
+    if errors.is_empty() {
+        println!("no errors found");
+
+        return Ok(());
+    }
+
+    for err in errors.iter() {
+        println!("{}", err);
+    }
+
+    anyhow::bail!("found {} errors", errors.len());
+}
+
+/// copy the single file at `fat_path` out of the FS to `output_path`
+///
+/// fails if `fat_path` does not exist or is not a regular file
+fn extract(fat_fs: &FatFs, fat_path: &str, output_path: &Path) -> anyhow::Result<()> {
+    let Some(dir_entry) = fat_fs.find_entry(fat_path)? else {
+        anyhow::bail!("{} not found", fat_path);
+    };
+
+    anyhow::ensure!(!dir_entry.is_dir(), "{} is a directory", fat_path);
+    anyhow::ensure!(dir_entry.is_file(), "{} is not a regular file", fat_path);
+
+    let mut file = std::fs::File::create(output_path)?;
+
+    if dir_entry.first_cluster() != 0 {
+        let mut reader = fat_fs
+            .file_reader(dir_entry.first_cluster())
+            .take(dir_entry.file_size() as u64);
+
+        let n = std::io::copy(&mut reader, &mut file)?;
+
+        anyhow::ensure!(
+            n == dir_entry.file_size() as u64,
+            "cluster chain ended after {} of {} bytes",
+            n,
+            dir_entry.file_size()
+        );
+    }
+
+    println!("extracted {} ({} bytes)", fat_path, dir_entry.file_size());
 
     Ok(())
 }
 
-fn tree(fat_fs: &FatFs, show_hidden: bool) {
-    fn do_indent(indent: u32) {
-        for _ in 0..indent {
-            print!("    ");
+/// write the local file `local_file` to `fat_path` in the FS
+///
+/// the parent dir of `fat_path` has to exist already. an existing file is overwritten, reusing
+/// as much of its cluster chain as possible
+fn inject(fat_fs: &mut FatFs, local_file: &Path, fat_path: &str) -> anyhow::Result<()> {
+    let data = std::fs::read(local_file)?;
+
+    let file_size = u32::try_from(data.len())
+        .map_err(|_| anyhow::anyhow!("{} is too large for FAT", local_file.display()))?;
+
+    let (parent_path, name) = fat_path.rsplit_once('/').unwrap_or(("", fat_path));
+
+    anyhow::ensure!(!name.is_empty(), "{} has no file name", fat_path);
+
+    let dir_cluster = if parent_path.trim_matches('/').is_empty() {
+        0
+    } else {
+        match fat_fs.find_entry(parent_path)? {
+            Some(dir_entry) if dir_entry.is_dir() => dir_entry.first_cluster(),
+            Some(_) => anyhow::bail!("{} is not a directory", parent_path),
+            None => anyhow::bail!("{} not found", parent_path),
+        }
+    };
+
+    let mut dir_entry = match fat_fs.find_entry(fat_path)? {
+        Some(dir_entry) => {
+            anyhow::ensure!(dir_entry.is_file(), "{} is not a regular file", fat_path);
+
+            dir_entry
+        }
+        None => fat_fs.create_file(dir_cluster, name, Attr::Archive)?,
+    };
+
+    let first_cluster = fat_fs.write_file_from_slice(dir_entry.first_cluster(), &data)?;
+
+    dir_entry.set_first_cluster(first_cluster);
+    dir_entry.set_file_size(file_size);
+    dir_entry.set_write_datetime(chrono::Local::now())?;
+
+    dir_entry.write_back(fat_fs)?;
+
+    fat_fs.flush()?;
+
+    println!("injected {} ({} bytes)", fat_path, file_size);
+
+    Ok(())
+}
+
+/// copy every file in the FS to `output_dir`, recreating the directory structure
+///
+/// keeps going after a failed file unless `abort_on_error` is set, but fails in the end if any
+/// file could not be exported
+fn export_all(fat_fs: &FatFs, output_dir: &Path, abort_on_error: bool) -> anyhow::Result<()> {
+    fn export_file(fat_fs: &FatFs, dir_entry: &DirEntry, path: &Path) -> anyhow::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+
+        if dir_entry.first_cluster() != 0 {
+            // stream the file instead of reading it into memory as a whole
+            let mut reader = fat_fs
+                .file_reader(dir_entry.first_cluster())
+                .take(dir_entry.file_size() as u64);
+
+            let n = std::io::copy(&mut reader, &mut file)?;
+
+            anyhow::ensure!(
+                n == dir_entry.file_size() as u64,
+                "cluster chain ended after {} of {} bytes",
+                n,
+                dir_entry.file_size()
+            );
         }
+
+        let mtime = dir_entry
+            .write_time()
+            .and_local_timezone(chrono::Local)
+            .single()
+            .map(SystemTime::from);
+
+        if let Some(mtime) = mtime {
+            file.set_modified(mtime)?;
+        }
+
+        Ok(())
     }
 
-    fn tree_impl(
+    fn export_dir(
         fat_fs: &FatFs,
         iter: impl Iterator<Item = DirEntry>,
-        show_hidden: bool,
-        indent: u32,
-    ) {
-        for dir_entry in iter.filter(|x| show_hidden || !x.is_hidden()) {
-            do_indent(indent);
+        dir_path: &Path,
+        abort_on_error: bool,
+        n_errors: &mut usize,
+    ) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir_path)?;
 
-            println!("{}", dir_entry);
-
-            if dir_entry.is_dot() || dir_entry.is_dotdot() {
-                // do not descent into . and ..
+        for dir_entry in iter {
+            if dir_entry.is_dot_or_dotdot() {
                 continue;
             }
 
-            if dir_entry.is_dir() {
+            let path = dir_path.join(dir_entry.name_string().as_str());
+
+            let res = if dir_entry.is_dir() {
                 let iter = fat_fs.dir_iter(dir_entry.first_cluster());
 
-                tree_impl(fat_fs, iter, show_hidden, indent + 1);
+                export_dir(fat_fs, iter, &path, abort_on_error, n_errors)
+            } else if dir_entry.is_file() {
+                export_file(fat_fs, &dir_entry, &path)
+            } else {
+                // volume id and the like
+                continue;
+            };
+
+            if let Err(err) = res {
+                if abort_on_error {
+                    return Err(err.context(format!("failed to export {}", path.display())));
+                }
+
+                eprintln!("failed to export {}: {}", path.display(), err);
+
+                *n_errors += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut n_errors = 0;
+
+    export_dir(fat_fs, fat_fs.root_dir_iter(), output_dir, abort_on_error, &mut n_errors)?;
+
+    anyhow::ensure!(n_errors == 0, "failed to export {} files", n_errors);
+
+    Ok(())
+}
+
+/// create a new image from the host directory tree at `host_dir`
+///
+/// `options` are the remaining command line arguments, see `USAGE`. without `--size` the image
+/// is a FAT32 volume large enough to hold everything. last-modified times are preserved where FAT
+/// can represent them
+fn import(host_dir: &Path, options: &[String]) -> anyhow::Result<()> {
+    let mut output = None;
+    let mut fat_type = None;
+    let mut size = None;
+
+    let mut options = options.iter();
+
+    while let Some(option) = options.next() {
+        let Some(value) = options.next() else {
+            anyhow::bail!("{} needs a value\n{}", option, USAGE);
+        };
+
+        match option.as_str() {
+            "--output" => output = Some(PathBuf::from(value)),
+            "--fat-type" => {
+                fat_type = Some(match value.as_str() {
+                    "fat12" => FatType::Fat12,
+                    "fat16" => FatType::Fat16,
+                    "fat32" => FatType::Fat32,
+                    _ => anyhow::bail!("unknown FAT type {}", value),
+                })
+            }
+            "--size" => size = Some(value.parse::<u64>()?),
+            _ => anyhow::bail!("unknown option {}\n{}", option, USAGE),
+        }
+    }
+
+    let Some(output) = output else {
+        anyhow::bail!("--output is required\n{}", USAGE);
+    };
+
+    anyhow::ensure!(host_dir.is_dir(), "{} is not a directory", host_dir.display());
+
+    let size = match (size, fat_type) {
+        (Some(size), _) => size,
+        (None, Some(FatType::Fat12 | FatType::Fat16)) => {
+            anyhow::bail!("--size is required for FAT12 and FAT16")
+        }
+        (None, _) => {
+            fat_type = Some(FatType::Fat32);
+
+            // every file and dir takes up at least one cluster of at most 32 KiB, twice that
+            // leaves plenty of room for the FATs and dir entries
+            let mut needed = 0;
+
+            for entry in WalkDir::new(host_dir) {
+                needed += entry?.metadata()?.len().next_multiple_of(32 * 1024).max(32 * 1024);
             }
+
+            (2 * needed).max(MIN_IMPORT_SIZE)
         }
+    };
+
+    let file = std::fs::File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&output)?;
+
+    let mut fat_fs = FatFs::format(file, FormatParams { fat_type, ..FormatParams::new(size) })?;
+
+    // first cluster of every dir created so far, the root dir is 0
+    let mut dir_clusters: HashMap<PathBuf, u32> = HashMap::new();
+
+    dir_clusters.insert(host_dir.to_owned(), 0);
+
+    let mut n_files = 0;
+    let mut n_dirs = 0;
+
+    // parents are always visited before their contents
+    for entry in WalkDir::new(host_dir).min_depth(1).sort_by_file_name() {
+        let entry = entry?;
+        let path = entry.path();
+
+        let Some(name) = entry.file_name().to_str() else {
+            anyhow::bail!("{} is not valid UTF-8", path.display());
+        };
+
+        let dir_cluster = dir_clusters[path.parent().unwrap()];
+
+        let metadata = entry.metadata()?;
+
+        let mut dir_entry = if metadata.is_dir() {
+            let dir_entry = fat_fs
+                .create_dir(dir_cluster, name)
+                .map_err(|err| err.context(format!("failed to create {}", path.display())))?;
+
+            dir_clusters.insert(path.to_owned(), dir_entry.first_cluster());
+
+            n_dirs += 1;
+
+            dir_entry
+        } else if metadata.is_file() {
+            let data = std::fs::read(path)?;
+
+            let file_size = u32::try_from(data.len())
+                .map_err(|_| anyhow::anyhow!("{} is too large for FAT", path.display()))?;
+
+            let mut dir_entry = fat_fs
+                .create_file(dir_cluster, name, Attr::Archive)
+                .map_err(|err| err.context(format!("failed to create {}", path.display())))?;
+
+            let first_cluster = fat_fs.write_file_from_slice(0, &data)?;
+
+            dir_entry.set_first_cluster(first_cluster);
+            dir_entry.set_file_size(file_size);
+
+            n_files += 1;
+
+            dir_entry
+        } else {
+            eprintln!("skipping {}, it is neither a file nor a directory", path.display());
+
+            continue;
+        };
+
+        // FAT can't represent times before 1980, those keep the time of import
+        if let Err(err) = dir_entry.set_write_datetime(metadata.modified()?) {
+            eprintln!("can't preserve the modification time of {}: {}", path.display(), err);
+        }
+
+        dir_entry.write_back(&fat_fs)?;
     }
 
-    tree_impl(fat_fs, fat_fs.root_dir_iter(), show_hidden, 0);
+    fat_fs.flush()?;
+
+    println!(
+        "imported {} files and {} directories into {} ({} bytes)",
+        n_files,
+        n_dirs,
+        output.display(),
+        size
+    );
+
+    Ok(())
 }
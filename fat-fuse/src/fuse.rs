@@ -3,12 +3,13 @@ use std::io::{Read, Write};
 use std::time::{Duration, SystemTime};
 
 use bitflags::bitflags;
-use fat_bits::dir::DirEntry;
-use fuser::{FileType, Filesystem};
-use libc::{EACCES, EBADF, EINVAL, EIO, EISDIR, ENOENT, ENOSYS, ENOTDIR};
-use log::{debug, error};
+use fuser::Filesystem;
+use libc::{
+    EACCES, EBADF, EINVAL, EIO, EISDIR, ENODATA, ENOENT, ENOSYS, ENOTDIR, ENOTSUP, ERANGE, EROFS,
+};
+use log::{debug, error, warn};
 
-use crate::FatFuse;
+use crate::{FAT_IOCTL_SET_ATTRIBUTES, FatFuse, seek_offset};
 use crate::inode::InodeRef;
 
 const TTL: Duration = Duration::from_secs(1);
@@ -49,6 +50,17 @@ fn get_inode_by_fh_or_ino(fat_fuse: &FatFuse, fh: Option<u64>, ino: u64) -> Resu
     .unwrap_or_else(|| fat_fuse.get_inode(ino).ok_or(ENOENT))
 }
 
+/// reply with the size of `data` if `size` is 0, and with `data` itself if it fits into `size`
+fn reply_xattr(reply: fuser::ReplyXattr, size: u32, data: &[u8]) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if data.len() > size as usize {
+        reply.error(ERANGE);
+    } else {
+        reply.data(data);
+    }
+}
+
 impl Filesystem for FatFuse {
     fn init(
         &mut self,
@@ -67,8 +79,15 @@ impl Filesystem for FatFuse {
         }
 
         debug!("ino_by_fh: {}", self.ino_by_fh.len());
+        for (&fh, &ino) in self.ino_by_fh.iter() {
+            warn!("fh {} was never released (ino {})", fh, ino);
+        }
 
         debug!("ino_by_path: {}", self.ino_by_path.len());
+
+        if let Err(err) = self.flush() {
+            error!("failed to flush FS on destroy: {err}");
+        }
     }
 
     fn lookup(
@@ -78,6 +97,9 @@ impl Filesystem for FatFuse {
         name: &std::ffi::OsStr,
         reply: fuser::ReplyEntry,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
         let Some(name) = name.to_str() else {
             // TODO: add proper handling of non-utf8 strings
             debug!("cannot convert OsStr {:?} to str", name);
@@ -88,20 +110,8 @@ impl Filesystem for FatFuse {
 
         debug!("looking up file {} with parent ino {}", name, parent);
 
-        let Some(parent_inode) = self.get_inode(parent).cloned() else {
-            // parent inode does not exist
-            // TODO: how can we make sure this does not happed?
-            // TODO: panic?
-            debug!("could not find inode for parent ino {}", parent);
-
-            reply.error(ENOENT);
-            return;
-        };
-
-        let parent_inode = parent_inode.borrow();
-
-        let dir_entry: DirEntry = match parent_inode.find_child_by_name(&self.fat_fs, name) {
-            Ok(dir_entry) => dir_entry,
+        let inode = match self.lookup_child(parent, name) {
+            Ok(inode) => inode,
             Err(err) => {
                 debug!("error: {}", err);
                 reply.error(err);
@@ -110,8 +120,6 @@ impl Filesystem for FatFuse {
             }
         };
 
-        let inode = self.get_or_make_inode(&dir_entry, &parent_inode);
-
         let mut inode = inode.borrow_mut();
 
         let attr = inode.file_attr();
@@ -125,24 +133,12 @@ impl Filesystem for FatFuse {
     }
 
     fn forget(&mut self, _req: &fuser::Request<'_>, ino: u64, nlookup: u64) {
-        debug!("forgetting ino {} ({} times)", ino, nlookup);
-
-        let Some(inode) = self.get_inode(ino).cloned() else {
-            debug!("tried to forget {} refs of inode {}, but was not found", ino, nlookup);
-
-            return;
-        };
-
-        let mut inode_ref = inode.borrow_mut();
-
-        if inode_ref.dec_ref_count(nlookup) == 0 {
-            debug!("dropping inode {}", inode_ref.ino());
+        self.mark_active();
+        self.debug_assert_consistent();
 
-            drop(inode_ref);
+        debug!("forgetting ino {} ({} times)", ino, nlookup);
 
-            // no more references, drop inode
-            self.drop_inode(inode);
-        }
+        self.forget_inode(ino, nlookup);
     }
 
     fn getattr(
@@ -152,6 +148,9 @@ impl Filesystem for FatFuse {
         fh: Option<u64>,
         reply: fuser::ReplyAttr,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
         let inode = match get_inode_by_fh_or_ino(self, fh, ino) {
             Ok(inode) => inode,
             Err(err) => {
@@ -164,8 +163,7 @@ impl Filesystem for FatFuse {
 
         let attr = inode.file_attr();
 
-        inode.update_atime(SystemTime::now());
-        if let Err(err) = inode.write_back(&self.fat_fs) {
+        if let Err(err) = self.record_access(&mut inode, true) {
             debug!("error while writing back inode: {err}");
 
             reply.error(EIO);
@@ -181,9 +179,9 @@ impl Filesystem for FatFuse {
         &mut self,
         _req: &fuser::Request<'_>,
         ino: u64,
-        _mode: Option<u32>,
-        _uid: Option<u32>,
-        _gid: Option<u32>,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
         size: Option<u64>,
         atime: Option<fuser::TimeOrNow>,
         mtime: Option<fuser::TimeOrNow>,
@@ -195,36 +193,48 @@ impl Filesystem for FatFuse {
         _flags: Option<u32>,
         reply: fuser::ReplyAttr,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
         let inode = match get_inode_by_fh_or_ino(self, fh, ino) {
-            Ok(inode) => inode,
+            Ok(inode) => inode.clone(),
             Err(err) => {
                 reply.error(err);
                 return;
             }
         };
 
+        self.mark_dirty();
+
         let mut inode = inode.borrow_mut();
 
-        if let Some(new_size) = size {
-            inode.update_size(new_size);
+        if let Err(err) = self.chown_inode(&mut inode, uid, gid) {
+            reply.error(err);
+            return;
         }
 
-        if let Some(atime) = atime {
-            let atime = match atime {
-                fuser::TimeOrNow::SpecificTime(system_time) => system_time,
-                fuser::TimeOrNow::Now => SystemTime::now(),
-            };
-
-            inode.update_atime(atime);
+        if let Some(mode) = mode
+            && let Err(err) = self.chmod_inode(&mut inode, mode)
+        {
+            reply.error(err);
+            return;
         }
 
-        if let Some(mtime) = mtime {
-            let mtime = match mtime {
-                fuser::TimeOrNow::SpecificTime(system_time) => system_time,
-                fuser::TimeOrNow::Now => SystemTime::now(),
-            };
+        if let Some(new_size) = size
+            && let Err(err) = self.truncate_file(&mut inode, new_size)
+        {
+            reply.error(err);
+            return;
+        }
 
-            inode.update_mtime(mtime);
+        if let Err(err) = self.set_times(&mut inode, atime, mtime) {
+            reply.error(err);
+            return;
         }
 
         if let Err(err) = inode.write_back(&self.fat_fs) {
@@ -238,6 +248,9 @@ impl Filesystem for FatFuse {
     }
 
     fn readlink(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyData) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
         debug!("[Not Implemented] readlink(ino: {:#x?})", ino);
         reply.error(ENOSYS);
     }
@@ -252,12 +265,40 @@ impl Filesystem for FatFuse {
         rdev: u32,
         reply: fuser::ReplyEntry,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
         debug!(
-            "[Not Implemented] mknod(parent: {:#x?}, name: {:?}, mode: {}, \
-            umask: {:#x?}, rdev: {})",
+            "mknod(parent: {:#x?}, name: {:?}, mode: {:o}, umask: {:o}, rdev: {})",
             parent, name, mode, umask, rdev
         );
-        reply.error(ENOSYS);
+
+        let Some(name) = name.to_str() else {
+            // TODO: add proper handling of non-utf8 strings
+            debug!("cannot convert OsStr {:?} to str", name);
+
+            reply.error(EINVAL);
+            return;
+        };
+
+        let inode = match self.make_node(parent, name, mode & !umask) {
+            Ok(inode) => inode,
+            Err(err) => {
+                reply.error(err);
+                return;
+            }
+        };
+
+        let mut inode = inode.borrow_mut();
+
+        reply.entry(&TTL, &inode.file_attr(), inode.generation() as u64);
+
+        inode.inc_ref_count();
     }
 
     fn mkdir(
@@ -269,11 +310,40 @@ impl Filesystem for FatFuse {
         umask: u32,
         reply: fuser::ReplyEntry,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
         debug!(
-            "[Not Implemented] mkdir(parent: {:#x?}, name: {:?}, mode: {}, umask: {:#x?})",
+            "mkdir(parent: {:#x?}, name: {:?}, mode: {:o}, umask: {:o})",
             parent, name, mode, umask
         );
-        reply.error(ENOSYS);
+
+        let Some(name) = name.to_str() else {
+            // TODO: add proper handling of non-utf8 strings
+            debug!("cannot convert OsStr {:?} to str", name);
+
+            reply.error(EINVAL);
+            return;
+        };
+
+        let inode = match self.make_dir(parent, name) {
+            Ok(inode) => inode,
+            Err(err) => {
+                reply.error(err);
+                return;
+            }
+        };
+
+        let mut inode = inode.borrow_mut();
+
+        reply.entry(&TTL, &inode.file_attr(), inode.generation() as u64);
+
+        inode.inc_ref_count();
     }
 
     fn unlink(
@@ -283,8 +353,28 @@ impl Filesystem for FatFuse {
         name: &std::ffi::OsStr,
         reply: fuser::ReplyEmpty,
     ) {
-        debug!("[Not Implemented] unlink(parent: {:#x?}, name: {:?})", parent, name,);
-        reply.error(ENOSYS);
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
+        let Some(name) = name.to_str() else {
+            // TODO: add proper handling of non-utf8 strings
+            debug!("cannot convert OsStr {:?} to str", name);
+
+            reply.error(EINVAL);
+            return;
+        };
+
+        if let Err(err) = self.remove_file(parent, name) {
+            reply.error(err);
+            return;
+        }
+
+        reply.ok();
     }
 
     fn rmdir(
@@ -294,6 +384,14 @@ impl Filesystem for FatFuse {
         name: &std::ffi::OsStr,
         reply: fuser::ReplyEmpty,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
         let Some(name) = name.to_str() else {
             // TODO: add proper handling of non-utf8 strings
             debug!("cannot convert OsStr {:?} to str", name);
@@ -302,27 +400,8 @@ impl Filesystem for FatFuse {
             return;
         };
 
-        let Some(parent_inode) = self.get_inode(parent) else {
-            debug!("parent inode {parent} does not exist");
-
-            reply.error(ENOENT);
-            return;
-        };
-
-        let dir_entry = match parent_inode.borrow().find_child_by_name(&self.fat_fs, name) {
-            Ok(dir_entry) => dir_entry,
-            Err(err) => {
-                debug!("parent inode {parent} has no child {name}");
-
-                reply.error(err);
-                return;
-            }
-        };
-
-        if let Err(err) = dir_entry.erase(&self.fat_fs) {
-            debug!("error while erasing DirEntry: {err}");
-
-            reply.error(EIO);
+        if let Err(err) = self.remove_dir(parent, name) {
+            reply.error(err);
             return;
         }
 
@@ -339,15 +418,47 @@ impl Filesystem for FatFuse {
         flags: u32,
         reply: fuser::ReplyEmpty,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
         debug!(
-            "[Not Implemented] rename(parent: {:#x?}, name: {:?}, newparent: {:#x?}, \
-            newname: {:?}, flags: {})",
+            "rename(parent: {:#x?}, name: {:?}, newparent: {:#x?}, newname: {:?}, flags: {})",
             parent, name, newparent, newname, flags,
         );
-        reply.error(ENOSYS);
+
+        let (Some(name), Some(newname)) = (name.to_str(), newname.to_str()) else {
+            // TODO: add proper handling of non-utf8 strings
+            debug!("cannot convert OsStr {:?} or {:?} to str", name, newname);
+
+            reply.error(EINVAL);
+            return;
+        };
+
+        if flags & !libc::RENAME_NOREPLACE != 0 {
+            // RENAME_EXCHANGE and RENAME_WHITEOUT are not supported
+            debug!("unsupported rename flags: {:#x}", flags);
+
+            reply.error(EINVAL);
+            return;
+        }
+
+        let no_replace = flags & libc::RENAME_NOREPLACE != 0;
+
+        match self.rename_entry(parent, name, newparent, newname, no_replace) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
     }
 
     fn open(&mut self, _req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
         if !self.inode_table.contains_key(&ino) {
             reply.error(ENOENT);
             return;
@@ -357,13 +468,23 @@ impl Filesystem for FatFuse {
 
         debug!("flags: {flags:?}");
 
-        let Some(inode) = self.get_inode(ino).cloned() else {
-            debug!("inode {ino} not found");
+        let inode = match self.get_file_inode(ino) {
+            Ok(inode) => inode,
+            Err(err) => {
+                debug!("can't open inode {ino}: {err}");
 
-            reply.error(ENOENT);
-            return;
+                reply.error(err);
+                return;
+            }
         };
 
+        if flags.intersects(OpenFlags::Write | OpenFlags::Truncate) && self.is_read_only() {
+            debug!("tried to open inode {ino} with write access on a read-only mount");
+
+            reply.error(EROFS);
+            return;
+        }
+
         let mut inode = inode.borrow_mut();
 
         if flags.intersects(OpenFlags::Write) && inode.is_read_only() {
@@ -407,6 +528,9 @@ impl Filesystem for FatFuse {
         _lock_owner: Option<u64>,
         reply: fuser::ReplyData,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
         debug!("trying to read {size} bytes at offset {offset} from inode {ino} (fh: {fh})");
 
         if offset < 0 {
@@ -472,8 +596,13 @@ impl Filesystem for FatFuse {
             }
         };
 
-        if reader.skip(offset) != offset {
-            // this should not happen as we checked for valid bounds earlier
+        reader.skip(offset);
+
+        if reader.position() != offset {
+            // this should not happen as we checked for valid bounds earlier, unless the cluster
+            // chain is shorter than the file size
+            debug!("could only skip to {} instead of {offset}", reader.position());
+
             reply.error(EIO);
             return;
         }
@@ -493,7 +622,9 @@ impl Filesystem for FatFuse {
             debug!("expected to read {size} bytes, but only read {bytes_read}");
         }
 
-        inode.update_atime(SystemTime::now());
+        if let Err(err) = self.record_access(&mut inode, false) {
+            debug!("error while updating access time: {err}");
+        }
 
         reply.data(&buf[..bytes_read]);
 
@@ -512,6 +643,14 @@ impl Filesystem for FatFuse {
         _lock_owner: Option<u64>,
         reply: fuser::ReplyWrite,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
         debug!("new write request: ino={ino} fh={fh} offset={offset} data={data:?}");
 
         if offset < 0 {
@@ -552,6 +691,28 @@ impl Filesystem for FatFuse {
             return;
         }
 
+        if inode.first_cluster() == 0 {
+            // empty file, allocate its clusters first
+            if data.is_empty() {
+                reply.written(0);
+                return;
+            }
+
+            if let Err(err) = self.truncate_file(&mut inode, offset + data.len() as u64) {
+                reply.error(err);
+                return;
+            }
+
+            if let Err(err) = inode.write_back(&self.fat_fs) {
+                debug!("error while writing back inode: {err}");
+
+                reply.error(EIO);
+                return;
+            }
+        }
+
+        self.mark_dirty();
+
         let mut writer = match inode.file_writer(&mut self.fat_fs) {
             Ok(writer) => writer,
             Err(err) => {
@@ -584,7 +745,7 @@ impl Filesystem for FatFuse {
             bytes_written += zeros.len();
         }
 
-        if let Err(err) = writer.write_all(&data) {
+        if let Err(err) = writer.write_all(data) {
             debug!("writing data returned error: {err}");
 
             reply.error(EIO);
@@ -593,6 +754,13 @@ impl Filesystem for FatFuse {
 
         bytes_written += data.len();
 
+        if let Err(err) = writer.finish() {
+            debug!("writing back FAT returned error: {err}");
+
+            reply.error(EIO);
+            return;
+        }
+
         if offset + bytes_written as u64 > inode.size() {
             debug!("write increased file size, updating...");
 
@@ -608,7 +776,12 @@ impl Filesystem for FatFuse {
             }
         }
 
-        // TODO: update write and access time
+        if let Err(err) = inode.update_write_time(&self.fat_fs, SystemTime::now()) {
+            debug!("error while updating write time: {err}");
+
+            reply.error(EIO);
+            return;
+        }
 
         reply.written(bytes_written as u32);
     }
@@ -621,6 +794,9 @@ impl Filesystem for FatFuse {
         _lock_owner: u64,
         reply: fuser::ReplyEmpty,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
         debug!("flushing ino={ino} fh={fh}");
 
         let Some(inode) = self.get_inode_by_fh(fh) else {
@@ -662,6 +838,9 @@ impl Filesystem for FatFuse {
         _flush: bool,
         reply: fuser::ReplyEmpty,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
         let Some(found_ino) = self.ino_by_fh.remove(&fh) else {
             debug!("tried to release fh {fh} with ino {ino}, but no ino was found in mapping");
 
@@ -687,6 +866,9 @@ impl Filesystem for FatFuse {
         _datasync: bool,
         reply: fuser::ReplyEmpty,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
         debug!("flushing ino={ino} fh={fh}");
 
         let Some(inode) = self.get_inode_by_fh(fh) else {
@@ -708,13 +890,22 @@ impl Filesystem for FatFuse {
             return;
         }
 
-        if !inode.is_dir() {
+        if inode.is_dir() {
             debug!("called fsync on directory (ino: {ino}, fh: {fh}");
 
             reply.error(EISDIR);
             return;
         }
 
+        drop(inode);
+
+        if let Err(err) = self.flush() {
+            debug!("fsync failed: {err}");
+
+            reply.error(EIO);
+            return;
+        }
+
         reply.ok();
     }
 
@@ -725,6 +916,16 @@ impl Filesystem for FatFuse {
         _flags: i32,
         reply: fuser::ReplyOpen,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if let Err(err) = self.get_dir_inode(ino) {
+            debug!("can't open inode {ino} as a directory: {err}");
+
+            reply.error(err);
+            return;
+        }
+
         let fh = self.next_fh();
 
         if let Some(old_ino) = self.ino_by_fh.insert(fh, ino) {
@@ -742,7 +943,11 @@ impl Filesystem for FatFuse {
         offset: i64,
         mut reply: fuser::ReplyDirectory,
     ) {
-        let Ok(mut offset): Result<usize, _> = offset.try_into() else {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        let Ok(offset): Result<usize, _> = offset.try_into() else {
+            reply.error(EINVAL);
             return;
         };
 
@@ -753,12 +958,10 @@ impl Filesystem for FatFuse {
             return;
         };
 
-        let dir_inode = dir_inode.borrow();
-
-        if dir_inode.ino() != ino {
+        if dir_inode.borrow().ino() != ino {
             debug!(
                 "ino {} of inode associated with fh {} does not match given ino {}",
-                dir_inode.ino(),
+                dir_inode.borrow().ino(),
                 fh,
                 ino
             );
@@ -767,51 +970,19 @@ impl Filesystem for FatFuse {
             return;
         }
 
-        let mut _next_idx = 1;
-        let mut next_offset = || {
-            let next_idx = _next_idx;
-            _next_idx += 1;
-            next_idx
-        };
-
-        if dir_inode.is_root() {
-            if offset == 0 {
-                debug!("adding . to root dir");
-                if reply.add(1, next_offset(), FileType::Directory, ".") {
-                    return;
-                }
-            } else {
-                offset -= 1;
-            }
-
-            if offset == 0 {
-                debug!("adding .. to root dir");
-                if reply.add(1, next_offset(), FileType::Directory, "..") {
-                    return;
-                }
-            } else {
-                offset -= 1;
+        let entries = match self.list_dir(&dir_inode, Some(fh), offset) {
+            Ok(entries) => entries,
+            Err(err) => {
+                reply.error(err);
+                return;
             }
-        }
-
-        let Ok(dir_iter) = dir_inode.dir_iter(&self.fat_fs) else {
-            reply.error(ENOTDIR);
-            return;
         };
 
-        // need to drop dir_iter here so we can borrow self mut again
-        // also skip over `offset` entries
-        let dirs: Vec<DirEntry> = dir_iter.skip(offset).collect();
-
-        for dir_entry in dirs {
-            let name = dir_entry.name_string();
-
-            let inode = self.get_or_make_inode(&dir_entry, &dir_inode);
-
+        for (idx, (name, inode)) in entries.into_iter().enumerate() {
             let inode = inode.borrow();
 
             debug!("adding entry {} (ino: {})", name, inode.ino());
-            if reply.add(ino, next_offset(), inode.kind().into(), name) {
+            if reply.add(inode.ino(), (offset + idx + 1) as i64, inode.kind().into(), name) {
                 return;
             }
         }
@@ -827,13 +998,62 @@ impl Filesystem for FatFuse {
         ino: u64,
         fh: u64,
         offset: i64,
-        reply: fuser::ReplyDirectoryPlus,
+        mut reply: fuser::ReplyDirectoryPlus,
     ) {
-        debug!(
-            "[Not Implemented] readdirplus(ino: {:#x?}, fh: {}, offset: {})",
-            ino, fh, offset
-        );
-        reply.error(ENOSYS);
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        let Ok(offset): Result<usize, _> = offset.try_into() else {
+            reply.error(EINVAL);
+            return;
+        };
+
+        let Some(dir_inode) = self.get_inode_by_fh(fh).cloned() else {
+            debug!("could not find inode accociated with fh {} (ino: {})", fh, ino);
+
+            reply.error(EBADF);
+            return;
+        };
+
+        if dir_inode.borrow().ino() != ino {
+            debug!(
+                "ino {} of inode associated with fh {} does not match given ino {}",
+                dir_inode.borrow().ino(),
+                fh,
+                ino
+            );
+
+            reply.error(EINVAL);
+            return;
+        }
+
+        let entries = match self.list_dir(&dir_inode, Some(fh), offset) {
+            Ok(entries) => entries,
+            Err(err) => {
+                reply.error(err);
+                return;
+            }
+        };
+
+        for (idx, (name, inode)) in entries.into_iter().enumerate() {
+            let mut inode = inode.borrow_mut();
+
+            debug!("adding entry {} (ino: {})", name, inode.ino());
+
+            let attr = inode.file_attr();
+            let generation = inode.generation() as u64;
+
+            if reply.add(inode.ino(), (offset + idx + 1) as i64, &name, &TTL, &attr, generation) {
+                return;
+            }
+
+            // like lookup, every entry apart from . and .. counts as a lookup of the inode
+            if name != "." && name != ".." {
+                inode.inc_ref_count();
+            }
+        }
+
+        reply.ok();
     }
 
     fn releasedir(
@@ -844,6 +1064,11 @@ impl Filesystem for FatFuse {
         _flags: i32,
         reply: fuser::ReplyEmpty,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        self.dir_entry_cache.remove(fh);
+
         let Some(ino) = self.ino_by_fh.remove(&fh) else {
             debug!("can't find inode {} by fh {}", ino, fh);
 
@@ -880,15 +1105,152 @@ impl Filesystem for FatFuse {
         datasync: bool,
         reply: fuser::ReplyEmpty,
     ) {
-        debug!(
-            "[Not Implemented] fsyncdir(ino: {:#x?}, fh: {}, datasync: {})",
-            ino, fh, datasync
-        );
-        reply.error(ENOSYS);
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        debug!("fsyncdir(ino: {:#x?}, fh: {}, datasync: {})", ino, fh, datasync);
+
+        match get_inode_by_fh_or_ino(self, Some(fh), ino) {
+            Ok(inode) if !inode.borrow().is_dir() => {
+                reply.error(ENOTDIR);
+                return;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                reply.error(err);
+                return;
+            }
+        }
+
+        // directory entries are written in place, so syncing the whole FS is all there is to do
+        if let Err(err) = self.flush() {
+            debug!("fsyncdir failed: {err}");
+
+            reply.error(EIO);
+            return;
+        }
+
+        reply.ok();
     }
 
     fn statfs(&mut self, _req: &fuser::Request<'_>, _ino: u64, reply: fuser::ReplyStatfs) {
-        reply.statfs(0, 0, 0, 0, 0, 512, 255, 0);
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        debug!("active file handles: {}", self.active_file_handle_count());
+
+        // free_clusters is counted from the FAT on load and kept up to date afterwards, so it's
+        // never worse than the FSInfo hint
+        let blocks = self.fat_fs.cluster_count() as u64;
+        let free = self.fat_fs.free_clusters() as u64;
+        let block_size = self.fat_fs.bytes_per_cluster() as u32;
+
+        // FAT has no inodes, so there is no meaningful file count
+        reply.statfs(blocks, free, free, 0, 0, block_size, 255, block_size);
+    }
+
+    fn setxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        name: &std::ffi::OsStr,
+        value: &[u8],
+        _flags: i32,
+        _position: u32,
+        reply: fuser::ReplyEmpty,
+    ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
+        let Some(name) = name.to_str() else {
+            reply.error(ENOTSUP);
+            return;
+        };
+
+        match self.set_fat_xattr(ino, name, value) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        name: &std::ffi::OsStr,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        let Some(name) = name.to_str() else {
+            reply.error(ENODATA);
+            return;
+        };
+
+        match self.get_fat_xattr(ino, name) {
+            Ok(value) => reply_xattr(reply, size, value),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn listxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.get_inode(ino).is_none() {
+            reply.error(ENOENT);
+            return;
+        }
+
+        reply_xattr(reply, size, &FatFuse::list_fat_xattrs());
+    }
+
+    fn access(&mut self, _req: &fuser::Request<'_>, ino: u64, mask: i32, reply: fuser::ReplyEmpty) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        match self.check_access(ino, mask) {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn ioctl(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _flags: u32,
+        cmd: u32,
+        in_data: &[u8],
+        _out_size: u32,
+        reply: fuser::ReplyIoctl,
+    ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if cmd == FAT_IOCTL_SET_ATTRIBUTES && self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
+        match self.fat_ioctl(ino, cmd, in_data) {
+            Ok(data) => reply.ioctl(0, &data),
+            Err(err) => reply.error(err),
+        }
     }
 
     fn create(
@@ -901,11 +1263,80 @@ impl Filesystem for FatFuse {
         flags: i32,
         reply: fuser::ReplyCreate,
     ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        if self.is_read_only() {
+            reply.error(EROFS);
+            return;
+        }
+
         debug!(
-            "[Not Implemented] create(parent: {:#x?}, name: {:?}, mode: {}, umask: {:#x?}, \
-            flags: {:#x?})",
+            "create(parent: {:#x?}, name: {:?}, mode: {:o}, umask: {:o}, flags: {:#x?})",
             parent, name, mode, umask, flags
         );
-        reply.error(ENOSYS);
+
+        let Some(name) = name.to_str() else {
+            // TODO: add proper handling of non-utf8 strings
+            debug!("cannot convert OsStr {:?} to str", name);
+
+            reply.error(EINVAL);
+            return;
+        };
+
+        let inode = match self.create_file(parent, name, mode & !umask) {
+            Ok(inode) => inode,
+            Err(err) => {
+                reply.error(err);
+                return;
+            }
+        };
+
+        let mut inode = inode.borrow_mut();
+
+        let fh = self.next_fh();
+
+        if let Some(old_ino) = self.ino_by_fh.insert(fh, inode.ino()) {
+            debug!("fh {} was associated with ino {}, now with ino {}", fh, old_ino, inode.ino());
+        }
+
+        debug!("created inode {}: fh {}", inode.ino(), fh);
+
+        reply.created(&TTL, &inode.file_attr(), inode.generation() as u64, fh, 0);
+
+        inode.inc_ref_count();
+    }
+
+    fn lseek(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: fuser::ReplyLseek,
+    ) {
+        self.mark_active();
+        self.debug_assert_consistent();
+
+        debug!("lseek(ino: {:#x?}, fh: {}, offset: {}, whence: {})", ino, fh, offset, whence);
+
+        let inode = match get_inode_by_fh_or_ino(self, Some(fh), ino) {
+            Ok(inode) => inode.borrow(),
+            Err(err) => {
+                reply.error(err);
+                return;
+            }
+        };
+
+        if !inode.is_file() {
+            reply.error(EISDIR);
+            return;
+        }
+
+        match seek_offset(inode.size(), offset, whence) {
+            Ok(offset) => reply.offset(offset),
+            Err(err) => reply.error(err),
+        }
     }
 }
@@ -1,34 +1,14 @@
-use std::cell::{LazyCell, RefCell};
+use std::cell::RefCell;
 use std::rc::Rc;
 use std::time::SystemTime;
 
 use chrono::{NaiveDateTime, NaiveTime};
-use fat_bits::FatFs;
-use fat_bits::dir::{DirEntry, DirIter};
+use fat_bits::dir::DirIter;
 use fat_bits::iter::{ClusterChainReader, ClusterChainWriter};
+use fat_bits::{Attr, DirEntry, FatFs};
 use fuser::FileAttr;
 use libc::{EISDIR, ENOENT, ENOTDIR};
 use log::debug;
-use rand::{Rng, SeedableRng as _};
-
-thread_local! {
-/// SAFETY
-///
-/// do not access this directly, only invoke the get_random_u32 function
-// static RNG: LazyCell<UnsafeCell<rand::rngs::SmallRng>> = LazyCell::new(|| UnsafeCell::new(rand::rngs::SmallRng::from_os_rng()));
-
-/// performance should not be a bottleneck here, since we only need to occasionally generate u32s to
-/// be used as generations in inodes
-/// if at some point (contrary to expectations) it should become, can switch it to an UnsafeCell
-static RNG: LazyCell<RefCell<rand::rngs::SmallRng>> = LazyCell::new(|| RefCell::new(rand::rngs::SmallRng::from_os_rng()));
-}
-
-fn get_random<T>() -> T
-where
-    rand::distr::StandardUniform: rand::distr::Distribution<T>,
-{
-    RNG.with(|rng| rng.borrow_mut().random())
-}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Kind {
@@ -47,6 +27,11 @@ impl From<Kind> for fuser::FileType {
 
 pub const ROOT_INO: u64 = 1;
 
+/// ref count the root inode starts with and never drops below
+///
+/// the kernel may forget the root at any time, but it must stay around regardless
+pub const ROOT_REF_COUNT: u64 = u64::MAX / 2;
+
 pub type InodeRef = Rc<RefCell<Inode>>;
 // pub type InodeWeak = Weak<RefCell<Inode>>;
 
@@ -83,6 +68,9 @@ pub struct Inode {
     crtime: SystemTime,
 
     first_cluster: u32,
+
+    // offset of the DirEntry backing this inode, meaningless for the root dir
+    dir_entry_offset: u64,
 }
 
 impl Drop for Inode {
@@ -97,15 +85,17 @@ impl Drop for Inode {
 }
 
 impl Inode {
-    fn new_generation() -> u32 {
-        let rand: u16 = get_random();
-
-        let secs = SystemTime::UNIX_EPOCH
-            .elapsed()
-            .map(|dur| dur.as_secs() as u16)
-            .unwrap_or(0);
-
-        ((secs as u32) << 16) | rand as u32
+    /// generation for an inode backed by `dir_entry`
+    ///
+    /// derived only from what is stored on disk, so it stays the same across remounts. directories
+    /// use their first cluster, files their first cluster xor'ed with their size shifted into the
+    /// high bits. files with the same size but different first clusters always differ
+    pub fn generation_for(dir_entry: &DirEntry) -> u32 {
+        if dir_entry.is_dir() {
+            dir_entry.first_cluster()
+        } else {
+            dir_entry.first_cluster() ^ (dir_entry.file_size() << 16)
+        }
     }
 
     pub fn new(
@@ -119,7 +109,7 @@ impl Inode {
     ) -> Inode {
         assert!(dir_entry.is_file() || dir_entry.is_dir());
 
-        let generation = Self::new_generation();
+        let generation = Self::generation_for(dir_entry);
 
         let kind = if dir_entry.is_dir() {
             Kind::Dir
@@ -164,6 +154,7 @@ impl Inode {
             uid,
             gid,
             first_cluster: dir_entry.first_cluster(),
+            dir_entry_offset: dir_entry.offset(),
             path,
         }
     }
@@ -174,7 +165,7 @@ impl Inode {
         Inode {
             ino: ROOT_INO,
             generation: 0, // root cluster always has constant generation of 0
-            ref_count: 0,
+            ref_count: ROOT_REF_COUNT,
             parent: None, // parent is self
             size: 0,
             block_size: fat_fs.bytes_per_sector() as u32,
@@ -187,6 +178,7 @@ impl Inode {
             uid,
             gid,
             first_cluster: root_cluster,
+            dir_entry_offset: 0,
             path: "/".into(),
         }
     }
@@ -232,6 +224,10 @@ impl Inode {
 
         self.ref_count = self.ref_count.saturating_sub(n);
 
+        if self.is_root() {
+            self.ref_count = self.ref_count.max(ROOT_REF_COUNT);
+        }
+
         self.ref_count
     }
 
@@ -267,6 +263,40 @@ impl Inode {
         Rc::clone(&self.path)
     }
 
+    /// last component of the path
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap()
+    }
+
+    pub fn set_path(&mut self, path: Rc<str>) {
+        self.path = path;
+    }
+
+    /// the DirEntry backing this inode was moved to `offset`, e.g. by a rename
+    pub fn set_dir_entry_offset(&mut self, offset: u64) {
+        assert!(!self.is_root());
+
+        self.dir_entry_offset = offset;
+    }
+
+    /// re-parent this inode, e.g. after it was moved to a different directory
+    /// FAT has no notion of ownership, so this only changes the in-memory inode
+    pub fn set_owner(&mut self, uid: Option<u32>, gid: Option<u32>) {
+        if let Some(uid) = uid {
+            self.uid = uid;
+        }
+
+        if let Some(gid) = gid {
+            self.gid = gid;
+        }
+    }
+
+    pub fn set_parent(&mut self, parent: InodeRef) {
+        assert!(!self.is_root());
+
+        self.parent = Some(parent);
+    }
+
     pub fn is_root(&self) -> bool {
         self.ino == ROOT_INO
     }
@@ -293,7 +323,7 @@ impl Inode {
         }
     }
 
-    pub fn dir_iter<'a>(&'a self, fat_fs: &'a FatFs) -> Result<DirIter<'a>, i32> {
+    pub fn dir_iter<'a>(&'a self, fat_fs: &'a FatFs) -> Result<DirIter<ClusterChainReader<'a>>, i32> {
         if self.kind != Kind::Dir {
             return Err(ENOTDIR);
         }
@@ -307,6 +337,9 @@ impl Inode {
         Ok(fat_fs.dir_iter(self.first_cluster))
     }
 
+    /// find the child called `name`, matched according to the FS's `case_sensitive` setting
+    ///
+    /// fails with ENOTDIR if this isn't a directory and with ENOENT if there is no such child
     pub fn find_child_by_name(&self, fat_fs: &FatFs, name: &str) -> Result<DirEntry, i32> {
         self.dir_iter(fat_fs)
             .and_then(|mut dir_iter| dir_iter.find_by_name(name).ok_or(ENOENT))
@@ -328,6 +361,21 @@ impl Inode {
         Ok(fat_fs.file_writer(self.first_cluster()))
     }
 
+    /// the chain of this inode now starts at `first_cluster`, e.g. after it was truncated
+    pub fn set_first_cluster(&mut self, first_cluster: u32) {
+        if first_cluster == self.first_cluster {
+            return;
+        }
+
+        self.first_cluster = first_cluster;
+        self.dirty = true;
+    }
+
+    /// this inode has changes that haven't been written back to its DirEntry yet
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
     pub fn update_size(&mut self, new_size: u64) {
         debug!("updating size to {new_size}");
 
@@ -357,6 +405,113 @@ impl Inode {
         self.dirty = true;
     }
 
+    /// find the DirEntry corresponding to this inode in the parent directory
+    fn find_dir_entry(&self, fat_fs: &FatFs) -> anyhow::Result<DirEntry> {
+        let Some(parent_inode) = self.parent() else {
+            anyhow::bail!("parent inode of {} does not exist", self.ino);
+        };
+
+        let parent_inode = parent_inode.borrow();
+
+        // look up by offset, empty files all share the first cluster 0 and the rendered name may
+        // differ from what the inode was looked up by, e.g. for hidden files
+        let dir_entry = parent_inode.dir_iter(fat_fs).ok().and_then(|mut dir_iter| {
+            dir_iter.find(|dir_entry| dir_entry.offset() == self.dir_entry_offset)
+        });
+
+        let Some(dir_entry) = dir_entry else {
+            anyhow::bail!("could not find dir_entry corresponding to self in parent inode");
+        };
+
+        Ok(dir_entry)
+    }
+
+    /// FAT attributes of the DirEntry on disk, the root dir has none
+    pub fn fat_attr(&self, fat_fs: &FatFs) -> anyhow::Result<Attr> {
+        if self.is_root() {
+            return Ok(Attr::Directory);
+        }
+
+        Ok(self.find_dir_entry(fat_fs)?.attr())
+    }
+
+    /// replace the FAT attributes and write them to the DirEntry directly
+    ///
+    /// the directory bit can't be changed this way
+    pub fn set_fat_attr(&mut self, fat_fs: &FatFs, attr: Attr) -> anyhow::Result<()> {
+        anyhow::ensure!(!self.is_root(), "root dir has no attributes");
+
+        let mut dir_entry = self.find_dir_entry(fat_fs)?;
+
+        dir_entry.set_attr(attr);
+        dir_entry.write_back(fat_fs)?;
+
+        self.read_only = dir_entry.is_readonly();
+
+        Ok(())
+    }
+
+    /// set atime and mtime to now and write them to the DirEntry directly
+    pub fn touch(&mut self, fat_fs: &mut FatFs) -> anyhow::Result<()> {
+        self.touch_impl(fat_fs, true, true)
+    }
+
+    /// set atime to now and write it to the DirEntry directly
+    pub fn touch_atime(&mut self, fat_fs: &mut FatFs) -> anyhow::Result<()> {
+        self.touch_impl(fat_fs, true, false)
+    }
+
+    /// set mtime to now and write it to the DirEntry directly
+    pub fn touch_mtime(&mut self, fat_fs: &mut FatFs) -> anyhow::Result<()> {
+        self.touch_impl(fat_fs, false, true)
+    }
+
+    fn touch_impl(&mut self, fat_fs: &mut FatFs, atime: bool, mtime: bool) -> anyhow::Result<()> {
+        let now = SystemTime::now();
+
+        if !self.is_root() {
+            let offset = self.find_dir_entry(fat_fs)?.offset();
+
+            let res = match (atime, mtime) {
+                (true, true) => fat_fs.touch(offset),
+                (true, false) => fat_fs.touch_access(offset),
+                (false, true) => fat_fs.touch_write(offset),
+                (false, false) => Ok(()),
+            };
+
+            res.map_err(|err| {
+                anyhow::anyhow!("failed to touch dir_entry for inode {}: {err}", self.ino)
+            })?;
+        }
+
+        // already on disk, so don't mark as dirty
+        if atime {
+            self.atime = now;
+        }
+
+        if mtime {
+            self.mtime = now;
+        }
+
+        Ok(())
+    }
+
+    /// set mtime and write it to the DirEntry directly
+    pub fn update_write_time(&mut self, fat_fs: &FatFs, mtime: SystemTime) -> anyhow::Result<()> {
+        if !self.is_root() {
+            let mut dir_entry = self.find_dir_entry(fat_fs)?;
+
+            dir_entry.update_write_time(fat_fs, mtime.into()).map_err(|err| {
+                anyhow::anyhow!("failed to update mtime for inode {}: {err}", self.ino)
+            })?;
+        }
+
+        // already on disk, so don't mark as dirty
+        self.mtime = mtime;
+
+        Ok(())
+    }
+
     pub fn write_back(&mut self, fat_fs: &FatFs) -> anyhow::Result<()> {
         if !self.dirty {
             return Ok(());
@@ -369,35 +524,19 @@ impl Inode {
             return Ok(());
         }
 
-        let Some(parent_inode) = self.parent() else {
-            anyhow::bail!("parent inode of {} does not exist", self.ino);
-        };
-
-        let parent_inode = parent_inode.borrow();
-
-        // since we just wrote to the file with this inode, first cluster should not be zero
-        let Some(mut dir_entry) = parent_inode
-            .dir_iter(fat_fs)
-            .unwrap()
-            .find(|dir_entry| dir_entry.first_cluster() == self.first_cluster())
-        else {
-            anyhow::bail!("could not find dir_entry corresponding to self in parent inode");
-        };
-
-        drop(parent_inode);
+        let mut dir_entry = self.find_dir_entry(fat_fs)?;
 
         // update stats on DirEntry
         assert!(self.size <= u32::MAX as u64);
 
-        dir_entry.update_file_size(self.size as u32);
+        dir_entry.set_file_size(self.size as u32);
+        dir_entry.set_first_cluster(self.first_cluster);
 
-        dir_entry
-            .update_last_access_date(self.atime)
-            .map_err(|err| {
-                anyhow::anyhow!("failed to update atime for inode {}: {err}", self.ino)
-            })?;
+        dir_entry.set_last_access_date(self.atime).map_err(|err| {
+            anyhow::anyhow!("failed to update atime for inode {}: {err}", self.ino)
+        })?;
 
-        dir_entry.update_write_time(self.mtime).map_err(|err| {
+        dir_entry.set_write_datetime(self.mtime).map_err(|err| {
             anyhow::anyhow!("failed to update mtime for inode {}: {err}", self.ino)
         })?;
 
@@ -3,19 +3,178 @@ mod inode;
 
 use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::io::Write as _;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
 
-use fat_bits::dir::DirEntry;
-use fat_bits::{FatFs, SliceLike};
+use fat_bits::{Attr, DirEntry, FatFs, SliceLike};
+use fuser::TimeOrNow;
 use fxhash::FxHashMap;
+use libc::{
+    EACCES, EEXIST, EFBIG, EINVAL, EIO, EISDIR, ENODATA, ENOENT, ENOTDIR, ENOTEMPTY, ENOTSUP,
+    ENOTTY, ENXIO, EPERM, EROFS,
+};
 use log::{debug, error};
 
 use crate::inode::{Inode, InodeRef};
 
+/// directory entries of open directory handles
+///
+/// a listing that takes several readdir calls only reads the directory from disk once
+#[derive(Default)]
+struct DirEntryCache {
+    entries_by_fh: BTreeMap<u64, Vec<DirEntry>>,
+}
+
+impl DirEntryCache {
+    fn get(&self, fh: u64) -> Option<&[DirEntry]> {
+        self.entries_by_fh.get(&fh).map(Vec::as_slice)
+    }
+
+    fn insert(&mut self, fh: u64, entries: Vec<DirEntry>) {
+        self.entries_by_fh.insert(fh, entries);
+    }
+
+    fn remove(&mut self, fh: u64) {
+        self.entries_by_fh.remove(&fh);
+    }
+
+    fn clear(&mut self) {
+        self.entries_by_fh.clear();
+    }
+}
+
+/// FAT attributes exposed as extended attributes
+const FAT_XATTRS: [(&str, Attr); 4] = [
+    ("user.fat.readonly", Attr::ReadOnly),
+    ("user.fat.hidden", Attr::Hidden),
+    ("user.fat.system", Attr::System),
+    ("user.fat.archive", Attr::Archive),
+];
+
+/// _IOR('r', 0x10, u32) from linux/msdos_fs.h
+const FAT_IOCTL_GET_ATTRIBUTES: u32 = 0x80047210;
+
+/// _IOW('r', 0x11, u32) from linux/msdos_fs.h
+const FAT_IOCTL_SET_ATTRIBUTES: u32 = 0x40047211;
+
+/// default number of entries in the lookup cache
+const LOOKUP_CACHE_CAPACITY: usize = 512;
+
+struct CachedLookup {
+    inode: InodeRef,
+    ino: u64,
+    last_used: u64,
+}
+
+/// inodes that (parent ino, name) pairs were recently looked up as
+///
+/// evicts the least recently used entry once it's full
+struct LookupCache {
+    capacity: usize,
+    next_use: u64,
+
+    entries: FxHashMap<(u64, String), CachedLookup>,
+    // keys by the time of their last use, so the first one is the least recently used
+    keys_by_use: BTreeMap<u64, (u64, String)>,
+}
+
+impl LookupCache {
+    fn new(capacity: usize) -> LookupCache {
+        LookupCache {
+            capacity,
+            next_use: 0,
+            entries: FxHashMap::default(),
+            keys_by_use: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, parent: u64, name: &str) -> Option<InodeRef> {
+        let key = (parent, name.to_owned());
+
+        let cached = self.entries.get_mut(&key)?;
+
+        self.keys_by_use.remove(&cached.last_used);
+
+        cached.last_used = self.next_use;
+        self.next_use += 1;
+
+        self.keys_by_use.insert(cached.last_used, key);
+
+        Some(Rc::clone(&cached.inode))
+    }
+
+    fn insert(&mut self, parent: u64, name: &str, inode: InodeRef) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let key = (parent, name.to_owned());
+
+        if let Some(old) = self.entries.remove(&key) {
+            self.keys_by_use.remove(&old.last_used);
+        }
+
+        self.shrink_to(self.capacity - 1);
+
+        let ino = inode.borrow().ino();
+        let last_used = self.next_use;
+        self.next_use += 1;
+
+        self.keys_by_use.insert(last_used, key.clone());
+        self.entries.insert(
+            key,
+            CachedLookup {
+                inode,
+                ino,
+                last_used,
+            },
+        );
+    }
+
+    /// drop all entries that are either children of or resolve to `ino`
+    fn remove_ino(&mut self, ino: u64) {
+        self.entries.retain(|(parent, _), cached| *parent != ino && cached.ino != ino);
+        self.keys_by_use.retain(|_, key| self.entries.contains_key(key));
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+
+        self.shrink_to(capacity);
+    }
+
+    /// evict least recently used entries until at most `len` are left
+    fn shrink_to(&mut self, len: usize) {
+        while self.entries.len() > len {
+            let Some((_, key)) = self.keys_by_use.pop_first() else {
+                break;
+            };
+
+            self.entries.remove(&key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.keys_by_use.clear();
+    }
+}
+
+/// whether a mounted FS may be modified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MountMode {
+    ReadOnly,
+    ReadWrite,
+}
+
 #[allow(dead_code)]
 pub struct FatFuse {
     fat_fs: FatFs,
 
+    mode: MountMode,
+
     uid: u32,
     gid: u32,
 
@@ -27,6 +186,21 @@ pub struct FatFuse {
     ino_by_first_cluster: BTreeMap<u32, u64>,
     ino_by_fh: BTreeMap<u64, u64>,
     ino_by_path: FxHashMap<Rc<str>, u64>,
+
+    dir_entry_cache: DirEntryCache,
+    lookup_cache: LookupCache,
+
+    // shared, so the mount can be watched for inactivity from another thread
+    last_operation: Arc<Mutex<Instant>>,
+
+    // changed since the last flush
+    dirty: bool,
+
+    // reject changes of uid and gid instead of only applying them in memory
+    strict_chown: bool,
+
+    // treat all files as executable, not just those without an extension
+    exec_all_files: bool,
 }
 
 /// SAFETY
@@ -35,7 +209,23 @@ pub struct FatFuse {
 unsafe impl Send for FatFuse {}
 
 impl FatFuse {
+    /// read-only FS, all operations that would modify it fail with EROFS
     pub fn new<S>(data: S) -> anyhow::Result<FatFuse>
+    where
+        S: SliceLike + Send + 'static,
+    {
+        FatFuse::with_mode(data, MountMode::ReadOnly)
+    }
+
+    /// writable FS
+    pub fn new_rw<S>(data: S) -> anyhow::Result<FatFuse>
+    where
+        S: SliceLike + Send + 'static,
+    {
+        FatFuse::with_mode(data, MountMode::ReadWrite)
+    }
+
+    fn with_mode<S>(data: S, mode: MountMode) -> anyhow::Result<FatFuse>
     where
         S: SliceLike + Send + 'static,
     {
@@ -46,6 +236,7 @@ impl FatFuse {
 
         let mut fat_fuse = FatFuse {
             fat_fs,
+            mode,
             uid,
             gid,
             next_ino: 2, // 0 is reserved and 1 is root
@@ -54,6 +245,12 @@ impl FatFuse {
             ino_by_first_cluster: BTreeMap::new(),
             ino_by_fh: BTreeMap::new(),
             ino_by_path: FxHashMap::default(),
+            dir_entry_cache: DirEntryCache::default(),
+            lookup_cache: LookupCache::new(LOOKUP_CACHE_CAPACITY),
+            last_operation: Arc::new(Mutex::new(Instant::now())),
+            dirty: false,
+            strict_chown: false,
+            exec_all_files: false,
         };
 
         // TODO: build and insert root dir inode
@@ -65,6 +262,45 @@ impl FatFuse {
         Ok(fat_fuse)
     }
 
+    pub fn mode(&self) -> MountMode {
+        self.mode
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.mode == MountMode::ReadOnly
+    }
+
+    /// whether chown fails with EPERM, since FAT can't store ownership. off by default, in which
+    /// case the new owner is only kept in memory
+    pub fn set_strict_chown(&mut self, strict_chown: bool) {
+        self.strict_chown = strict_chown;
+    }
+
+    /// whether access grants X_OK for every file. off by default, in which case only files without
+    /// an extension count as executable
+    pub fn set_exec_all_files(&mut self, exec_all_files: bool) {
+        self.exec_all_files = exec_all_files;
+    }
+
+    /// handle to the time of the last FUSE operation
+    ///
+    /// stays valid after the FatFuse has been moved into the mount
+    pub fn last_operation(&self) -> Arc<Mutex<Instant>> {
+        Arc::clone(&self.last_operation)
+    }
+
+    /// maximum number of (parent, name) lookups that are cached, 512 by default
+    ///
+    /// a capacity of 0 disables the cache
+    pub fn set_lookup_cache_capacity(&mut self, capacity: usize) {
+        self.lookup_cache.set_capacity(capacity);
+    }
+
+    /// record that a FUSE operation just happened
+    fn mark_active(&self) {
+        *self.last_operation.lock().unwrap() = Instant::now();
+    }
+
     fn next_ino(&mut self) -> u64 {
         let ino = self.next_ino;
 
@@ -121,10 +357,10 @@ impl FatFuse {
             debug!("ejected inode {} {}", old_inode.ino(), old_inode.generation());
         }
 
-        if first_cluster != 0 {
-            if let Some(old_ino) = self.ino_by_first_cluster.insert(first_cluster, ino) {
-                debug!("ejected old {} -> {} cluster to ino mapping", first_cluster, old_ino);
-            }
+        if first_cluster != 0
+            && let Some(old_ino) = self.ino_by_first_cluster.insert(first_cluster, ino)
+        {
+            debug!("ejected old {} -> {} cluster to ino mapping", first_cluster, old_ino);
         }
 
         let path = new_inode.borrow().path();
@@ -143,6 +379,8 @@ impl FatFuse {
 
         debug!("dropping inode {}", ino);
 
+        self.lookup_cache.remove_ino(ino);
+
         if self.inode_table.remove(&ino).is_none() {
             error!("tried to drop inode with ino {}, but was not in table", ino);
 
@@ -202,96 +440,2235 @@ impl FatFuse {
         }
     }
 
-    fn get_inode(&self, ino: u64) -> Option<&InodeRef> {
-        self.inode_table.get(&ino)
+    /// value of the extended attribute `name` of inode `ino`, "1" if the FAT attribute is set and
+    /// "0" otherwise
+    fn get_fat_xattr(&self, ino: u64, name: &str) -> Result<&'static [u8], i32> {
+        let inode = self.get_inode(ino).ok_or(ENOENT)?;
+
+        let (_, flag) = FAT_XATTRS.iter().find(|(xattr, _)| *xattr == name).ok_or(ENODATA)?;
+
+        let attr = inode.borrow().fat_attr(&self.fat_fs).map_err(|err| {
+            debug!("failed to read attributes of inode {ino}: {err}");
+
+            EIO
+        })?;
+
+        Ok(if attr.contains(*flag) { b"1" } else { b"0" })
     }
 
-    fn get_or_make_inode(&mut self, dir_entry: &DirEntry, parent: &Inode) -> InodeRef {
-        // let parent = parent.borrow();
+    /// check whether inode `ino` may be accessed as requested by `mask`, like access(2)
+    ///
+    /// FAT has no permission bits, so everything is readable, writable unless the mount or the
+    /// file is read-only, and directories are searchable. files are executable if they have no
+    /// extension, or always with `exec_all_files`
+    fn check_access(&self, ino: u64, mask: i32) -> Result<(), i32> {
+        let inode = self.get_inode(ino).ok_or(ENOENT)?;
+        let inode = inode.borrow();
 
-        // try to find inode by first cluster first
-        if dir_entry.first_cluster() != 0
-            && let Some(inode) = self.get_inode_by_first_cluster(dir_entry.first_cluster())
+        if mask & libc::W_OK != 0 {
+            if self.is_read_only() {
+                return Err(EROFS);
+            }
+
+            if inode.is_read_only() {
+                return Err(EACCES);
+            }
+        }
+
+        if mask & libc::X_OK != 0
+            && inode.is_file()
+            && !self.exec_all_files
+            && inode.name().rsplit_once('.').is_some_and(|(stem, _)| !stem.is_empty())
         {
-            return inode;
+            return Err(EACCES);
         }
 
-        // try to find inode by path
-        // mostly for empty files/directories which have a first cluster of 0
+        Ok(())
+    }
 
-        let path = {
-            let mut path = parent.path().as_ref().to_owned();
+    /// names of all extended attributes, each terminated by a NUL byte
+    fn list_fat_xattrs() -> Vec<u8> {
+        let mut names = Vec::new();
 
-            if parent.ino() != inode::ROOT_INO {
-                // root inode already has trailing slash
-                path.push('/');
-            }
+        for (name, _) in FAT_XATTRS {
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+        }
+
+        names
+    }
 
-            path += &dir_entry.name_string();
+    /// set or clear the FAT attribute behind the extended attribute `name` of inode `ino`
+    ///
+    /// `value` has to be either "1" or "0"
+    fn set_fat_xattr(&mut self, ino: u64, name: &str, value: &[u8]) -> Result<(), i32> {
+        let inode = self.get_inode(ino).cloned().ok_or(ENOENT)?;
 
-            path
+        let Some(&(_, flag)) = FAT_XATTRS.iter().find(|(xattr, _)| *xattr == name) else {
+            return Err(ENOTSUP);
         };
 
-        if let Some(inode) = self.get_inode_by_path(&path) {
-            return inode;
+        let set = match value {
+            b"1" => true,
+            b"0" => false,
+            _ => return Err(EINVAL),
+        };
+
+        let mut inode = inode.borrow_mut();
+
+        if inode.is_root() {
+            return Err(EPERM);
         }
 
-        // no inode found, make a new one
-        let ino = self.next_ino();
+        let mut attr = inode.fat_attr(&self.fat_fs).map_err(|_| EIO)?;
+        attr.set(flag, set);
 
-        let Some(parent_inode) = self.get_inode(parent.ino()).cloned() else {
-            // TODO: what do we do here? should not happen
-            panic!("parent_ino {} does not lead to inode", parent.ino());
+        self.mark_dirty();
+
+        inode.set_fat_attr(&self.fat_fs, attr).map_err(|err| {
+            debug!("failed to write attributes of inode {ino}: {err}");
+
+            EIO
+        })
+    }
+
+    /// handle the ioctls of the Linux FAT driver for reading and writing the FAT attributes
+    ///
+    /// both pass the attributes as a native endian u32. returns the data to send back, which is
+    /// empty for FAT_IOCTL_SET_ATTRIBUTES
+    fn fat_ioctl(&mut self, ino: u64, cmd: u32, in_data: &[u8]) -> Result<Vec<u8>, i32> {
+        let inode = self.get_inode(ino).cloned().ok_or(ENOENT)?;
+
+        match cmd {
+            FAT_IOCTL_GET_ATTRIBUTES => {
+                let attr = inode.borrow().fat_attr(&self.fat_fs).map_err(|err| {
+                    debug!("failed to read attributes of inode {ino}: {err}");
+
+                    EIO
+                })?;
+
+                Ok((attr.bits() as u32).to_ne_bytes().to_vec())
+            }
+            FAT_IOCTL_SET_ATTRIBUTES => {
+                let bytes: [u8; 4] = in_data.try_into().map_err(|_| EINVAL)?;
+
+                let bits = u8::try_from(u32::from_ne_bytes(bytes)).map_err(|_| EINVAL)?;
+                let attr = Attr::from_bits(bits).ok_or(EINVAL)?;
+
+                let mut inode = inode.borrow_mut();
+
+                if inode.is_root() {
+                    return Err(EPERM);
+                }
+
+                self.mark_dirty();
+
+                // the directory and volume id bits are kept as they are
+                inode.set_fat_attr(&self.fat_fs, attr).map_err(|err| {
+                    debug!("failed to write attributes of inode {ino}: {err}");
+
+                    EIO
+                })?;
+
+                Ok(Vec::new())
+            }
+            _ => {
+                debug!("unsupported ioctl {cmd:#X} on inode {ino}");
+
+                Err(ENOTTY)
+            }
+        }
+    }
+
+    /// drop `nlookup` references to inode `ino`, and the inode itself once none are left
+    ///
+    /// the root inode is never dropped
+    fn forget_inode(&mut self, ino: u64, nlookup: u64) {
+        self.lookup_cache.remove_ino(ino);
+
+        let Some(inode) = self.get_inode(ino).cloned() else {
+            debug!("tried to forget {} refs of inode {}, but was not found", ino, nlookup);
+
+            return;
         };
 
-        let inode =
-            Inode::new(&self.fat_fs, dir_entry, ino, self.uid, self.gid, path, parent_inode);
+        let mut inode_ref = inode.borrow_mut();
 
-        self.insert_inode(inode)
+        let ref_count = inode_ref.dec_ref_count(nlookup);
+
+        if ino == inode::ROOT_INO {
+            debug!("not dropping root inode (ref count: {ref_count})");
+
+            return;
+        }
+
+        if ref_count == 0 {
+            debug!("dropping inode {}", inode_ref.ino());
+
+            drop(inode_ref);
+
+            // no more references, drop inode
+            self.drop_inode(inode);
+        }
     }
 
-    pub fn get_inode_by_first_cluster(&self, first_cluster: u32) -> Option<InodeRef> {
-        if first_cluster == 0 {
-            debug!("trying to get inode by first cluster 0");
+    /// remove the empty directory `name` from the directory with ino `parent`
+    ///
+    /// frees the directory's clusters and drops its inode
+    fn remove_dir(&mut self, parent: u64, name: &str) -> Result<(), i32> {
+        let Some(parent_inode) = self.get_inode(parent).cloned() else {
+            debug!("parent inode {parent} does not exist");
 
-            return None;
+            return Err(ENOENT);
+        };
+
+        let parent_inode = parent_inode.borrow();
+
+        let dir_entry = parent_inode
+            .find_child_by_name(&self.fat_fs, name)
+            .inspect_err(|_| debug!("parent inode {parent} has no child {name}"))?;
+
+        if !dir_entry.is_dir() {
+            return Err(ENOTDIR);
         }
 
-        let ino = self.ino_by_first_cluster.get(&first_cluster)?;
+        if dir_entry.is_dot() {
+            return Err(EINVAL);
+        }
 
-        if let Some(inode) = self.inode_table.get(ino) {
-            Some(Rc::clone(inode))
+        if dir_entry.is_dotdot() {
+            return Err(ENOTEMPTY);
+        }
+
+        let first_cluster = dir_entry.first_cluster();
+
+        if first_cluster != 0 && !self.fat_fs.dir_iter(first_cluster).is_empty_dir() {
+            debug!("directory {name} is not empty");
+
+            return Err(ENOTEMPTY);
+        }
+
+        let parent_cluster = if parent_inode.is_root() {
+            0
         } else {
-            debug!(
-                "first cluster {} is mapped to ino {}, but inode is not in table",
-                first_cluster, ino
-            );
+            parent_inode.first_cluster()
+        };
 
-            None
+        drop(parent_inode);
+
+        self.mark_dirty();
+
+        if let Err(err) = self.fat_fs.remove_dir_entry(parent_cluster, dir_entry) {
+            debug!("error while removing {name}: {err}");
+
+            return Err(EIO);
         }
+
+        if first_cluster != 0
+            && let Some(inode) = self.get_inode_by_first_cluster(first_cluster)
+        {
+            self.drop_inode(inode);
+        }
+
+        Ok(())
     }
 
-    pub fn get_inode_by_fh(&self, fh: u64) -> Option<&InodeRef> {
-        let ino = *self.ino_by_fh.get(&fh)?;
+    /// remove the file `name` from the directory with ino `parent`
+    ///
+    /// frees the file's clusters and drops its inode
+    fn remove_file(&mut self, parent: u64, name: &str) -> Result<(), i32> {
+        let Some(parent_inode) = self.get_inode(parent).cloned() else {
+            debug!("parent inode {parent} does not exist");
 
-        if let Some(inode) = self.get_inode(ino) {
-            Some(inode)
+            return Err(ENOENT);
+        };
+
+        let parent_inode = parent_inode.borrow();
+
+        let dir_entry = parent_inode
+            .find_child_by_name(&self.fat_fs, name)
+            .inspect_err(|_| debug!("parent inode {parent} has no child {name}"))?;
+
+        if dir_entry.is_dir() {
+            return Err(EISDIR);
+        }
+
+        let parent_cluster = if parent_inode.is_root() {
+            0
         } else {
-            debug!("fh {} is mapped to ino {}, but inode is not in table", fh, ino);
+            parent_inode.first_cluster()
+        };
 
-            None
+        let inode = if dir_entry.first_cluster() != 0 {
+            self.get_inode_by_first_cluster(dir_entry.first_cluster())
+        } else {
+            self.get_inode_by_path(&Self::child_path(&parent_inode, &dir_entry))
+        };
+
+        drop(parent_inode);
+
+        self.mark_dirty();
+
+        if let Err(err) = self.fat_fs.remove_dir_entry(parent_cluster, dir_entry) {
+            debug!("error while removing {name}: {err}");
+
+            return Err(EIO);
+        }
+
+        if let Some(inode) = inode {
+            self.drop_inode(inode);
         }
+
+        Ok(())
     }
 
-    pub fn get_inode_by_path(&self, path: &str) -> Option<InodeRef> {
-        let ino = *self.ino_by_path.get(path)?;
+    /// change the owner of `inode`, only in memory
+    fn chown_inode(
+        &self,
+        inode: &mut Inode,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> Result<(), i32> {
+        if uid.is_none() && gid.is_none() {
+            return Ok(());
+        }
 
-        if let Some(inode) = self.get_inode(ino).cloned() {
-            Some(inode)
-        } else {
-            debug!("path {} is mapped to ino {}, but inode is not in table", path, ino);
+        if self.strict_chown {
+            debug!("can't change owner of inode {}, FAT has no ownership", inode.ino());
 
-            None
+            return Err(EPERM);
+        }
+
+        inode.set_owner(uid, gid);
+
+        Ok(())
+    }
+
+    /// apply the permission bits `mode` to `inode`
+    ///
+    /// FAT only has a read-only attribute, which is set iff `mode` grants no write permission
+    fn chmod_inode(&mut self, inode: &mut Inode, mode: u32) -> Result<(), i32> {
+        let read_only = mode & 0o222 == 0;
+
+        if read_only == inode.is_read_only() {
+            return Ok(());
+        }
+
+        if inode.is_root() {
+            debug!("root dir has no attributes, ignoring mode {mode:o}");
+
+            return Ok(());
+        }
+
+        let mut attr = inode.fat_attr(&self.fat_fs).map_err(|_| EIO)?;
+        attr.set(Attr::ReadOnly, read_only);
+
+        self.mark_dirty();
+
+        inode.set_fat_attr(&self.fat_fs, attr).map_err(|err| {
+            debug!("failed to write attributes of inode {}: {err}", inode.ino());
+
+            EIO
+        })
+    }
+
+    /// update the access and modification times of `inode`
+    ///
+    /// times set to now are written to the DirEntry directly, independently of each other, specific
+    /// times only update the inode
+    fn set_times(
+        &mut self,
+        inode: &mut Inode,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+    ) -> Result<(), i32> {
+        let touch_err = |err: anyhow::Error| {
+            debug!("touching inode failed: {err}");
+
+            EIO
+        };
+
+        if let (Some(TimeOrNow::Now), Some(TimeOrNow::Now)) = (atime, mtime) {
+            // plain touch, update timestamps in place
+            return inode.touch(&mut self.fat_fs).map_err(touch_err);
         }
+
+        match atime {
+            Some(TimeOrNow::Now) => inode.touch_atime(&mut self.fat_fs).map_err(touch_err)?,
+            Some(TimeOrNow::SpecificTime(atime)) => inode.update_atime(atime),
+            None => {}
+        }
+
+        match mtime {
+            Some(TimeOrNow::Now) => inode.touch_mtime(&mut self.fat_fs).map_err(touch_err)?,
+            Some(TimeOrNow::SpecificTime(mtime)) => inode.update_mtime(mtime),
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    /// set the access time of `inode` to now, writing it back right away if `write_back` is set
+    ///
+    /// does nothing on a read-only mount, whose backing storage might not even be writable
+    fn record_access(&self, inode: &mut Inode, write_back: bool) -> anyhow::Result<()> {
+        if self.is_read_only() {
+            return Ok(());
+        }
+
+        inode.update_atime(SystemTime::now());
+
+        if write_back {
+            inode.write_back(&self.fat_fs)?;
+        }
+
+        Ok(())
+    }
+
+    /// shrink or grow the file of `inode` to `new_size` bytes
+    ///
+    /// bytes past the old size read as zeros. the inode is left dirty, so the caller has to write
+    /// it back
+    fn truncate_file(&mut self, inode: &mut Inode, new_size: u64) -> Result<(), i32> {
+        if !inode.is_file() {
+            return Err(EISDIR);
+        }
+
+        if new_size > u32::MAX as u64 {
+            return Err(EFBIG);
+        }
+
+        let old_size = inode.size();
+        let old_first_cluster = inode.first_cluster();
+
+        self.mark_dirty();
+
+        let first_cluster = self.fat_fs.truncate(old_first_cluster, new_size).map_err(|err| {
+            debug!("failed to truncate inode {} to {new_size} bytes: {err}", inode.ino());
+
+            EIO
+        })?;
+
+        if first_cluster != old_first_cluster {
+            if self.ino_by_first_cluster.get(&old_first_cluster) == Some(&inode.ino()) {
+                self.ino_by_first_cluster.remove(&old_first_cluster);
+            }
+
+            if first_cluster != 0 {
+                self.ino_by_first_cluster.insert(first_cluster, inode.ino());
+            }
+
+            inode.set_first_cluster(first_cluster);
+        }
+
+        let bytes_per_cluster = self.fat_fs.bytes_per_cluster() as u64;
+
+        if new_size > old_size
+            && old_first_cluster != 0
+            && !old_size.is_multiple_of(bytes_per_cluster)
+        {
+            // clusters added by truncate are zeroed, but the old last cluster may contain garbage
+            let end = new_size.min(old_size.next_multiple_of(bytes_per_cluster));
+
+            let mut writer = self.fat_fs.file_writer(first_cluster);
+
+            if writer.skip(old_size) != old_size
+                || writer.write_all(&vec![0; (end - old_size) as usize]).is_err()
+                || writer.finish().is_err()
+            {
+                debug!("failed to zero bytes {old_size}..{end} of inode {}", inode.ino());
+
+                return Err(EIO);
+            }
+        }
+
+        inode.update_size(new_size);
+
+        Ok(())
+    }
+
+    /// names and inodes of the entries of the directory `dir_inode`, skipping the first `offset`
+    ///
+    /// . and .. resolve to the directory itself and its parent. they are not stored on disk for the
+    /// root dir, so they get added here
+    ///
+    /// if the directory is open as `fh`, its entries are cached between calls. the cache is
+    /// refilled at offset 0, so rewinding the directory picks up any changes
+    fn list_dir(
+        &mut self,
+        dir_inode: &InodeRef,
+        fh: Option<u64>,
+        offset: usize,
+    ) -> Result<Vec<(String, InodeRef)>, i32> {
+        let dir = dir_inode.borrow();
+
+        let parent_inode = dir.parent().cloned().unwrap_or_else(|| Rc::clone(dir_inode));
+
+        let mut entries = Vec::new();
+
+        if dir.is_root() {
+            entries.push((".".to_owned(), Rc::clone(dir_inode)));
+            entries.push(("..".to_owned(), Rc::clone(dir_inode)));
+        }
+
+        let n_synthetic = entries.len();
+
+        entries.drain(..offset.min(n_synthetic));
+
+        let skip = offset.saturating_sub(n_synthetic);
+
+        // collect first, so the DirIter is gone before new inodes get made
+        let dir_entries: Vec<DirEntry> = match fh {
+            Some(fh) => {
+                if offset == 0 || self.dir_entry_cache.get(fh).is_none() {
+                    let dir_entries = dir.dir_iter(&self.fat_fs)?.collect();
+
+                    self.dir_entry_cache.insert(fh, dir_entries);
+                }
+
+                let cached = self.dir_entry_cache.get(fh).unwrap();
+
+                cached.iter().skip(skip).cloned().collect()
+            }
+            None => dir.dir_iter(&self.fat_fs)?.skip(skip).collect(),
+        };
+
+        for dir_entry in dir_entries {
+            let inode = if dir_entry.is_dot() {
+                Rc::clone(dir_inode)
+            } else if dir_entry.is_dotdot() {
+                Rc::clone(&parent_inode)
+            } else {
+                self.get_or_make_inode(&dir_entry, &dir)
+            };
+
+            entries.push((dir_entry.name_string().into(), inode));
+        }
+
+        Ok(entries)
+    }
+
+    /// first cluster (0 for the root dir) of the directory with ino `parent`, checking that
+    /// `name` does not exist in it yet
+    fn dir_cluster_for_new_child(&self, parent: u64, name: &str) -> Result<(InodeRef, u32), i32> {
+        let Some(parent_inode) = self.get_inode(parent).cloned() else {
+            debug!("parent inode {parent} does not exist");
+
+            return Err(ENOENT);
+        };
+
+        let dir_cluster = {
+            let parent_inode = parent_inode.borrow();
+
+            if !parent_inode.is_dir() {
+                return Err(ENOTDIR);
+            }
+
+            if parent_inode.find_child_by_name(&self.fat_fs, name).is_ok() {
+                return Err(EEXIST);
+            }
+
+            if parent_inode.is_root() {
+                0
+            } else {
+                parent_inode.first_cluster()
+            }
+        };
+
+        Ok((parent_inode, dir_cluster))
+    }
+
+    /// create an empty file `name` in the directory with ino `parent`
+    ///
+    /// the file is read-only if `mode` grants no write permission at all
+    fn create_file(&mut self, parent: u64, name: &str, mode: u32) -> Result<InodeRef, i32> {
+        let (parent_inode, dir_cluster) = self.dir_cluster_for_new_child(parent, name)?;
+
+        let mut attr = Attr::Archive;
+
+        if mode & 0o222 == 0 {
+            attr |= Attr::ReadOnly;
+        }
+
+        self.mark_dirty();
+
+        let dir_entry = match self.fat_fs.create_file(dir_cluster, name, attr) {
+            Ok(dir_entry) => dir_entry,
+            Err(err) => {
+                debug!("failed to create file {name}: {err}");
+
+                return Err(EIO);
+            }
+        };
+
+        Ok(self.get_or_make_inode(&dir_entry, &parent_inode.borrow()))
+    }
+
+    /// create the node `name` in the directory with ino `parent`
+    ///
+    /// only regular files are supported, FAT has no notion of devices, FIFOs, or sockets
+    fn make_node(&mut self, parent: u64, name: &str, mode: u32) -> Result<InodeRef, i32> {
+        if mode & libc::S_IFMT != libc::S_IFREG {
+            debug!("can't create node {name} with mode {mode:o}, only regular files are supported");
+
+            return Err(EPERM);
+        }
+
+        self.create_file(parent, name, mode)
+    }
+
+    /// create an empty directory `name` in the directory with ino `parent`
+    fn make_dir(&mut self, parent: u64, name: &str) -> Result<InodeRef, i32> {
+        let (parent_inode, dir_cluster) = self.dir_cluster_for_new_child(parent, name)?;
+
+        self.mark_dirty();
+
+        let dir_entry = match self.fat_fs.create_dir(dir_cluster, name) {
+            Ok(dir_entry) => dir_entry,
+            Err(err) => {
+                debug!("failed to create directory {name}: {err}");
+
+                return Err(EIO);
+            }
+        };
+
+        Ok(self.get_or_make_inode(&dir_entry, &parent_inode.borrow()))
+    }
+
+    /// move `name` in the directory with ino `parent` to `new_name` in the directory with ino
+    /// `new_parent`
+    ///
+    /// with `no_replace` an existing `new_name` is an error instead of being replaced
+    fn rename_entry(
+        &mut self,
+        parent: u64,
+        name: &str,
+        new_parent: u64,
+        new_name: &str,
+        no_replace: bool,
+    ) -> Result<(), i32> {
+        let (Some(parent_inode), Some(new_parent_inode)) =
+            (self.get_inode(parent).cloned(), self.get_inode(new_parent).cloned())
+        else {
+            debug!("parent inode {parent} or {new_parent} does not exist");
+
+            return Err(ENOENT);
+        };
+
+        let (src, dst, src_cluster, dst_cluster) = {
+            let parent_inode = parent_inode.borrow();
+            let new_parent_inode = new_parent_inode.borrow();
+
+            let src = parent_inode.find_child_by_name(&self.fat_fs, name)?;
+
+            if src.is_dot_or_dotdot() {
+                return Err(EINVAL);
+            }
+
+            let dst = match new_parent_inode.find_child_by_name(&self.fat_fs, new_name) {
+                Ok(dst) => Some(dst),
+                Err(ENOENT) => None,
+                Err(err) => return Err(err),
+            };
+
+            let dir_cluster = |inode: &Inode| {
+                if inode.is_root() {
+                    0
+                } else {
+                    inode.first_cluster()
+                }
+            };
+
+            (src, dst, dir_cluster(&parent_inode), dir_cluster(&new_parent_inode))
+        };
+
+        let mut replaced_inode = None;
+
+        if let Some(dst) = dst
+            && dst.offset() != src.offset()
+        {
+            if no_replace {
+                return Err(EEXIST);
+            }
+
+            if dst.is_dir() && !src.is_dir() {
+                return Err(EISDIR);
+            }
+
+            if !dst.is_dir() && src.is_dir() {
+                return Err(ENOTDIR);
+            }
+
+            if dst.is_dir() && !self.fat_fs.dir_iter(dst.first_cluster()).is_empty_dir() {
+                return Err(ENOTEMPTY);
+            }
+
+            let dst_path = Self::child_path(&new_parent_inode.borrow(), &dst);
+
+            replaced_inode = self.get_inode_by_path(&dst_path);
+        }
+
+        let old_path = Self::child_path(&parent_inode.borrow(), &src);
+
+        let inode = if src.first_cluster() != 0 {
+            self.get_inode_by_first_cluster(src.first_cluster())
+        } else {
+            self.get_inode_by_path(&old_path)
+        };
+
+        self.mark_dirty();
+
+        // names may now resolve to different inodes, or not at all
+        self.lookup_cache.clear();
+
+        let renamed = match self.fat_fs.rename(src_cluster, name, dst_cluster, new_name) {
+            Ok(renamed) => renamed,
+            Err(err) => {
+                debug!("failed to rename {name} to {new_name}: {err}");
+
+                return Err(EIO);
+            }
+        };
+
+        if let Some(replaced_inode) = replaced_inode {
+            self.drop_inode(replaced_inode);
+        }
+
+        if let Some(inode) = inode {
+            let mut inode = inode.borrow_mut();
+
+            inode.set_parent(new_parent_inode.clone());
+            inode.set_dir_entry_offset(renamed.offset());
+        }
+
+        let new_path = Self::child_path(&new_parent_inode.borrow(), &renamed);
+
+        self.move_paths(&old_path, &new_path);
+
+        Ok(())
+    }
+
+    /// point all inodes at or below `old_path` to the corresponding paths below `new_path`
+    fn move_paths(&mut self, old_path: &str, new_path: &str) {
+        let prefix = format!("{old_path}/");
+
+        for (&ino, inode) in self.inode_table.iter() {
+            let mut inode = inode.borrow_mut();
+
+            let path = inode.path();
+
+            if path.as_ref() != old_path && !path.starts_with(&prefix) {
+                continue;
+            }
+
+            let moved_path: Rc<str> = format!("{new_path}{}", &path[old_path.len()..]).into();
+
+            if self.ino_by_path.get(&path) == Some(&ino) {
+                self.ino_by_path.remove(&path);
+            }
+
+            debug!("moving ino {} from {} to {}", ino, path, moved_path);
+
+            self.ino_by_path.insert(Rc::clone(&moved_path), ino);
+
+            inode.set_path(moved_path);
+        }
+    }
+
+    /// number of file handles that have been opened, but not yet released
+    pub fn active_file_handle_count(&self) -> usize {
+        self.ino_by_fh.len()
+    }
+
+    /// write all pending changes back to disk
+    ///
+    /// only touches the FAT and the underlying storage if something changed since the last flush
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        for inode in self.inode_table.values() {
+            let mut inode = inode.borrow_mut();
+
+            if !inode.is_dirty() {
+                continue;
+            }
+
+            self.dirty = true;
+
+            if let Err(err) = inode.write_back(&self.fat_fs) {
+                error!("failed to write back inode {}: {err}", inode.ino());
+            }
+        }
+
+        if !self.dirty {
+            return Ok(());
+        }
+
+        self.fat_fs.flush()?;
+
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    /// record that the FS was changed and needs to be flushed
+    ///
+    /// also drops all cached directory listings, as they might be out of date now
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+
+        self.dir_entry_cache.clear();
+    }
+
+    /// rebuild `ino_by_first_cluster` and `ino_by_path` from the inode table
+    ///
+    /// recovery operation in case the lookup tables got out of sync with `inode_table`, not meant
+    /// to be called on a hot path
+    pub fn rebuild_lookup_tables(&mut self) {
+        debug!("rebuilding lookup tables from {} inodes", self.inode_table.len());
+
+        self.ino_by_first_cluster.clear();
+        self.ino_by_path.clear();
+
+        for (&ino, inode) in self.inode_table.iter() {
+            let inode = inode.borrow();
+
+            if inode.ino() != ino {
+                error!("inode table maps ino {} to inode with ino {}", ino, inode.ino());
+            }
+
+            let first_cluster = inode.first_cluster();
+
+            if first_cluster != 0
+                && let Some(old_ino) = self.ino_by_first_cluster.insert(first_cluster, ino)
+            {
+                debug!("first cluster {} is shared by inos {} and {}", first_cluster, old_ino, ino);
+            }
+
+            let path = inode.path();
+
+            if let Some(old_ino) = self.ino_by_path.insert(Rc::clone(&path), ino) {
+                debug!("path {} is shared by inos {} and {}", path, old_ino, ino);
+            }
+        }
+    }
+
+    /// panic if the lookup tables are not consistent with the inode table
+    pub fn assert_consistent(&self) {
+        for (&ino, inode) in self.inode_table.iter() {
+            let inode = inode.borrow();
+
+            assert_eq!(inode.ino(), ino, "inode table maps ino {} to ino {}", ino, inode.ino());
+
+            let first_cluster = inode.first_cluster();
+
+            if first_cluster != 0 {
+                assert_eq!(
+                    self.ino_by_first_cluster.get(&first_cluster),
+                    Some(&ino),
+                    "first cluster {} of ino {} is not mapped to it",
+                    first_cluster,
+                    ino
+                );
+            }
+
+            assert_eq!(
+                self.ino_by_path.get(&inode.path()),
+                Some(&ino),
+                "path {} of ino {} is not mapped to it",
+                inode.path(),
+                ino
+            );
+        }
+
+        for (&first_cluster, ino) in self.ino_by_first_cluster.iter() {
+            assert!(
+                self.inode_table.contains_key(ino),
+                "first cluster {} is mapped to ino {}, but inode is not in table",
+                first_cluster,
+                ino
+            );
+        }
+
+        for (path, ino) in self.ino_by_path.iter() {
+            assert!(
+                self.inode_table.contains_key(ino),
+                "path {} is mapped to ino {}, but inode is not in table",
+                path,
+                ino
+            );
+        }
+    }
+
+    /// `assert_consistent`, but only in debug builds
+    fn debug_assert_consistent(&self) {
+        if cfg!(debug_assertions) {
+            self.assert_consistent();
+        }
+    }
+
+    fn get_inode(&self, ino: u64) -> Option<&InodeRef> {
+        self.inode_table.get(&ino)
+    }
+
+    /// inode `ino`, which has to be a file
+    fn get_file_inode(&self, ino: u64) -> Result<InodeRef, i32> {
+        let inode = self.get_inode(ino).ok_or(ENOENT)?;
+
+        if inode.borrow().is_dir() {
+            return Err(EISDIR);
+        }
+
+        Ok(Rc::clone(inode))
+    }
+
+    /// inode `ino`, which has to be a directory
+    fn get_dir_inode(&self, ino: u64) -> Result<InodeRef, i32> {
+        let inode = self.get_inode(ino).ok_or(ENOENT)?;
+
+        if !inode.borrow().is_dir() {
+            return Err(ENOTDIR);
+        }
+
+        Ok(Rc::clone(inode))
+    }
+
+    /// inode of the child `name` of the directory with ino `parent`
+    ///
+    /// recent lookups are cached, so resolving a deep path doesn't rescan every directory on the
+    /// way each time
+    fn lookup_child(&mut self, parent: u64, name: &str) -> Result<InodeRef, i32> {
+        if let Some(inode) = self.lookup_cache.get(parent, name) {
+            let ino = inode.borrow().ino();
+
+            // only trust the cache as long as the inode is still the one in the table
+            if self.get_inode(ino).is_some_and(|found| Rc::ptr_eq(found, &inode)) {
+                return Ok(inode);
+            }
+
+            self.lookup_cache.remove_ino(ino);
+        }
+
+        let Some(parent_inode) = self.get_inode(parent).cloned() else {
+            // parent inode does not exist
+            // TODO: how can we make sure this does not happed?
+            debug!("could not find inode for parent ino {}", parent);
+
+            return Err(ENOENT);
+        };
+
+        let parent_inode = parent_inode.borrow();
+
+        let dir_entry = parent_inode.find_child_by_name(&self.fat_fs, name)?;
+
+        let inode = self.get_or_make_inode_readonly(&dir_entry, &parent_inode);
+
+        self.lookup_cache.insert(parent, name, Rc::clone(&inode));
+
+        Ok(inode)
+    }
+
+    fn get_or_make_inode(&mut self, dir_entry: &DirEntry, parent: &Inode) -> InodeRef {
+        // let parent = parent.borrow();
+
+        // try to find inode by first cluster first
+        if dir_entry.first_cluster() != 0
+            && let Some(inode) = self.get_inode_by_first_cluster(dir_entry.first_cluster())
+        {
+            return inode;
+        }
+
+        // try to find inode by path
+        // mostly for empty files/directories which have a first cluster of 0
+
+        let path = Self::child_path(parent, dir_entry);
+
+        if let Some(inode) = self.get_inode_by_path(&path) {
+            return inode;
+        }
+
+        // no inode found, make a new one
+        let ino = self.next_ino();
+
+        let Some(parent_inode) = self.get_inode(parent.ino()).cloned() else {
+            // TODO: what do we do here? should not happen
+            panic!("parent_ino {} does not lead to inode", parent.ino());
+        };
+
+        let inode =
+            Inode::new(&self.fat_fs, dir_entry, ino, self.uid, self.gid, path, parent_inode);
+
+        self.insert_inode(inode)
+    }
+
+    /// like `get_or_make_inode`, but never evicts anything from the lookup tables
+    ///
+    /// a new inode only gets mapped by first cluster and path if those aren't taken already
+    fn get_or_make_inode_readonly(&mut self, dir_entry: &DirEntry, parent: &Inode) -> InodeRef {
+        if dir_entry.first_cluster() != 0
+            && let Some(inode) = self.get_inode_by_first_cluster(dir_entry.first_cluster())
+        {
+            return inode;
+        }
+
+        let path = Self::child_path(parent, dir_entry);
+
+        if let Some(inode) = self.get_inode_by_path(&path) {
+            return inode;
+        }
+
+        let ino = self.next_ino();
+
+        let Some(parent_inode) = self.get_inode(parent.ino()).cloned() else {
+            // TODO: what do we do here? should not happen
+            panic!("parent_ino {} does not lead to inode", parent.ino());
+        };
+
+        let inode = Rc::new(RefCell::new(Inode::new(
+            &self.fat_fs,
+            dir_entry,
+            ino,
+            self.uid,
+            self.gid,
+            path,
+            parent_inode,
+        )));
+
+        // ino is fresh, so nothing gets replaced here
+        self.inode_table.insert(ino, Rc::clone(&inode));
+
+        let (first_cluster, path) = {
+            let inode = inode.borrow();
+
+            (inode.first_cluster(), inode.path())
+        };
+
+        if first_cluster != 0 {
+            self.ino_by_first_cluster.entry(first_cluster).or_insert(ino);
+        }
+
+        self.ino_by_path.entry(path).or_insert(ino);
+
+        debug!("inserted new inode with ino {} (first cluster: {})", ino, first_cluster);
+
+        inode
+    }
+
+    /// path of the child `dir_entry` of `parent`
+    fn child_path(parent: &Inode, dir_entry: &DirEntry) -> String {
+        let mut path = parent.path().as_ref().to_owned();
+
+        if parent.ino() != inode::ROOT_INO {
+            // root inode already has trailing slash
+            path.push('/');
+        }
+
+        path += &dir_entry.name_string();
+
+        path
+    }
+
+    pub fn get_inode_by_first_cluster(&self, first_cluster: u32) -> Option<InodeRef> {
+        if first_cluster == 0 {
+            debug!("trying to get inode by first cluster 0");
+
+            return None;
+        }
+
+        let ino = self.ino_by_first_cluster.get(&first_cluster)?;
+
+        if let Some(inode) = self.inode_table.get(ino) {
+            Some(Rc::clone(inode))
+        } else {
+            debug!(
+                "first cluster {} is mapped to ino {}, but inode is not in table",
+                first_cluster, ino
+            );
+
+            None
+        }
+    }
+
+    pub fn get_inode_by_fh(&self, fh: u64) -> Option<&InodeRef> {
+        let ino = *self.ino_by_fh.get(&fh)?;
+
+        if let Some(inode) = self.get_inode(ino) {
+            Some(inode)
+        } else {
+            debug!("fh {} is mapped to ino {}, but inode is not in table", fh, ino);
+
+            None
+        }
+    }
+
+    /// full cluster chain of the inode at `path`
+    ///
+    /// debugging aid, returns an empty vec for empty files or if `path` does not lead to a known
+    /// inode
+    pub fn get_cluster_chain(&self, path: &str) -> Vec<u32> {
+        let Some(inode) = self.get_inode_by_path(path) else {
+            debug!("path {} is not associated with an inode", path);
+
+            return Vec::new();
+        };
+
+        let first_cluster = inode.borrow().first_cluster();
+
+        let mut chain = Vec::new();
+
+        if first_cluster == 0 {
+            return chain;
+        }
+
+        let mut cluster = Some(first_cluster);
+
+        while let Some(cur_cluster) = cluster {
+            if chain.len() > self.fat_fs.cluster_count() as usize {
+                // chain is longer than there are clusters, so it must contain a cycle
+                debug!("cluster chain of {} contains a cycle", path);
+
+                break;
+            }
+
+            chain.push(cur_cluster);
+
+            cluster = match self.fat_fs.next_cluster(cur_cluster) {
+                Ok(next_cluster) => next_cluster,
+                Err(err) => {
+                    debug!("cluster chain of {} is broken at {}: {}", path, cur_cluster, err);
+
+                    None
+                }
+            };
+        }
+
+        chain
+    }
+
+    pub fn get_inode_by_path(&self, path: &str) -> Option<InodeRef> {
+        let ino = *self.ino_by_path.get(path)?;
+
+        if let Some(inode) = self.get_inode(ino).cloned() {
+            Some(inode)
+        } else {
+            debug!("path {} is mapped to ino {}, but inode is not in table", path, ino);
+
+            None
+        }
+    }
+}
+
+/// resolve an lseek of `offset` relative to `whence` in a file of `size` bytes
+///
+/// FAT has no sparse files, so all of the file is data and the only hole is the one at EOF
+fn seek_offset(size: u64, offset: i64, whence: i32) -> Result<i64, i32> {
+    let size = size as i64;
+
+    let new_offset = match whence {
+        libc::SEEK_SET => offset,
+        libc::SEEK_END => size.checked_add(offset).ok_or(EINVAL)?,
+        libc::SEEK_DATA => {
+            if offset >= size {
+                return Err(ENXIO);
+            }
+
+            offset
+        }
+        libc::SEEK_HOLE => {
+            if offset >= size {
+                return Err(ENXIO);
+            }
+
+            size
+        }
+        // SEEK_CUR needs the file position, which only the kernel knows
+        _ => return Err(EINVAL),
+    };
+
+    if new_offset < 0 {
+        return Err(EINVAL);
+    }
+
+    Ok(new_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use libc::{
+        EACCES, EEXIST, EINVAL, EIO, EISDIR, ENODATA, ENOENT, ENOTDIR, ENOTEMPTY, ENOTSUP, ENOTTY,
+        ENXIO, EPERM, EROFS,
+    };
+
+    use std::rc::Rc;
+
+    use fat_bits::{Attr, DirEntry, SliceLike};
+
+    use super::{FatFuse, LookupCache, MountMode, seek_offset};
+    use crate::inode::{Inode, InodeRef, ROOT_INO, ROOT_REF_COUNT};
+
+    /// build a tiny FAT12 image: one sector per cluster, a single FAT and a one sector root dir
+    fn fat12_image() -> &'static mut [u8] {
+        const TOTAL_SECTORS: u16 = 64;
+
+        let mut image = vec![0; TOTAL_SECTORS as usize * 512];
+
+        let bpb = &mut image[..512];
+
+        bpb[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        bpb[3..11].copy_from_slice(b"MSWIN4.1");
+        bpb[11..13].copy_from_slice(&512u16.to_le_bytes());
+        bpb[13] = 1;
+        bpb[14..16].copy_from_slice(&1u16.to_le_bytes());
+        bpb[16] = 1;
+        bpb[17..19].copy_from_slice(&16u16.to_le_bytes());
+        bpb[19..21].copy_from_slice(&TOTAL_SECTORS.to_le_bytes());
+        bpb[21] = 0xF8;
+        bpb[22..24].copy_from_slice(&1u16.to_le_bytes());
+        bpb[36] = 0x80;
+        bpb[54..62].copy_from_slice(b"FAT12   ");
+        bpb[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        // media descriptor and EOF in the two reserved entries
+        image[512..515].copy_from_slice(&[0xF8, 0xFF, 0xFF]);
+
+        Box::leak(image.into_boxed_slice())
+    }
+
+    /// backing store that can be read, but rejects every write like a file opened read-only
+    struct ReadOnlyImage(Vec<u8>);
+
+    impl SliceLike for ReadOnlyImage {
+        fn read_at_offset(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+            let offset = offset as usize;
+
+            buf.copy_from_slice(&self.0[offset..offset + buf.len()]);
+
+            Ok(())
+        }
+
+        fn write_at_offset(&mut self, _offset: u64, _bytes: &[u8]) -> std::io::Result<()> {
+            Err(std::io::Error::from_raw_os_error(libc::EBADF))
+        }
+
+        fn byte_len(&self) -> std::io::Result<u64> {
+            Ok(self.0.len() as u64)
+        }
+    }
+
+    fn make_dirs(fat_fuse: &mut FatFuse, names: &[&str]) -> Vec<DirEntry> {
+        names.iter().map(|name| fat_fuse.fat_fs.create_dir(0, name).unwrap()).collect()
+    }
+
+    fn make_inode(fat_fuse: &FatFuse, dir_entry: &DirEntry, ino: u64) -> Inode {
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+
+        let path = format!("/{}", dir_entry.name_string());
+
+        Inode::new(
+            &fat_fuse.fat_fs,
+            dir_entry,
+            ino,
+            fat_fuse.uid,
+            fat_fuse.gid,
+            path,
+            root_inode,
+        )
+    }
+
+    #[test]
+    fn inode_table_insert_drop() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+        fat_fuse.assert_consistent();
+
+        let dir_entries = make_dirs(&mut fat_fuse, &["A", "B", "C"]);
+
+        let mut inodes = Vec::new();
+
+        for dir_entry in dir_entries.iter() {
+            let ino = fat_fuse.next_ino();
+            let inode = make_inode(&fat_fuse, dir_entry, ino);
+
+            inodes.push(fat_fuse.insert_inode(inode));
+            fat_fuse.assert_consistent();
+        }
+
+        // drop in neither insertion nor reverse order
+        for idx in [1, 0, 2] {
+            fat_fuse.drop_inode(inodes[idx].clone());
+            fat_fuse.assert_consistent();
+        }
+
+        assert_eq!(fat_fuse.inode_table.len(), 1);
+        assert_eq!(fat_fuse.ino_by_path.len(), 1);
+    }
+
+    #[test]
+    fn inode_table_replace() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_entries = make_dirs(&mut fat_fuse, &["A"]);
+
+        let ino = fat_fuse.next_ino();
+
+        let inode = make_inode(&fat_fuse, &dir_entries[0], ino);
+        let old_inode = fat_fuse.insert_inode(inode);
+        fat_fuse.assert_consistent();
+
+        // same ino again replaces the old inode
+        let inode = make_inode(&fat_fuse, &dir_entries[0], ino);
+        let new_inode = fat_fuse.insert_inode(inode);
+        fat_fuse.assert_consistent();
+
+        assert!(Rc::ptr_eq(fat_fuse.get_inode(ino).unwrap(), &new_inode));
+
+        // dropping the stale inode takes the new one with it, since they share their ino
+        fat_fuse.drop_inode(old_inode);
+        fat_fuse.assert_consistent();
+
+        assert!(fat_fuse.get_inode(ino).is_none());
+
+        // dropping again is a no-op
+        fat_fuse.drop_inode(new_inode);
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn inode_table_get_or_make() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_entries = make_dirs(&mut fat_fuse, &["A", "B"]);
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+
+        let a = fat_fuse.get_or_make_inode(&dir_entries[0], &root_inode.borrow());
+        fat_fuse.assert_consistent();
+
+        let b = fat_fuse.get_or_make_inode(&dir_entries[1], &root_inode.borrow());
+        fat_fuse.assert_consistent();
+
+        // cached
+        let a2 = fat_fuse.get_or_make_inode(&dir_entries[0], &root_inode.borrow());
+        fat_fuse.assert_consistent();
+
+        assert!(Rc::ptr_eq(&a, &a2));
+
+        fat_fuse.drop_inode(b);
+        fat_fuse.assert_consistent();
+
+        fat_fuse.drop_inode(a);
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    #[should_panic(expected = "is not mapped to it")]
+    fn inode_table_inconsistent() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_entries = make_dirs(&mut fat_fuse, &["A"]);
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+
+        let a = fat_fuse.get_or_make_inode(&dir_entries[0], &root_inode.borrow());
+
+        fat_fuse.ino_by_path.remove(&a.borrow().path());
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn inode_table_rebuild() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_entries = make_dirs(&mut fat_fuse, &["A", "B"]);
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+
+        let a = fat_fuse.get_or_make_inode(&dir_entries[0], &root_inode.borrow());
+        let b = fat_fuse.get_or_make_inode(&dir_entries[1], &root_inode.borrow());
+
+        // lose some mappings and add stale ones
+        fat_fuse.ino_by_path.remove(&a.borrow().path());
+        fat_fuse.ino_by_first_cluster.remove(&b.borrow().first_cluster());
+        fat_fuse.ino_by_path.insert("/STALE".into(), 1234);
+        fat_fuse.ino_by_first_cluster.insert(1234, 1234);
+
+        fat_fuse.rebuild_lookup_tables();
+        fat_fuse.assert_consistent();
+
+        assert_eq!(fat_fuse.ino_by_path.len(), 3);
+        assert_eq!(fat_fuse.ino_by_first_cluster.len(), 2);
+
+        assert!(Rc::ptr_eq(&fat_fuse.get_inode_by_path("/A").unwrap(), &a));
+        assert!(fat_fuse.get_inode_by_path("/STALE").is_none());
+
+        assert!(Rc::ptr_eq(
+            &fat_fuse.get_inode_by_first_cluster(b.borrow().first_cluster()).unwrap(),
+            &b
+        ));
+    }
+
+    // no case-insensitive lookup yet, so stick to upper case names
+
+    #[test]
+    fn rmdir_not_empty() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_cluster = fat_fuse.fat_fs.create_dir(0, "DIR").unwrap().first_cluster();
+        fat_fuse.fat_fs.create_dir(dir_cluster, "SUBDIR").unwrap();
+
+        let free_clusters = fat_fuse.fat_fs.free_clusters();
+
+        assert_eq!(fat_fuse.remove_dir(ROOT_INO, "DIR"), Err(ENOTEMPTY));
+
+        // nothing may have been touched
+        assert_eq!(fat_fuse.fat_fs.free_clusters(), free_clusters);
+        assert!(fat_fuse.fat_fs.dir_contains(0, "DIR"));
+        assert!(fat_fuse.fat_fs.dir_contains(dir_cluster, "SUBDIR"));
+    }
+
+    #[test]
+    fn rmdir_empty() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let free_clusters = fat_fuse.fat_fs.free_clusters();
+
+        let dir_cluster = fat_fuse.fat_fs.create_dir(0, "DIR").unwrap().first_cluster();
+        fat_fuse.fat_fs.create_dir(dir_cluster, "SUBDIR").unwrap();
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+        let dir_entry = root_inode
+            .borrow()
+            .find_child_by_name(&fat_fuse.fat_fs, "DIR")
+            .unwrap();
+        let dir_inode = fat_fuse.get_or_make_inode(&dir_entry, &root_inode.borrow());
+        let dir_ino = dir_inode.borrow().ino();
+
+        assert_eq!(fat_fuse.remove_dir(dir_ino, "SUBDIR"), Ok(()));
+        assert_eq!(fat_fuse.remove_dir(ROOT_INO, "DIR"), Ok(()));
+
+        assert_eq!(fat_fuse.fat_fs.free_clusters(), free_clusters);
+        assert!(!fat_fuse.fat_fs.dir_contains(0, "DIR"));
+        assert!(fat_fuse.get_inode(dir_ino).is_none());
+
+        assert_eq!(fat_fuse.remove_dir(ROOT_INO, "DIR"), Err(ENOENT));
+    }
+
+    #[test]
+    fn find_child_by_name() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_entries = make_dirs(&mut fat_fuse, &["Some Dir", "OTHER"]);
+        let file_inode = fat_fuse.create_file(ROOT_INO, "file.txt", 0o644).unwrap();
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+        let root_inode = root_inode.borrow();
+
+        for (name, expected) in [("Some Dir", &dir_entries[0]), ("some dir", &dir_entries[0])] {
+            let dir_entry = root_inode.find_child_by_name(&fat_fuse.fat_fs, name).unwrap();
+
+            assert_eq!(dir_entry.offset(), expected.offset());
+        }
+
+        let dir_entry = root_inode.find_child_by_name(&fat_fuse.fat_fs, "other").unwrap();
+        assert_eq!(dir_entry.first_cluster(), dir_entries[1].first_cluster());
+
+        assert_eq!(root_inode.find_child_by_name(&fat_fuse.fat_fs, "FILE.TXT").map(|_| ()), Ok(()));
+
+        assert_eq!(
+            root_inode.find_child_by_name(&fat_fuse.fat_fs, "missing").err(),
+            Some(ENOENT)
+        );
+        assert_eq!(
+            file_inode.borrow().find_child_by_name(&fat_fuse.fat_fs, "x").err(),
+            Some(ENOTDIR)
+        );
+    }
+
+    #[test]
+    fn create_file() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let inode = fat_fuse.create_file(ROOT_INO, "notes.txt", 0o644).unwrap();
+
+        {
+            let inode = inode.borrow();
+
+            assert!(inode.is_file());
+            assert!(!inode.is_read_only());
+            assert_eq!(inode.size(), 0);
+            assert_eq!(inode.first_cluster(), 0);
+            assert!(fat_fuse.get_inode(inode.ino()).is_some());
+        }
+
+        assert!(fat_fuse.fat_fs.dir_contains(0, "notes.txt"));
+        assert_eq!(fat_fuse.create_file(ROOT_INO, "notes.txt", 0o644).err(), Some(EEXIST));
+
+        let read_only = fat_fuse.create_file(ROOT_INO, "RO", 0o444).unwrap();
+
+        assert!(read_only.borrow().is_read_only());
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn flush_clears_dirty() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        assert!(!fat_fuse.dirty);
+        fat_fuse.flush().unwrap();
+
+        fat_fuse.create_file(ROOT_INO, "notes.txt", 0o644).unwrap();
+
+        assert!(fat_fuse.dirty);
+        fat_fuse.flush().unwrap();
+        assert!(!fat_fuse.dirty);
+
+        assert!(fat_fuse.fat_fs.dir_contains(0, "notes.txt"));
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn truncate_file() {
+        use std::io::{Read as _, Write as _};
+
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let free_clusters = fat_fuse.fat_fs.free_clusters();
+
+        let inode = fat_fuse.create_file(ROOT_INO, "FILE", 0o644).unwrap();
+        let mut inode = inode.borrow_mut();
+
+        fat_fuse.truncate_file(&mut inode, 700).unwrap();
+        inode.write_back(&fat_fuse.fat_fs).unwrap();
+
+        let first_cluster = inode.first_cluster();
+
+        assert_ne!(first_cluster, 0);
+        assert_eq!(inode.size(), 700);
+        assert_eq!(fat_fuse.fat_fs.free_clusters(), free_clusters - 2);
+        assert_eq!(fat_fuse.ino_by_first_cluster.get(&first_cluster), Some(&inode.ino()));
+
+        let dir_entry = fat_fuse.fat_fs.root_dir_iter().find_by_name("FILE").unwrap();
+
+        assert_eq!(dir_entry.first_cluster(), first_cluster);
+        assert_eq!(dir_entry.file_size(), 700);
+
+        fat_fuse.fat_fs.file_writer(first_cluster).write_all(&[0xAA; 700]).unwrap();
+
+        // shrinking and growing again must not bring back the old data
+        fat_fuse.truncate_file(&mut inode, 100).unwrap();
+        fat_fuse.truncate_file(&mut inode, 1000).unwrap();
+        inode.write_back(&fat_fuse.fat_fs).unwrap();
+
+        let mut buf = [0; 1000];
+        fat_fuse.fat_fs.file_reader(inode.first_cluster()).read_exact(&mut buf).unwrap();
+
+        assert!(buf[..100].iter().all(|&b| b == 0xAA));
+        assert!(buf[100..].iter().all(|&b| b == 0));
+
+        fat_fuse.truncate_file(&mut inode, 0).unwrap();
+        inode.write_back(&fat_fuse.fat_fs).unwrap();
+
+        assert_eq!(inode.first_cluster(), 0);
+        assert_eq!(fat_fuse.fat_fs.free_clusters(), free_clusters);
+        assert!(!fat_fuse.ino_by_first_cluster.contains_key(&first_cluster));
+
+        drop(inode);
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn set_times() {
+        use std::time::{Duration, SystemTime};
+
+        use chrono::{Datelike as _, Local};
+        use fuser::TimeOrNow;
+
+        let mut fat_fuse = FatFuse::new_rw(fat12_image()).unwrap();
+
+        let inode = fat_fuse.create_file(ROOT_INO, "FILE", 0o644).unwrap();
+        let mut inode = inode.borrow_mut();
+
+        // years of the last access and write dates on disk
+        let years = |fat_fuse: &FatFuse| {
+            let dir_entry = fat_fuse.fat_fs.root_dir_iter().find_by_name("FILE").unwrap();
+
+            (dir_entry.last_access_date().year(), dir_entry.write_time().year())
+        };
+
+        // 2001-01-01 12:00:00 UTC
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(978_350_400);
+
+        let this_year = Local::now().year();
+
+        let specific = Some(TimeOrNow::SpecificTime(old));
+
+        fat_fuse.set_times(&mut inode, specific, specific).unwrap();
+        inode.write_back(&fat_fuse.fat_fs).unwrap();
+
+        assert_eq!(years(&fat_fuse), (2001, 2001));
+
+        // either time can be set to now on its own
+        fat_fuse.set_times(&mut inode, None, Some(TimeOrNow::Now)).unwrap();
+
+        assert!(!inode.is_dirty());
+        assert_eq!(years(&fat_fuse), (2001, this_year));
+
+        fat_fuse.set_times(&mut inode, specific, specific).unwrap();
+        inode.write_back(&fat_fuse.fat_fs).unwrap();
+
+        fat_fuse.set_times(&mut inode, Some(TimeOrNow::Now), specific).unwrap();
+        inode.write_back(&fat_fuse.fat_fs).unwrap();
+
+        assert_eq!(years(&fat_fuse), (this_year, 2001));
+
+        fat_fuse.set_times(&mut inode, specific, specific).unwrap();
+        inode.write_back(&fat_fuse.fat_fs).unwrap();
+
+        fat_fuse.set_times(&mut inode, Some(TimeOrNow::Now), Some(TimeOrNow::Now)).unwrap();
+
+        assert_eq!(years(&fat_fuse), (this_year, this_year));
+        assert_eq!(inode.file_attr().atime, inode.file_attr().mtime);
+
+        drop(inode);
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn list_dir() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_inode = fat_fuse.make_dir(ROOT_INO, "DIR").unwrap();
+        let file_inode = fat_fuse.create_file(ROOT_INO, "FILE", 0o644).unwrap();
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+
+        let entries = fat_fuse.list_dir(&root_inode, None, 0).unwrap();
+
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+
+        assert_eq!(names, [".", "..", "DIR", "FILE"]);
+
+        // same attributes getattr would return
+        for (_, inode) in &entries {
+            let inode = inode.borrow();
+            let attr = inode.file_attr();
+
+            let by_ino = fat_fuse.get_inode(attr.ino).unwrap().borrow().file_attr();
+
+            assert_eq!(attr.ino, inode.ino());
+            assert_eq!(attr.kind, by_ino.kind);
+            assert_eq!(attr.size, by_ino.size);
+            assert_eq!(attr.mtime, by_ino.mtime);
+        }
+
+        assert!(Rc::ptr_eq(&entries[2].1, &dir_inode));
+        assert!(Rc::ptr_eq(&entries[3].1, &file_inode));
+
+        let entries = fat_fuse.list_dir(&root_inode, None, 3).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert!(Rc::ptr_eq(&entries[0].1, &file_inode));
+
+        // . and .. of a subdir are on disk, but still resolve to the right inodes
+        let entries = fat_fuse.list_dir(&dir_inode, None, 0).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert!(Rc::ptr_eq(&entries[0].1, &dir_inode));
+        assert!(Rc::ptr_eq(&entries[1].1, &root_inode));
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn list_dir_cached() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        fat_fuse.create_file(ROOT_INO, "A", 0o644).unwrap();
+        fat_fuse.create_file(ROOT_INO, "B", 0o644).unwrap();
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+
+        let names = |entries: Vec<(String, InodeRef)>| -> Vec<String> {
+            entries.into_iter().map(|(name, _)| name).collect()
+        };
+
+        let fh = 7;
+
+        let entries = fat_fuse.list_dir(&root_inode, Some(fh), 0).unwrap();
+        assert_eq!(names(entries), [".", "..", "A", "B"]);
+        assert_eq!(fat_fuse.dir_entry_cache.get(fh).unwrap().len(), 2);
+
+        // a change on disk that doesn't go through FatFuse isn't seen by later batches
+        fat_fuse.fat_fs.create_file(0, "C", Attr::Archive).unwrap();
+
+        let entries = fat_fuse.list_dir(&root_inode, Some(fh), 3).unwrap();
+        assert_eq!(names(entries), ["B"]);
+
+        // but rewinding re-reads the directory
+        let entries = fat_fuse.list_dir(&root_inode, Some(fh), 0).unwrap();
+        assert_eq!(names(entries), [".", "..", "A", "B", "C"]);
+
+        // changes made through FatFuse drop the cache
+        fat_fuse.create_file(ROOT_INO, "D", 0o644).unwrap();
+        assert!(fat_fuse.dir_entry_cache.get(fh).is_none());
+
+        let entries = fat_fuse.list_dir(&root_inode, Some(fh), 5).unwrap();
+        assert_eq!(names(entries), ["D"]);
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn list_dir_paginated() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_inode = fat_fuse.make_dir(ROOT_INO, "DIR").unwrap();
+        let dir_ino = dir_inode.borrow().ino();
+
+        for name in ["A", "B", "C"] {
+            fat_fuse.create_file(ROOT_INO, name, 0o644).unwrap();
+            fat_fuse.create_file(dir_ino, name, 0o644).unwrap();
+        }
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+
+        for (fh, inode) in [(1, &root_inode), (2, &dir_inode)] {
+            let all: Vec<String> = fat_fuse
+                .list_dir(inode, Some(fh), 0)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+
+            // fetch one entry per batch, resuming at the offset readdir hands out for it
+            let mut names = Vec::new();
+            let mut offset = 0;
+
+            loop {
+                let entries = fat_fuse.list_dir(inode, Some(fh), offset).unwrap();
+
+                let Some((name, _)) = entries.into_iter().next() else {
+                    break;
+                };
+
+                names.push(name);
+                offset += 1;
+            }
+
+            assert_eq!(names, all);
+            assert_eq!(names[..2], [".", ".."]);
+            assert_eq!(names[names.len() - 3..], ["A", "B", "C"]);
+        }
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn lookup_cache_lru() {
+        let fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+
+        let mut cache = LookupCache::new(2);
+
+        cache.insert(ROOT_INO, "A", Rc::clone(&root_inode));
+        cache.insert(ROOT_INO, "B", Rc::clone(&root_inode));
+
+        // A is now more recently used than B, so B gets evicted
+        assert!(cache.get(ROOT_INO, "A").is_some());
+
+        cache.insert(ROOT_INO, "C", Rc::clone(&root_inode));
+
+        assert!(cache.get(ROOT_INO, "A").is_some());
+        assert!(cache.get(ROOT_INO, "B").is_none());
+        assert!(cache.get(ROOT_INO, "C").is_some());
+        assert_eq!(cache.entries.len(), cache.keys_by_use.len());
+
+        cache.set_capacity(1);
+
+        assert_eq!(cache.entries.len(), 1);
+        assert!(cache.get(ROOT_INO, "C").is_some());
+
+        cache.remove_ino(ROOT_INO);
+
+        assert!(cache.entries.is_empty());
+        assert!(cache.keys_by_use.is_empty());
+
+        cache.set_capacity(0);
+        cache.insert(ROOT_INO, "A", root_inode);
+
+        assert!(cache.get(ROOT_INO, "A").is_none());
+    }
+
+    #[test]
+    fn stable_generations() {
+        let entry = |first_cluster: u32, file_size: u32, attr: Attr| {
+            let mut dir_entry = DirEntry::create(*b"ENTRY      ", attr).unwrap();
+
+            dir_entry.set_first_cluster(first_cluster);
+            dir_entry.set_file_size(file_size);
+
+            dir_entry
+        };
+
+        for file_size in [0, 1, 512, 0xFFFF, 0x1_0000, 123_456_789] {
+            let generations: std::collections::HashSet<u32> = (2..4096)
+                .map(|cluster| Inode::generation_for(&entry(cluster, file_size, Attr::Archive)))
+                .collect();
+
+            assert_eq!(generations.len(), 4094);
+        }
+
+        assert_eq!(Inode::generation_for(&entry(42, 0, Attr::Directory)), 42);
+
+        // looking a file up again after its inode was dropped yields the same generation
+        let mut fat_fuse = FatFuse::new_rw(fat12_image()).unwrap();
+
+        let file_inode = fat_fuse.create_file(ROOT_INO, "FILE", 0o644).unwrap();
+        let file_ino = file_inode.borrow().ino();
+        let generation = file_inode.borrow().generation();
+
+        let dir_entry = fat_fuse.fat_fs.root_dir_iter().find_by_name("FILE").unwrap();
+
+        assert_eq!(generation, Inode::generation_for(&dir_entry));
+
+        file_inode.borrow_mut().inc_ref_count();
+        drop(file_inode);
+
+        fat_fuse.forget_inode(file_ino, 1);
+
+        assert!(fat_fuse.get_inode(file_ino).is_none());
+
+        let file_inode = fat_fuse.lookup_child(ROOT_INO, "FILE").unwrap();
+
+        assert_eq!(file_inode.borrow().generation(), generation);
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn lookup_child() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_inode = fat_fuse.make_dir(ROOT_INO, "DIR").unwrap();
+        let dir_ino = dir_inode.borrow().ino();
+
+        let file_inode = fat_fuse.create_file(dir_ino, "FILE", 0o644).unwrap();
+        let file_ino = file_inode.borrow().ino();
+
+        assert!(Rc::ptr_eq(&fat_fuse.lookup_child(ROOT_INO, "DIR").unwrap(), &dir_inode));
+        assert!(Rc::ptr_eq(&fat_fuse.lookup_child(dir_ino, "FILE").unwrap(), &file_inode));
+        assert_eq!(fat_fuse.lookup_child(dir_ino, "MISSING").unwrap_err(), ENOENT);
+
+        assert_eq!(fat_fuse.lookup_cache.entries.len(), 2);
+
+        // served from the cache
+        assert!(Rc::ptr_eq(&fat_fuse.lookup_child(dir_ino, "FILE").unwrap(), &file_inode));
+
+        // removing the file drops its inode, and with it the cached lookup
+        fat_fuse.remove_file(dir_ino, "FILE").unwrap();
+
+        assert_eq!(fat_fuse.lookup_cache.entries.len(), 1);
+        assert!(fat_fuse.get_inode(file_ino).is_none());
+        assert_eq!(fat_fuse.lookup_child(dir_ino, "FILE").unwrap_err(), ENOENT);
+
+        // renamed entries resolve under their new name only
+        fat_fuse.rename_entry(ROOT_INO, "DIR", ROOT_INO, "OTHER", false).unwrap();
+
+        assert_eq!(fat_fuse.lookup_child(ROOT_INO, "DIR").unwrap_err(), ENOENT);
+        assert!(Rc::ptr_eq(&fat_fuse.lookup_child(ROOT_INO, "OTHER").unwrap(), &dir_inode));
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn mount_mode() {
+        let fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        assert_eq!(fat_fuse.mode(), MountMode::ReadOnly);
+        assert!(fat_fuse.is_read_only());
+
+        let fat_fuse = FatFuse::new_rw(fat12_image()).unwrap();
+
+        assert_eq!(fat_fuse.mode(), MountMode::ReadWrite);
+        assert!(!fat_fuse.is_read_only());
+    }
+
+    #[test]
+    fn check_access() {
+        let mut fat_fuse = FatFuse::new_rw(fat12_image()).unwrap();
+
+        let dir_ino = fat_fuse.make_dir(ROOT_INO, "DIR").unwrap().borrow().ino();
+        let bin_ino = fat_fuse.create_file(ROOT_INO, "RUN", 0o755).unwrap().borrow().ino();
+        let txt_ino = fat_fuse.create_file(ROOT_INO, "README.TXT", 0o644).unwrap().borrow().ino();
+        let ro_ino = fat_fuse.create_file(ROOT_INO, "RO", 0o444).unwrap().borrow().ino();
+
+        let all = libc::R_OK | libc::W_OK | libc::X_OK;
+
+        for ino in [ROOT_INO, dir_ino, bin_ino] {
+            assert_eq!(fat_fuse.check_access(ino, libc::F_OK), Ok(()));
+            assert_eq!(fat_fuse.check_access(ino, all), Ok(()));
+        }
+
+        assert_eq!(fat_fuse.check_access(txt_ino, libc::R_OK | libc::W_OK), Ok(()));
+        assert_eq!(fat_fuse.check_access(txt_ino, libc::X_OK), Err(EACCES));
+
+        assert_eq!(fat_fuse.check_access(ro_ino, libc::R_OK | libc::X_OK), Ok(()));
+        assert_eq!(fat_fuse.check_access(ro_ino, libc::W_OK), Err(EACCES));
+
+        assert_eq!(fat_fuse.check_access(1000, libc::F_OK), Err(ENOENT));
+
+        fat_fuse.set_exec_all_files(true);
+
+        assert_eq!(fat_fuse.check_access(txt_ino, libc::X_OK), Ok(()));
+
+        let fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        assert_eq!(fat_fuse.check_access(ROOT_INO, libc::R_OK | libc::X_OK), Ok(()));
+        assert_eq!(fat_fuse.check_access(ROOT_INO, libc::W_OK), Err(EROFS));
+    }
+
+    #[test]
+    fn fat_ioctl() {
+        use super::{FAT_IOCTL_GET_ATTRIBUTES, FAT_IOCTL_SET_ATTRIBUTES};
+
+        let mut fat_fuse = FatFuse::new_rw(fat12_image()).unwrap();
+
+        let dir_ino = fat_fuse.make_dir(ROOT_INO, "DIR").unwrap().borrow().ino();
+        let file_ino = fat_fuse.create_file(ROOT_INO, "FILE", 0o644).unwrap().borrow().ino();
+
+        let get = |fat_fuse: &mut FatFuse, ino| {
+            let bytes = fat_fuse.fat_ioctl(ino, FAT_IOCTL_GET_ATTRIBUTES, &[]).unwrap();
+
+            Attr::from_bits(u32::from_ne_bytes(bytes.try_into().unwrap()) as u8).unwrap()
+        };
+
+        assert_eq!(get(&mut fat_fuse, file_ino), Attr::Archive);
+        assert_eq!(get(&mut fat_fuse, ROOT_INO), Attr::Directory);
+
+        let attr = Attr::ReadOnly | Attr::Hidden | Attr::System;
+        let data = (attr.bits() as u32).to_ne_bytes();
+
+        assert!(fat_fuse.fat_ioctl(file_ino, FAT_IOCTL_SET_ATTRIBUTES, &data).unwrap().is_empty());
+        assert_eq!(get(&mut fat_fuse, file_ino), attr);
+        assert!(fat_fuse.get_inode(file_ino).unwrap().borrow().is_read_only());
+
+        // the directory bit can't be removed
+        assert!(fat_fuse.fat_ioctl(dir_ino, FAT_IOCTL_SET_ATTRIBUTES, &data).is_ok());
+        assert_eq!(get(&mut fat_fuse, dir_ino), attr | Attr::Directory);
+
+        let set = |fat_fuse: &mut FatFuse, ino, data: &[u8]| {
+            fat_fuse.fat_ioctl(ino, FAT_IOCTL_SET_ATTRIBUTES, data).unwrap_err()
+        };
+
+        assert_eq!(set(&mut fat_fuse, ROOT_INO, &data), EPERM);
+        assert_eq!(set(&mut fat_fuse, file_ino, &[1]), EINVAL);
+        assert_eq!(set(&mut fat_fuse, file_ino, &0x100u32.to_ne_bytes()), EINVAL);
+        assert_eq!(set(&mut fat_fuse, 1000, &data), ENOENT);
+
+        assert_eq!(fat_fuse.fat_ioctl(file_ino, 0x5401, &[]).unwrap_err(), ENOTTY);
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn open_checks_kind() {
+        let mut fat_fuse = FatFuse::new_rw(fat12_image()).unwrap();
+
+        let dir_ino = fat_fuse.make_dir(ROOT_INO, "DIR").unwrap().borrow().ino();
+        let file_ino = fat_fuse.create_file(ROOT_INO, "FILE", 0o644).unwrap().borrow().ino();
+
+        assert_eq!(fat_fuse.get_file_inode(dir_ino).unwrap_err(), EISDIR);
+        assert_eq!(fat_fuse.get_file_inode(ROOT_INO).unwrap_err(), EISDIR);
+        assert_eq!(fat_fuse.get_file_inode(file_ino).unwrap().borrow().ino(), file_ino);
+
+        assert_eq!(fat_fuse.get_dir_inode(file_ino).unwrap_err(), ENOTDIR);
+        assert_eq!(fat_fuse.get_dir_inode(dir_ino).unwrap().borrow().ino(), dir_ino);
+        assert_eq!(fat_fuse.get_dir_inode(ROOT_INO).unwrap().borrow().ino(), ROOT_INO);
+
+        assert_eq!(fat_fuse.get_file_inode(1000).unwrap_err(), ENOENT);
+        assert_eq!(fat_fuse.get_dir_inode(1000).unwrap_err(), ENOENT);
+    }
+
+    #[test]
+    fn forget_root() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+
+        assert_eq!(root_inode.borrow().ref_count(), ROOT_REF_COUNT);
+
+        root_inode.borrow_mut().inc_ref_count();
+        fat_fuse.forget_inode(ROOT_INO, 1);
+
+        assert_eq!(root_inode.borrow().ref_count(), ROOT_REF_COUNT);
+
+        // forgetting more often than it was looked up neither drops nor underflows it
+        fat_fuse.forget_inode(ROOT_INO, u64::MAX);
+
+        assert_eq!(root_inode.borrow().ref_count(), ROOT_REF_COUNT);
+        assert!(Rc::ptr_eq(fat_fuse.get_inode(ROOT_INO).unwrap(), &root_inode));
+
+        // other inodes still get dropped once forgotten
+        let file_inode = fat_fuse.create_file(ROOT_INO, "FILE", 0o644).unwrap();
+        let file_ino = file_inode.borrow().ino();
+
+        file_inode.borrow_mut().inc_ref_count();
+        fat_fuse.forget_inode(file_ino, 1);
+
+        assert!(fat_fuse.get_inode(file_ino).is_none());
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn fat_xattrs() {
+        let mut fat_fuse = FatFuse::new_rw(fat12_image()).unwrap();
+
+        let inode = fat_fuse.create_file(ROOT_INO, "FILE", 0o644).unwrap();
+        let ino = inode.borrow().ino();
+
+        assert_eq!(
+            FatFuse::list_fat_xattrs(),
+            b"user.fat.readonly\0user.fat.hidden\0user.fat.system\0user.fat.archive\0"
+        );
+
+        assert_eq!(fat_fuse.get_fat_xattr(ino, "user.fat.archive"), Ok(&b"1"[..]));
+        assert_eq!(fat_fuse.get_fat_xattr(ino, "user.fat.hidden"), Ok(&b"0"[..]));
+        assert_eq!(fat_fuse.get_fat_xattr(ino, "user.other"), Err(ENODATA));
+
+        fat_fuse.set_fat_xattr(ino, "user.fat.hidden", b"1").unwrap();
+        fat_fuse.set_fat_xattr(ino, "user.fat.archive", b"0").unwrap();
+        fat_fuse.set_fat_xattr(ino, "user.fat.readonly", b"1").unwrap();
+
+        assert_eq!(fat_fuse.get_fat_xattr(ino, "user.fat.hidden"), Ok(&b"1"[..]));
+        assert_eq!(fat_fuse.get_fat_xattr(ino, "user.fat.archive"), Ok(&b"0"[..]));
+        assert!(inode.borrow().is_read_only());
+
+        // written to disk, hidden entries show up with a leading .
+        let dir_entry = fat_fuse.fat_fs.find_entry(".FILE").unwrap().unwrap();
+
+        assert_eq!(dir_entry.attr(), Attr::Hidden | Attr::ReadOnly);
+
+        assert_eq!(fat_fuse.set_fat_xattr(ino, "user.fat.hidden", b"yes"), Err(EINVAL));
+        assert_eq!(fat_fuse.set_fat_xattr(ino, "user.other", b"1"), Err(ENOTSUP));
+        assert_eq!(fat_fuse.set_fat_xattr(ROOT_INO, "user.fat.hidden", b"1"), Err(EPERM));
+        assert_eq!(fat_fuse.get_fat_xattr(ROOT_INO, "user.fat.hidden"), Ok(&b"0"[..]));
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn mknod() {
+        let mut fat_fuse = FatFuse::new_rw(fat12_image()).unwrap();
+
+        for mode in [libc::S_IFIFO, libc::S_IFCHR, libc::S_IFSOCK, libc::S_IFDIR] {
+            assert_eq!(fat_fuse.make_node(ROOT_INO, "NODE", mode | 0o644).unwrap_err(), EPERM);
+        }
+
+        assert!(!fat_fuse.fat_fs.dir_contains(0, "NODE"));
+
+        let inode = fat_fuse.make_node(ROOT_INO, "NODE", libc::S_IFREG | 0o644).unwrap();
+
+        assert!(inode.borrow().is_file());
+        assert!(fat_fuse.fat_fs.dir_contains(0, "NODE"));
+
+        let err = fat_fuse.make_node(ROOT_INO, "NODE", libc::S_IFREG | 0o644).unwrap_err();
+        assert_eq!(err, EEXIST);
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn setattr_mode_and_owner() {
+        let mut fat_fuse = FatFuse::new_rw(fat12_image()).unwrap();
+
+        let inode = fat_fuse.create_file(ROOT_INO, "FILE", 0o644).unwrap();
+        let mut inode = inode.borrow_mut();
+
+        // dropping all write bits sets the read-only attribute on disk
+        fat_fuse.chmod_inode(&mut inode, 0o444).unwrap();
+
+        assert!(inode.is_read_only());
+        assert_eq!(inode.file_attr().perm, 0o555);
+        assert!(fat_fuse.fat_fs.find_entry("FILE").unwrap().unwrap().is_readonly());
+
+        // any write bit clears it again
+        fat_fuse.chmod_inode(&mut inode, 0o600).unwrap();
+
+        assert!(!inode.is_read_only());
+        assert!(!fat_fuse.fat_fs.find_entry("FILE").unwrap().unwrap().is_readonly());
+
+        // ownership only lives in memory
+        fat_fuse.chown_inode(&mut inode, Some(1234), None).unwrap();
+
+        assert_eq!(inode.file_attr().uid, 1234);
+        assert_eq!(inode.file_attr().gid, fat_fuse.gid);
+
+        fat_fuse.set_strict_chown(true);
+
+        assert_eq!(fat_fuse.chown_inode(&mut inode, None, Some(1234)), Err(EPERM));
+        assert_eq!(fat_fuse.chown_inode(&mut inode, None, None), Ok(()));
+        assert_eq!(inode.file_attr().gid, fat_fuse.gid);
+
+        drop(inode);
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn unlink() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let free_clusters = fat_fuse.fat_fs.free_clusters();
+
+        // a file with some data, so there is a cluster to free
+        let cluster = fat_fuse.fat_fs.alloc_cluster(None).unwrap();
+
+        let mut dir_entry = fat_fuse.fat_fs.create_file(0, "FILE", Attr::Archive).unwrap();
+        dir_entry.set_first_cluster(cluster);
+        dir_entry.write_back(&fat_fuse.fat_fs).unwrap();
+
+        let root_inode = fat_fuse.get_inode(ROOT_INO).cloned().unwrap();
+        let inode = fat_fuse.get_or_make_inode(&dir_entry, &root_inode.borrow());
+        let ino = inode.borrow().ino();
+
+        fat_fuse.make_dir(ROOT_INO, "DIR").unwrap();
+
+        assert_eq!(fat_fuse.remove_file(ROOT_INO, "DIR"), Err(EISDIR));
+        assert_eq!(fat_fuse.remove_file(ROOT_INO, "FILE"), Ok(()));
+
+        assert!(fat_fuse.get_inode(ino).is_none());
+        assert!(!fat_fuse.fat_fs.dir_contains(0, "FILE"));
+        assert_eq!(fat_fuse.fat_fs.free_clusters(), free_clusters - 1);
+
+        assert_eq!(fat_fuse.remove_file(ROOT_INO, "FILE"), Err(ENOENT));
+
+        fat_fuse.assert_consistent();
+    }
+
+    #[test]
+    fn mkdir() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let free_clusters = fat_fuse.fat_fs.free_clusters();
+
+        let dir_inode = fat_fuse.make_dir(ROOT_INO, "DIR").unwrap();
+        let dir_ino = dir_inode.borrow().ino();
+
+        assert!(dir_inode.borrow().is_dir());
+        assert_eq!(fat_fuse.fat_fs.free_clusters(), free_clusters - 1);
+
+        let subdir_inode = fat_fuse.make_dir(dir_ino, "SUBDIR").unwrap();
+
+        assert_eq!(subdir_inode.borrow().path().as_ref(), "/DIR/SUBDIR");
+        assert_eq!(fat_fuse.make_dir(dir_ino, "SUBDIR").err(), Some(EEXIST));
+
+        fat_fuse.assert_consistent();
+
+        assert_eq!(fat_fuse.remove_dir(ROOT_INO, "DIR"), Err(ENOTEMPTY));
+        assert_eq!(fat_fuse.remove_dir(dir_ino, "SUBDIR"), Ok(()));
+        assert_eq!(fat_fuse.remove_dir(ROOT_INO, "DIR"), Ok(()));
+
+        assert_eq!(fat_fuse.fat_fs.free_clusters(), free_clusters);
+    }
+
+    #[test]
+    fn rename_updates_paths() {
+        let mut fat_fuse = FatFuse::new(fat12_image()).unwrap();
+
+        let dir_inode = fat_fuse.make_dir(ROOT_INO, "DIR").unwrap();
+        let dir_ino = dir_inode.borrow().ino();
+
+        let other_inode = fat_fuse.make_dir(ROOT_INO, "OTHER").unwrap();
+        let other_ino = other_inode.borrow().ino();
+
+        let file_inode = fat_fuse.create_file(dir_ino, "FILE", 0o644).unwrap();
+
+        assert_eq!(fat_fuse.rename_entry(ROOT_INO, "DIR", ROOT_INO, "OTHER", true), Err(EEXIST));
+
+        assert_eq!(fat_fuse.rename_entry(ROOT_INO, "DIR", other_ino, "MOVED", true), Ok(()));
+
+        assert_eq!(dir_inode.borrow().path().as_ref(), "/OTHER/MOVED");
+        assert_eq!(file_inode.borrow().path().as_ref(), "/OTHER/MOVED/FILE");
+        assert_eq!(
+            dir_inode.borrow().parent().map(|parent| parent.borrow().ino()),
+            Some(other_ino)
+        );
+
+        assert!(fat_fuse.get_inode_by_path("/DIR").is_none());
+        assert!(fat_fuse.get_inode_by_path("/OTHER/MOVED/FILE").is_some());
+
+        fat_fuse.assert_consistent();
+
+        // replacing a file drops the replaced inode
+        let replaced = fat_fuse.create_file(ROOT_INO, "TARGET", 0o644).unwrap();
+        let replaced_ino = replaced.borrow().ino();
+
+        assert_eq!(fat_fuse.rename_entry(dir_ino, "FILE", ROOT_INO, "TARGET", false), Ok(()));
+
+        assert!(fat_fuse.get_inode(replaced_ino).is_none());
+        assert_eq!(file_inode.borrow().path().as_ref(), "/TARGET");
+
+        fat_fuse.assert_consistent();
+
+        assert_eq!(fat_fuse.rename_entry(ROOT_INO, "OTHER", dir_ino, "X", false), Err(EIO));
+    }
+
+    #[test]
+    fn read_only_backing_store() {
+        let mut image = fat12_image().to_vec();
+
+        // an empty file in the root dir, which starts right after the reserved sector and the FAT
+        image[1024..1035].copy_from_slice(b"NOTES   TXT");
+        image[1024 + 11] = 0x20;
+
+        // creation, access and write date 1980-01-01
+        for offset in [16, 18, 24] {
+            image[1024 + offset] = 0x21;
+        }
+
+        let mut fat_fuse = FatFuse::new(ReadOnlyImage(image)).unwrap();
+
+        let inode = fat_fuse.lookup_child(ROOT_INO, "notes.txt").unwrap();
+        let ino = inode.borrow().ino();
+
+        {
+            let mut inode = inode.borrow_mut();
+
+            // getattr and read must not try to write the access time back
+            fat_fuse.record_access(&mut inode, true).unwrap();
+            fat_fuse.record_access(&mut inode, false).unwrap();
+
+            assert!(!inode.is_dirty());
+        }
+
+        let ref_count = inode.borrow().ref_count();
+        drop(inode);
+
+        // dropping the inode asserts that it is not dirty
+        fat_fuse.forget_inode(ino, ref_count);
+        assert!(fat_fuse.get_inode(ino).is_none());
+
+        fat_fuse.flush().unwrap();
+    }
+
+    #[test]
+    fn seek() {
+        assert_eq!(seek_offset(100, 10, libc::SEEK_SET), Ok(10));
+        assert_eq!(seek_offset(100, -10, libc::SEEK_END), Ok(90));
+        assert_eq!(seek_offset(100, -200, libc::SEEK_END), Err(EINVAL));
+        assert_eq!(seek_offset(100, 0, libc::SEEK_CUR), Err(EINVAL));
+
+        assert_eq!(seek_offset(100, 10, libc::SEEK_DATA), Ok(10));
+        assert_eq!(seek_offset(100, 100, libc::SEEK_DATA), Err(ENXIO));
+
+        assert_eq!(seek_offset(100, 10, libc::SEEK_HOLE), Ok(100));
+        assert_eq!(seek_offset(100, 100, libc::SEEK_HOLE), Err(ENXIO));
+
+        // an empty file has neither data nor holes
+        assert_eq!(seek_offset(0, 0, libc::SEEK_DATA), Err(ENXIO));
     }
 }
@@ -1,5 +1,6 @@
 use std::fs::OpenOptions;
 use std::sync::mpsc::channel;
+use std::time::Duration;
 
 use fat_fuse::FatFuse;
 use fuser::MountOption;
@@ -10,22 +11,76 @@ fn main() -> anyhow::Result<()> {
     let mut args = std::env::args();
 
     let _prog_name = args.next().unwrap();
-    let path = args.next().ok_or(anyhow::anyhow!("missing fs path"))?;
-    let mountpoint = args.next().ok_or(anyhow::anyhow!("missing mount point"))?;
 
-    // let file = File::open(path)?;
-    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut positional = Vec::new();
+    let mut idle_timeout = None;
+    let mut read_write = false;
+    let mut exec_all_files = false;
 
-    let fat_fuse = FatFuse::new(file)?;
+    while let Some(arg) = args.next() {
+        if arg == "--idle-timeout" {
+            let secs: u64 = args
+                .next()
+                .ok_or(anyhow::anyhow!("missing value for --idle-timeout"))?
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid value for --idle-timeout: {e}"))?;
 
-    let options = vec![
-        // MountOption::RO,
-        MountOption::FSName("fat-fuse".to_owned()),
-        MountOption::AutoUnmount,
-    ];
+            idle_timeout = Some(Duration::from_secs(secs));
+        } else if arg == "--rw" {
+            read_write = true;
+        } else if arg == "--exec" {
+            exec_all_files = true;
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    let mut positional = positional.into_iter();
+
+    let path = positional.next().ok_or(anyhow::anyhow!("missing fs path"))?;
+    let mountpoint = positional.next().ok_or(anyhow::anyhow!("missing mount point"))?;
+
+    let file = OpenOptions::new().read(true).write(read_write).open(path)?;
+
+    let mut fat_fuse = if read_write {
+        FatFuse::new_rw(file)?
+    } else {
+        FatFuse::new(file)?
+    };
+
+    fat_fuse.set_exec_all_files(exec_all_files);
+
+    let last_operation = fat_fuse.last_operation();
+
+    let mut options = vec![MountOption::FSName("fat-fuse".to_owned()), MountOption::AutoUnmount];
+
+    if !read_write {
+        options.push(MountOption::RO);
+    }
 
     let (tx, rx) = channel();
 
+    if let Some(idle_timeout) = idle_timeout {
+        let tx = tx.clone();
+
+        let check_interval = idle_timeout.min(Duration::from_secs(10));
+
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(check_interval);
+
+                if last_operation.lock().unwrap().elapsed() > idle_timeout {
+                    log::info!("idle for more than {}s, unmounting", idle_timeout.as_secs());
+
+                    // main may already be gone if Ctrl-C was hit, nothing to do in that case
+                    let _ = tx.send(());
+
+                    return;
+                }
+            }
+        });
+    }
+
     ctrlc::set_handler(move || {
         tx.send(()).unwrap();
     })